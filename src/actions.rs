@@ -1,16 +1,53 @@
+pub(crate) mod add_recovery_key;
 pub(crate) mod append_handle;
+pub(crate) mod batch_append_handle;
 pub(crate) mod create_account;
+pub(crate) mod create_invite;
+pub(crate) mod deactivate_account;
 pub mod domain;
+pub(crate) mod edit_verification_methods;
+pub(crate) mod export_identity;
 pub mod factory;
+pub(crate) mod generated_key;
+pub(crate) mod history;
+pub(crate) mod identity;
 pub(crate) mod inputs;
+pub mod interrupt;
+pub(crate) mod list_repos;
 pub(crate) mod migrate;
+pub mod options;
 pub(crate) mod prepare;
+pub mod registry;
+pub(crate) mod remove_handle;
+pub(crate) mod rotate_key;
+pub(crate) mod self_test;
+pub(crate) mod set_handles;
+pub(crate) mod tombstone;
+pub(crate) mod verify;
+pub(crate) mod verify_bundle;
 
 pub use domain::{TandemAction, Theme};
 pub use factory::{get_action, SUPPORTED_ACTIONS};
+pub use options::{OperationSource, Options};
+pub use registry::{ActionConstructor, ActionRegistry};
 
+pub(crate) use add_recovery_key::ActionAddRecoveryKey;
 pub(crate) use append_handle::ActionAppendHandle;
+pub(crate) use batch_append_handle::ActionBatchAppendHandle;
 pub(crate) use create_account::ActionCreateAccount;
-pub(crate) use inputs::{get_did_plc_input, get_handle_input, get_jwk_input};
+pub(crate) use create_invite::ActionCreateInvite;
+pub(crate) use deactivate_account::ActionDeactivateAccount;
+pub(crate) use edit_verification_methods::ActionEditVerificationMethods;
+pub(crate) use export_identity::ActionExportIdentity;
+pub(crate) use history::ActionHistory;
+pub(crate) use identity::ActionIdentity;
+pub(crate) use inputs::{get_did_plc_input, get_handle_input, get_jwk_input, get_key_format_input};
+pub(crate) use list_repos::ActionListRepos;
 pub(crate) use migrate::ActionMigrate;
 pub(crate) use prepare::ActionPrepare;
+pub(crate) use remove_handle::ActionRemoveHandle;
+pub(crate) use rotate_key::ActionRotateKey;
+pub(crate) use self_test::ActionSelfTest;
+pub(crate) use set_handles::ActionSetHandles;
+pub(crate) use tombstone::ActionTombstone;
+pub(crate) use verify_bundle::ActionVerifyBundle;
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::Select;
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        generated_key::output_generated_key,
+        get_did_plc_input, get_key_format_input,
+        inputs::{get_other_jwk_input, get_signer_input},
+        interrupt::clear_reminders,
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    crypto::{check_generated_key, jwk_to_did_key},
+    flows::{build_add_recovery_key, check_signer_is_rotation_key, last_operation, sign, submit},
+    multikey::Curve,
+    plc::{check_rotation_key_capacity, curl_command},
+    state::DidLock,
+};
+
+pub(crate) struct ActionAddRecoveryKey<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionAddRecoveryKey<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+
+    /// Returns the recovery key as a `did:key`, either derived from a
+    /// provided JWK or freshly generated.
+    fn recovery_key(&self) -> Result<String> {
+        let key_types = &["provided jwk", "generate p256", "generate k256"];
+        let key_type = Select::with_theme(self.theme.colorful_theme)
+            .with_prompt("Select recovery key type")
+            .default(0)
+            .items(&key_types[..])
+            .interact()?;
+
+        if key_type == 0 {
+            let jwk = get_other_jwk_input(self.theme.colorful_theme, "Recovery JWK")?;
+            let did_key = jwk_to_did_key(&jwk)?;
+            self.theme
+                .println(self.theme.green.apply_to("✔ Derived DID key"));
+            self.theme
+                .event("derived_did_key", json!({ "did_key": did_key }));
+            self.theme.println(self.theme.white_dim.apply_to(&did_key));
+            return Ok(format!("did:key:{}", did_key));
+        }
+
+        let key_format = get_key_format_input(self.theme.colorful_theme)?;
+        let (curve, (secret_jwk, encoded_public_key)) = if key_type == 1 {
+            (Curve::P256, crate::crypto::p256::gen_key(key_format)?)
+        } else {
+            (Curve::K256, crate::crypto::k256::gen_key(key_format)?)
+        };
+        check_generated_key(curve, &secret_jwk, key_format, &encoded_public_key)
+            .context("generated key failed its consistency check; nothing was changed")?;
+
+        output_generated_key(
+            self.theme,
+            &self.options,
+            &secret_jwk,
+            &encoded_public_key,
+            "Important! Securely store the following private key offline.",
+        )?;
+        Ok(format!("did:key:{}", encoded_public_key))
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionAddRecoveryKey<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Add Recovery Key' action adds a key as the first, highest precedence rotation key in your DID-PLC document. This action requires your tandem private key to authorize the change."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+
+        check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+        // Fail on a full document before a key is generated and shown.
+        check_rotation_key_capacity(&last.operation, 1)?;
+
+        let recovery_key = self.recovery_key()?;
+        warn_verification_method_drift(self.theme, &last.operation);
+        let operation = build_add_recovery_key(&last, &recovery_key)?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        clear_reminders();
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+        self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                "{} is now rotation key 0. PLC has no separate recovery key type: rotation keys earlier in the list take precedence, so for 72 hours after any operation signed by a later key, this key can sign a competing operation that replaces it. Keep it offline and use it only for recovery.",
+                recovery_key
+            ))
+        );
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
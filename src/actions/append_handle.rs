@@ -1,103 +1,230 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use json_patch::{patch, Patch};
-use serde_json::{from_value, json};
+use dialoguer::Confirm;
+use serde_json::json;
+use std::time::Instant;
 
 use crate::{
-    actions::{get_did_plc_input, get_handle_input, get_jwk_input, TandemAction, Theme},
-    crypto::sign_operation,
-    plc::{did_plc_last_operation, submit_operation},
+    actions::{
+        get_did_plc_input, get_handle_input,
+        inputs::{get_signer_input, signer_input_prompts},
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{build_append_handle, check_signer_is_rotation_key, last_operation, sign, submit},
+    plc::curl_command,
+    rate_limit::RateLimiter,
+    signer::Signer,
+    state::DidLock,
 };
 
 pub(crate) struct ActionAppendHandle<'a> {
     theme: &'a Theme<'a>,
     http_client: reqwest::Client,
     plc: String,
+    options: Options,
+    rate_limiter: RateLimiter,
 }
 
 impl<'a> ActionAppendHandle<'a> {
-    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
         Self {
             theme,
             http_client: http_client.clone(),
             plc: plc.to_string(),
+            options: options.clone(),
+            rate_limiter: RateLimiter::unlimited(),
+        }
+    }
+
+    /// Routes the directory requests `append_handle` makes through `rate_limiter`.
+    pub(crate) fn with_rate_limiter(self, rate_limiter: RateLimiter) -> Self {
+        Self {
+            rate_limiter,
+            ..self
         }
     }
 }
 
 #[async_trait]
 impl TandemAction for ActionAppendHandle<'_> {
+    fn needs_terminal(&self) -> bool {
+        self.options.did.is_none()
+            || self.options.new_handle.is_none()
+            || signer_input_prompts(&self.options)
+    }
+
     async fn run(&self) -> Result<()> {
-        println!(
-        "{}",
+        self.theme.println(
             self.theme
                 .white_dim
-                .apply_to("The 'Append Handle' action appends an additional handle to the 'alsoKnownAs' field in your DID-PLC document. This action requires your tandem private key.")
+                .apply_to("The 'Append Handle' action appends an additional handle to the 'alsoKnownAs' field in your DID-PLC document. This action requires your tandem private key."),
         );
 
-        let did = get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?;
-        let jwk = get_jwk_input(self.theme.colorful_theme)?;
-        let new_handle = get_handle_input(
-            self.theme.colorful_theme,
-            "What is the new handle being added?",
-        )?;
-
-        let did_key = crate::crypto::jwk_to_did_key(&jwk)?;
-        println!("{}", self.theme.green.apply_to("✔ Derived DID key"));
-        println!("{}", self.theme.white_dim.apply_to(&did_key));
-
-        let (last_commit, last_operation) =
-            did_plc_last_operation(&self.http_client, &self.plc, &did).await?;
-        println!(
-            "{}",
-            self.theme.green.apply_to("✔ Retreived last operation")
-        );
-        println!("{}", self.theme.white_dim.apply_to(&last_commit));
-        println!(
-            "{}",
-            self.theme.white_dim.apply_to(
-                serde_json::to_string_pretty(&last_operation)
-                    .context("failed to serialize DID document")?
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let new_handle = match &self.options.new_handle {
+            Some(handle) => handle.clone(),
+            None => get_handle_input(
+                self.theme.colorful_theme,
+                "What is the new handle being added?",
+            )?,
+        };
+
+        if let Some(signed_operation) = self
+            .append_handle(&did, signer.as_ref(), &new_handle)
+            .await?
+        {
+            offer_head_verification(
+                self.theme,
+                &self.http_client,
+                &self.plc,
+                &did,
+                &signed_operation,
             )
-        );
+            .await?;
+            self.write_receipt(&did, &signed_operation)?;
+        }
 
-        let operation_patch: Patch = from_value(json!([
-            { "op": "add", "path": "/alsoKnownAs/-", "value": format!("at://{}", new_handle) },
-            { "op": "remove", "path": "/sig" },
-            { "op": "replace", "path": "/prev", "value": last_commit },
-        ]))
-        .context("failed to create patch to append handle")?;
+        Ok(())
+    }
+}
 
-        let mut operation = last_operation.clone();
+impl ActionAppendHandle<'_> {
+    pub(crate) fn write_receipt(&self, did: &str, operation: &serde_json::Value) -> Result<()> {
+        write_receipt(self.theme, &self.options, &self.plc, did, operation)
+    }
 
-        patch(&mut operation, &operation_patch)?;
-        println!(
-            "{}",
-            self.theme
-                .green
-                .apply_to("✔ Prepared operation for signing")
+    /// Appends `new_handle` to the DID's `alsoKnownAs`, returning the submitted
+    /// operation or `None` if the handle was already present or `--dry-run`
+    /// stopped before submission.
+    pub(crate) async fn append_handle(
+        &self,
+        did: &str,
+        signer: &dyn Signer,
+        new_handle: &str,
+    ) -> Result<Option<serde_json::Value>> {
+        let _lock = DidLock::acquire(did, self.options.force)?;
+
+        let did_key = signer.did_key();
+        self.theme
+            .println(self.theme.green.apply_to("✔ Derived DID key"));
+        self.theme
+            .event("derived_did_key", json!({ "did_key": did_key }));
+        self.theme.println(self.theme.white_dim.apply_to(&did_key));
+
+        self.rate_limiter.wait().await;
+        let started = Instant::now();
+        let last = last_operation(
+            &self.http_client,
+            &self.plc,
+            did,
+            self.options.prev.as_deref(),
+        )
+        .await?;
+        if !last.is_head {
+            self.theme.println(self.theme.red_bold.apply_to(format!(
+                    "Warning! {} is not the current head of the audit log. Building on it will fork the chain.",
+                    last.cid
+                ))
+            );
+            if !Confirm::with_theme(self.theme.colorful_theme)
+                .with_prompt("Do you want to fork the chain?")
+                .default(false)
+                .show_default(true)
+                .wait_for_newline(true)
+                .interact()?
+            {
+                return Err(anyhow!("aborted fork of the operation chain"));
+            }
+        }
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
         );
-        println!(
-            "{}",
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+        self.theme.println(
             self.theme.white_dim.apply_to(
-                serde_json::to_string_pretty(&operation)
-                    .context("failed to serialize DID document")?
-            )
+                serde_json::to_string_pretty(&last.operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        check_signer_is_rotation_key(signer, &last.operation)?;
+        warn_verification_method_drift(self.theme, &last.operation);
+        let Some(operation) = build_append_handle(&last, new_handle)? else {
+            self.theme.println(self.theme.green.apply_to(format!(
+                "✔ Handle {} already present, nothing to do",
+                new_handle
+            )));
+            return Ok(None);
+        };
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
         );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
 
-        let signed_operation = sign_operation(&jwk, &operation)?;
-        println!("{}", self.theme.green.apply_to("✔ Signed operation"));
-        println!(
-            "{}",
+        let started = Instant::now();
+        let signed_operation = sign(signer, &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
             self.theme.white_dim.apply_to(
                 serde_json::to_string_pretty(&signed_operation)
-                    .context("failed to serialize DID document")?
-            )
+                    .context("failed to serialize DID document")?,
+            ),
         );
 
-        submit_operation(&self.http_client, &self.plc, &did, &signed_operation).await?;
-        println!("{}", self.theme.green.apply_to("✔ Operation submitted"));
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(self.theme, &self.options, &self.plc, did, &signed_operation)? {
+            return Ok(None);
+        }
+
+        self.rate_limiter.wait().await;
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
 
-        Ok(())
+        Ok(Some(signed_operation))
     }
 }
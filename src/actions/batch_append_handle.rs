@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use dialoguer::Input;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    actions::{
+        inputs::{is_valid_handle, read_jwk_file},
+        ActionAppendHandle, Options, TandemAction, Theme,
+    },
+    signer::JwkSigner,
+};
+
+pub(crate) struct ActionBatchAppendHandle<'a> {
+    theme: &'a Theme<'a>,
+    append_handle: ActionAppendHandle<'a>,
+}
+
+impl<'a> ActionBatchAppendHandle<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        // A pinned prev CID belongs to a single DID, so it never applies to a batch.
+        let options = Options {
+            prev: None,
+            ..options.clone()
+        };
+        Self {
+            theme,
+            append_handle: ActionAppendHandle::new(theme, http_client, plc, &options)
+                .with_rate_limiter(options.batch_rate_limiter()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct BatchRecord {
+    did: String,
+    jwk_path: PathBuf,
+    new_handle: String,
+}
+
+#[async_trait]
+impl TandemAction for ActionBatchAppendHandle<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Batch Append Handle' action appends a handle to each DID listed in a JSON or CSV file of did, jwk_path, and new_handle records."),
+        );
+
+        let batch_path = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("Path to the batch file")
+            .interact()?;
+        let batch_path = PathBuf::from(batch_path.trim());
+
+        let contents = std::fs::read_to_string(&batch_path)
+            .with_context(|| format!("failed to read {}", batch_path.display()))?;
+        let is_json = batch_path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let records = parse_batch(&contents, is_json)?;
+        let base_dir = batch_path.parent().unwrap_or(Path::new("."));
+
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to(format!("✔ Loaded {} records", records.len())),
+        );
+        self.theme
+            .event("loaded_records", json!({ "count": records.len() }));
+
+        let mut failures: Vec<(String, anyhow::Error)> = Vec::new();
+        for (index, record) in records.iter().enumerate() {
+            self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                "[{}/{}] {} → {}",
+                index + 1,
+                records.len(),
+                record.did,
+                record.new_handle
+            )));
+
+            match self.append_record(record, base_dir).await {
+                Ok(_) => {
+                    self.theme.println(
+                        self.theme
+                            .green
+                            .apply_to(format!("✔ Completed {}", record.did)),
+                    );
+                    self.theme
+                        .event("completed_record", json!({ "did": record.did }));
+                }
+                Err(err) => {
+                    self.theme.println(
+                        self.theme
+                            .red_bold
+                            .apply_to(format!("✘ Failed {}: {:#}", record.did, err)),
+                    );
+                    self.theme.event(
+                        "failed_record",
+                        json!({ "did": record.did, "error": format!("{:#}", err) }),
+                    );
+                    failures.push((record.did.clone(), err));
+                }
+            }
+        }
+
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ {} succeeded, {} failed",
+            records.len() - failures.len(),
+            failures.len()
+        )));
+        self.theme.event(
+            "batch_completed",
+            json!({
+                "succeeded": records.len() - failures.len(),
+                "failed": failures
+                    .iter()
+                    .map(|(did, err)| json!({ "did": did, "error": format!("{:#}", err) }))
+                    .collect::<Vec<_>>(),
+            }),
+        );
+        for (did, err) in &failures {
+            self.theme
+                .println(self.theme.red_bold.apply_to(format!("{}: {:#}", did, err)));
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} of {} records failed",
+                failures.len(),
+                records.len()
+            ))
+        }
+    }
+}
+
+impl ActionBatchAppendHandle<'_> {
+    async fn append_record(&self, record: &BatchRecord, base_dir: &Path) -> Result<()> {
+        if !record.did.starts_with("did:plc:") {
+            return Err(anyhow!("invalid DID-PLC {}", record.did));
+        }
+        let new_handle = is_valid_handle(&record.new_handle)
+            .ok_or_else(|| anyhow!("invalid handle {}", record.new_handle))?;
+
+        let jwk_path = base_dir.join(&record.jwk_path);
+        let signer = JwkSigner::new(read_jwk_file(&jwk_path)?)?;
+
+        if let Some(signed_operation) = self
+            .append_handle
+            .append_handle(&record.did, &signer, &new_handle)
+            .await?
+        {
+            self.append_handle
+                .write_receipt(&record.did, &signed_operation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses batch records from either a JSON array or CSV lines of
+/// `did,jwk_path,new_handle`, where a matching header line is optional.
+fn parse_batch(contents: &str, is_json: bool) -> Result<Vec<BatchRecord>> {
+    if is_json {
+        return serde_json::from_str(contents).context("failed to parse batch file as JSON");
+    }
+
+    let mut records = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split(',').map(str::trim).collect::<Vec<&str>>();
+        if fields == ["did", "jwk_path", "new_handle"] {
+            continue;
+        }
+        let [did, jwk_path, new_handle] = fields[..] else {
+            return Err(anyhow!(
+                "line {}: expected did,jwk_path,new_handle",
+                number + 1
+            ));
+        };
+        records.push(BatchRecord {
+            did: did.to_string(),
+            jwk_path: PathBuf::from(jwk_path),
+            new_handle: new_handle.to_string(),
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_batch_files() {
+        let expected = vec![
+            BatchRecord {
+                did: "did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+                jwk_path: PathBuf::from("keys/alice.jwk"),
+                new_handle: "alice.example.com".to_string(),
+            },
+            BatchRecord {
+                did: "did:plc:ewvi7nxzyoun6zhxrhs64oiz".to_string(),
+                jwk_path: PathBuf::from("/keys/bob.jwk"),
+                new_handle: "bob.example.com".to_string(),
+            },
+        ];
+
+        let csv = "did,jwk_path,new_handle\n\
+            did:plc:cbkjy5n7bk3ax2wplmtjofq2, keys/alice.jwk, alice.example.com\n\
+            \n\
+            # comment\n\
+            did:plc:ewvi7nxzyoun6zhxrhs64oiz,/keys/bob.jwk,bob.example.com\n";
+        assert_eq!(parse_batch(csv, false).unwrap(), expected);
+
+        let json = r#"[
+            {"did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2", "jwk_path": "keys/alice.jwk", "new_handle": "alice.example.com"},
+            {"did": "did:plc:ewvi7nxzyoun6zhxrhs64oiz", "jwk_path": "/keys/bob.jwk", "new_handle": "bob.example.com"}
+        ]"#;
+        assert_eq!(parse_batch(json, true).unwrap(), expected);
+
+        assert!(parse_batch("did:plc:cbkjy5n7bk3ax2wplmtjofq2,alice.example.com", false).is_err());
+    }
+}
@@ -1,66 +1,91 @@
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use dialoguer::{Confirm, Input, Password, Select};
+use dialoguer::{console::user_attended, Confirm, Input, Password, Select};
+use serde_json::json;
+use std::time::Instant;
 
 use crate::{
-    actions::{get_handle_input, get_jwk_input, TandemAction, Theme},
-    xrpc::{create_account, describe_server},
+    actions::{
+        generated_key::output_generated_key,
+        get_jwk_input, get_key_format_input,
+        inputs::{is_valid_email, is_valid_handle},
+        interrupt::clear_reminders,
+        Options, TandemAction, Theme,
+    },
+    crypto::check_generated_key,
+    multikey::{did_key_to_verifying_key, Curve},
+    resolve::{resolve_handle_dns, resolve_handle_http},
+    xrpc::{create_account, describe_server, pds_base_url, PdsClient},
 };
 
 pub(crate) struct ActionCreateAccount<'a> {
     theme: &'a Theme<'a>,
     http_client: reqwest::Client,
+    options: Options,
 }
 
 impl<'a> ActionCreateAccount<'a> {
-    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client) -> Self {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        options: &Options,
+    ) -> Self {
         Self {
             theme,
             http_client: http_client.clone(),
+            options: options.clone(),
         }
     }
 }
 
 #[async_trait]
 impl TandemAction for ActionCreateAccount<'_> {
+    fn uses_plc_directory(&self) -> bool {
+        false
+    }
+
     async fn run(&self) -> Result<()> {
-        println!(
-            "{}",
+        self.theme.println(
             self.theme
                 .white_dim
-                .apply_to("The 'Create Account' creates an account on a PDS.")
+                .apply_to("The 'Create Account' creates an account on a PDS."),
         );
 
-        let pds_hostname = get_handle_input(
-            self.theme.colorful_theme,
-            "What is the hostname of the PDS?",
-        )?;
+        let pds = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("What is the hostname or URL of the PDS?")
+            .interact()?;
+        let pds = pds_base_url(&pds)?;
 
+        let started = Instant::now();
         let (_pds_did, invite_required, available_domains) =
-            describe_server(&self.http_client, &pds_hostname)
+            describe_server(&self.http_client, &pds)
                 .await
                 .context("Unable to describe server.")?;
-        println!(
-            "{}",
-            self.theme.green.apply_to("✔ Retrieved PDS information")
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retrieved PDS information{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_pds_information",
+            json!({
+                "pds": pds,
+                "invite_required": invite_required,
+                "available_domains": available_domains,
+            }),
         );
 
         if available_domains.is_empty() {
-            println!(
-                "{}",
-                self.theme.red_bold.apply_to(
-                    "This PDS does not have any available domains. It is probably misconfigured."
-                )
-            );
+            self.theme.println(self.theme.red_bold.apply_to(
+                "This PDS does not have any available domains. It is probably misconfigured.",
+            ));
             return Ok(());
         }
 
         let invite_code = if invite_required {
-            println!(
-                "{}",
+            self.theme.println(
                 self.theme
                     .yellow_bold
-                    .apply_to("This PDS requires an invite code.")
+                    .apply_to("This PDS requires an invite code."),
             );
 
             let invite_code = Input::<String>::with_theme(self.theme.colorful_theme)
@@ -74,16 +99,82 @@ impl TandemAction for ActionCreateAccount<'_> {
         let random_handle =
             petname::petname(2, "-").ok_or_else(|| anyhow!("Failed to generate random handle"))?;
 
-        let suggested_handle = format!("{}{}", random_handle, available_domains[0]);
+        let pds_client = PdsClient::unauthenticated(&self.http_client, &pds)?;
+        let handle = loop {
+            let handle = if let Some(handle) = &self.options.handle {
+                handle.clone()
+            } else {
+                let mut handle_input =
+                    Input::<String>::with_theme(self.theme.colorful_theme).with_prompt("Handle");
+                if let Some(suggested_handle) =
+                    suggest_handle(&random_handle, &available_domains[0])
+                {
+                    handle_input = handle_input.default(suggested_handle);
+                }
+                handle_input.interact()?
+            };
 
-        let handle = Input::<String>::with_theme(self.theme.colorful_theme)
-            .with_prompt("Handle")
-            .default(suggested_handle)
-            .interact()?;
+            // Handles on custom domains may already point at an existing DID,
+            // so only handles under the PDS's own domains are checked here.
+            if is_custom_domain_handle(&handle, &available_domains) {
+                break handle;
+            }
+            match pds_client.resolve_handle(&handle).await {
+                Ok(None) => break handle,
+                Ok(Some(did)) => {
+                    self.theme.println(
+                        self.theme
+                            .yellow_bold
+                            .apply_to(format!("That handle is already taken by {}.", did)),
+                    );
+                    if self.options.handle.is_some() || !user_attended() {
+                        return Err(anyhow!("handle {} is already taken", handle));
+                    }
+                }
+                Err(err) => {
+                    self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                        "Unable to check whether {} is available: {:#}",
+                        handle, err
+                    )));
+                    break handle;
+                }
+            }
+        };
 
-        let email = Input::<String>::with_theme(self.theme.colorful_theme)
-            .with_prompt("Email")
-            .interact()?;
+        if is_custom_domain_handle(&handle, &available_domains)
+            && !self.handle_preflight(&handle).await?
+        {
+            return Ok(());
+        }
+
+        let email = if let Some(email) = &self.options.email {
+            email.clone()
+        } else if self.options.email_stdin {
+            let mut email = String::new();
+            std::io::stdin()
+                .read_line(&mut email)
+                .context("failed to read email from standard input")?;
+            let email = email.trim().to_string();
+            if !is_valid_email(&email) {
+                return Err(anyhow!("invalid email {} read from standard input", email));
+            }
+            email
+        } else if !user_attended() {
+            return Err(anyhow!(
+                "an email is required without a terminal: pass --email= or --email-stdin"
+            ));
+        } else {
+            Input::<String>::with_theme(self.theme.colorful_theme)
+                .with_prompt("Email")
+                .validate_with(|email: &String| {
+                    if is_valid_email(email) {
+                        Ok(())
+                    } else {
+                        Err(format!("invalid email {}", email))
+                    }
+                })
+                .interact()?
+        };
 
         let password = Password::with_theme(self.theme.colorful_theme)
             .with_prompt("Password")
@@ -106,59 +197,237 @@ impl TandemAction for ActionCreateAccount<'_> {
             }
         };
 
-        let key_types = &["provided jwk", "generate p256", "generate k256"];
-
-        let key_type = Select::with_theme(self.theme.colorful_theme)
-            .with_prompt("Select key type")
-            .default(0)
-            .items(&key_types[..])
-            .interact()?;
-
-        let recovery_key = if key_type == 0 {
-            let jwk = get_jwk_input(self.theme.colorful_theme)?;
-            let did_key = crate::crypto::jwk_to_did_key(&jwk)?;
-
-            println!("{}", self.theme.green.apply_to("✔ Derived DID key"));
-            println!("{}", self.theme.white_dim.apply_to(&did_key));
-            did_key
-        } else {
-            let (secret_jwk, encoded_public_key) = if key_type == 1 {
-                crate::crypto::p256::gen_key()
-            } else if key_type == 2 {
-                crate::crypto::k256::gen_key()
-            } else {
-                Err(anyhow!("Invalid key type"))
-            }?;
-            println!(
-                "{}",
-                self.theme
-                    .yellow_bold
-                    .apply_to("Important! Securely store the following private key."),
-            );
-            println!("{}", self.theme.red_bold.apply_to(&secret_jwk));
-            encoded_public_key
-        };
-        let recovery_key = format!("did:key:{}", recovery_key);
+        let rotation_key = self.genesis_rotation_key()?;
 
+        let started = Instant::now();
         let (new_did, new_handle, _new_access_jwt) = create_account(
             &self.http_client,
-            &pds_hostname,
+            &pds,
             &handle,
             &password,
             &email,
-            &recovery_key,
+            rotation_key.as_deref(),
             invite_code,
             existing_did,
         )
         .await?;
+        clear_reminders();
 
-        println!(
-            "{}",
-            self.theme
-                .green
-                .apply_to(format!("✔ Account created: {} ({})", new_did, new_handle))
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Account created: {} ({}){}",
+            new_did,
+            new_handle,
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "account_created",
+            json!({ "did": new_did, "handle": new_handle }),
         );
 
         Ok(())
     }
 }
+
+impl ActionCreateAccount<'_> {
+    /// Asks for a rotation key of the user's own to include in the genesis
+    /// operation, ahead of the PDS's key, so they control recovery from the
+    /// start. Returns `None` when the PDS should manage every rotation key.
+    fn genesis_rotation_key(&self) -> Result<Option<String>> {
+        let key_types = &[
+            "provided jwk",
+            "provided did:key",
+            "generate p256",
+            "generate k256",
+            "none (the PDS manages every rotation key)",
+        ];
+
+        let key_type = Select::with_theme(self.theme.colorful_theme)
+            .with_prompt("Select your rotation key type")
+            .default(0)
+            .items(&key_types[..])
+            .interact()?;
+
+        let did_key = match key_type {
+            0 => {
+                let jwk =
+                    get_jwk_input(self.theme.colorful_theme, self.options.jwk_file.as_deref())?;
+                let did_key = crate::crypto::jwk_to_did_key(&jwk)?;
+
+                self.theme
+                    .println(self.theme.green.apply_to("✔ Derived DID key"));
+                self.theme
+                    .event("derived_did_key", json!({ "did_key": did_key }));
+                self.theme.println(self.theme.white_dim.apply_to(&did_key));
+                did_key
+            }
+            1 => {
+                let did_key = Input::<String>::with_theme(self.theme.colorful_theme)
+                    .with_prompt("did:key")
+                    .validate_with(|input: &String| {
+                        genesis_rotation_key(input)
+                            .map(|_| ())
+                            .map_err(|err| err.to_string())
+                    })
+                    .interact()?;
+                self.theme.println(self.theme.yellow_bold.apply_to(
+                        "Make sure you hold the private key for this did:key. Without it, the key cannot be used to recover the account."
+                    )
+                );
+                did_key
+            }
+            2 | 3 => {
+                let key_format = get_key_format_input(self.theme.colorful_theme)?;
+                let (curve, (secret_jwk, encoded_public_key)) = if key_type == 2 {
+                    (Curve::P256, crate::crypto::p256::gen_key(key_format)?)
+                } else {
+                    (Curve::K256, crate::crypto::k256::gen_key(key_format)?)
+                };
+                check_generated_key(curve, &secret_jwk, key_format, &encoded_public_key)
+                    .context("generated key failed its consistency check; nothing was changed")?;
+                output_generated_key(
+                    self.theme,
+                    &self.options,
+                    &secret_jwk,
+                    &encoded_public_key,
+                    "Important! Securely store the following private key.",
+                )?;
+                encoded_public_key
+            }
+            _ => return Ok(None),
+        };
+
+        genesis_rotation_key(&did_key).map(Some)
+    }
+
+    /// Checks that a handle on a custom domain already resolves through DNS or
+    /// `.well-known`, which the PDS requires before it will create the account.
+    /// Returns false when the user chooses not to proceed.
+    async fn handle_preflight(&self, handle: &str) -> Result<bool> {
+        let started = Instant::now();
+        let resolved = match resolve_handle_dns(handle).await {
+            Ok(did) => Ok(did),
+            Err(_) => resolve_handle_http(&self.http_client, handle).await,
+        };
+
+        match resolved {
+            Ok(did) => {
+                self.theme.println(self.theme.green.apply_to(format!(
+                    "✔ Resolved {} to {}{}",
+                    handle,
+                    did,
+                    self.options.elapsed(started)
+                )));
+                self.theme
+                    .event("resolved_handle", json!({ "handle": handle, "did": did }));
+                Ok(true)
+            }
+            Err(err) => {
+                self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                        "{} is not one of this PDS's domains and does not resolve yet ({}). Configure the _atproto.{} TXT record or https://{}/.well-known/atproto-did first, or account creation will likely fail.",
+                        handle, err, handle, handle
+                    ))
+                );
+                if !user_attended() {
+                    return Err(anyhow!("handle {} does not resolve", handle));
+                }
+                Ok(Confirm::with_theme(self.theme.colorful_theme)
+                    .with_prompt("Do you want to proceed anyway?")
+                    .default(false)
+                    .show_default(true)
+                    .wait_for_newline(true)
+                    .interact()?)
+            }
+        }
+    }
+}
+
+/// Normalizes a rotation key for the genesis operation to a `did:key`,
+/// checking that it is a valid point on a curve PLC accepts.
+fn genesis_rotation_key(did_key: &str) -> Result<String> {
+    let did_key = did_key.trim();
+    let did_key = if did_key.starts_with("did:key:") {
+        did_key.to_string()
+    } else {
+        format!("did:key:{}", did_key)
+    };
+    did_key_to_verifying_key(&did_key)?;
+    Ok(did_key)
+}
+
+/// Returns true when a handle is not under any of the server's available user
+/// domains, so its DNS or `.well-known` must be set up by the user.
+fn is_custom_domain_handle(handle: &str, available_domains: &[String]) -> bool {
+    let handle = handle.to_ascii_lowercase();
+    !available_domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+        handle
+            .strip_suffix(&domain)
+            .is_some_and(|name| name.len() > 1 && name.ends_with('.'))
+    })
+}
+
+/// Joins a generated name with one of the server's available user domains,
+/// which some servers return with a leading dot and some without.
+fn suggest_handle(name: &str, domain: &str) -> Option<String> {
+    let handle = if domain.starts_with('.') {
+        format!("{}{}", name, domain)
+    } else {
+        format!("{}.{}", name, domain)
+    };
+    is_valid_handle(&handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggest_handle_domain_forms() {
+        assert_eq!(
+            suggest_handle("happy-otter", ".bsky.social"),
+            Some("happy-otter.bsky.social".to_string())
+        );
+        assert_eq!(
+            suggest_handle("happy-otter", "bsky.social"),
+            Some("happy-otter.bsky.social".to_string())
+        );
+        assert_eq!(suggest_handle("happy-otter", ".."), None);
+    }
+
+    #[test]
+    fn custom_domain_handles() {
+        let available_domains = vec![".bsky.social".to_string(), "pds.example.com".to_string()];
+        assert!(!is_custom_domain_handle(
+            "happy-otter.bsky.social",
+            &available_domains
+        ));
+        assert!(!is_custom_domain_handle(
+            "Happy-Otter.PDS.example.com",
+            &available_domains
+        ));
+        assert!(is_custom_domain_handle(
+            "alice.example.com",
+            &available_domains
+        ));
+        assert!(is_custom_domain_handle("bsky.social", &available_domains));
+        assert!(is_custom_domain_handle(
+            "alicebsky.social",
+            &available_domains
+        ));
+    }
+
+    #[test]
+    fn genesis_rotation_keys() {
+        let did_key = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        assert_eq!(genesis_rotation_key(did_key).unwrap(), did_key);
+        assert_eq!(
+            genesis_rotation_key(" zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg ").unwrap(),
+            did_key
+        );
+        assert!(
+            genesis_rotation_key("did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGb")
+                .is_err()
+        );
+        assert!(genesis_rotation_key("did:plc:cbkjy5n7bk3ax2wplmtjofq2").is_err());
+    }
+}
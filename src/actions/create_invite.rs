@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::{Input, Password};
+use serde_json::json;
+
+use crate::{
+    actions::{TandemAction, Theme},
+    xrpc::PdsAdminClient,
+};
+
+pub(crate) struct ActionCreateInvite<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+}
+
+impl<'a> ActionCreateInvite<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionCreateInvite<'_> {
+    fn uses_plc_directory(&self) -> bool {
+        false
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Create Invite' action creates invite codes on a PDS you administer. This action requires the PDS admin password."),
+        );
+
+        let pds = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("What is the hostname or URL of the PDS?")
+            .interact()?;
+        let admin_password = Password::with_theme(self.theme.colorful_theme)
+            .with_prompt("Admin password")
+            .interact()?;
+        let code_count = Input::<u32>::with_theme(self.theme.colorful_theme)
+            .with_prompt("How many invite codes?")
+            .default(1)
+            .interact()?;
+        let use_count = Input::<u32>::with_theme(self.theme.colorful_theme)
+            .with_prompt("Accounts per invite code")
+            .default(1)
+            .interact()?;
+
+        let admin_client = PdsAdminClient::new(&self.http_client, &pds, &admin_password)?;
+
+        for _ in 0..code_count {
+            let code = admin_client
+                .create_invite_code(use_count)
+                .await
+                .context("failed to create invite code")?;
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to(format!("✔ Created invite code {}", code)),
+            );
+            self.theme
+                .event("created_invite_code", json!({ "code": code }));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use dialoguer::{console::user_attended, Confirm, Password};
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{get_handle_input, Options, TandemAction, Theme},
+    resolve::resolve_handle,
+    xrpc::PdsClient,
+};
+
+pub(crate) struct ActionDeactivateAccount<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionDeactivateAccount<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionDeactivateAccount<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Deactivate Account' action deactivates your account on its current PDS, such as the source PDS after a migration. Your DID-PLC document is not changed."),
+        );
+
+        let handle = match &self.options.handle {
+            Some(handle) => handle.clone(),
+            None => get_handle_input(self.theme.colorful_theme, "What is your handle?")?,
+        };
+
+        let started = Instant::now();
+        let resolved_handle = resolve_handle(&self.http_client, &self.plc, &handle)
+            .await
+            .context("failed to resolve handle")?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Resolved {} ({}){}",
+            resolved_handle.did,
+            resolved_handle.pds,
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "resolved_handle",
+            json!({ "did": resolved_handle.did, "pds": resolved_handle.pds }),
+        );
+
+        let password = Password::with_theme(self.theme.colorful_theme)
+            .with_prompt("What is your password?")
+            .interact()?;
+
+        let started = Instant::now();
+        let pds_client = PdsClient::from_credentials(
+            &self.http_client,
+            &resolved_handle.pds,
+            &resolved_handle.did,
+            &password,
+        )
+        .await
+        .context("failed to authenticate against PDS")?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Authenticated with PDS{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("authenticated", json!({ "pds": resolved_handle.pds }));
+
+        self.theme.println(self.theme.red_bold.apply_to(format!(
+                "Warning! Deactivating {} on {} stops the PDS from serving its repository and accepting writes. If your DID still points at this PDS, your account will appear offline until it is activated again.",
+                resolved_handle.did, resolved_handle.pds
+            ))
+        );
+
+        if self.options.dry_run {
+            self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                    "Dry run: would POST com.atproto.server.deactivateAccount to {} for {}. Nothing was deactivated.",
+                    resolved_handle.pds, resolved_handle.did
+                ))
+            );
+            return Ok(());
+        }
+
+        if !user_attended() {
+            return Err(anyhow!(
+                "refusing to deactivate an account without a terminal"
+            ));
+        }
+        if !Confirm::with_theme(self.theme.colorful_theme)
+            .with_prompt(format!("Deactivate {}?", resolved_handle.did))
+            .default(false)
+            .show_default(true)
+            .wait_for_newline(true)
+            .interact()?
+        {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        pds_client
+            .deactivate_account()
+            .await
+            .context("failed to deactivate account")?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Account deactivated{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("deactivated", json!({ "did": resolved_handle.did }));
+
+        Ok(())
+    }
+}
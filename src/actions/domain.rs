@@ -1,6 +1,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use dialoguer::{console::Style, theme::ColorfulTheme};
+use std::fmt::Display;
 
 pub struct Theme<'a> {
     pub red_bold: Style,
@@ -8,9 +9,136 @@ pub struct Theme<'a> {
     pub green: Style,
     pub white_dim: Style,
     pub colorful_theme: &'a ColorfulTheme,
+
+    /// Print structured events instead of the formatted lines, for `--json`.
+    pub json: bool,
+}
+
+impl<'a> Theme<'a> {
+    pub fn colored(colorful_theme: &'a ColorfulTheme) -> Self {
+        Self {
+            red_bold: Style::new().red().bold(),
+            yellow_bold: Style::new().yellow().bold(),
+            green: Style::new().green(),
+            white_dim: Style::new().white().dim(),
+            colorful_theme,
+            json: false,
+        }
+    }
+
+    /// A theme without any styling, for `--no-color`, `NO_COLOR`, and output
+    /// that is not going to a terminal.
+    pub fn plain(colorful_theme: &'a ColorfulTheme) -> Self {
+        Self {
+            red_bold: Style::new(),
+            yellow_bold: Style::new(),
+            green: Style::new(),
+            white_dim: Style::new(),
+            colorful_theme,
+            json: false,
+        }
+    }
+
+    /// A theme that prints one JSON event per line to stdout in place of the
+    /// formatted output, for `--json`. Prompts are still shown on stderr.
+    pub fn json(colorful_theme: &'a ColorfulTheme) -> Self {
+        Self {
+            json: true,
+            ..Self::plain(colorful_theme)
+        }
+    }
+
+    /// Prints a formatted line, unless events are printed instead.
+    pub fn println(&self, line: impl Display) {
+        if !self.json {
+            println!("{}", line);
+        }
+    }
+
+    /// Prints a milestone of an action as a JSON event when `--json` is set.
+    /// `fields` is an object whose members are added after `step`.
+    pub fn event(&self, step: &str, fields: serde_json::Value) {
+        if self.json {
+            println!("{}", event_line(step, fields));
+        }
+    }
+}
+
+/// Renders an event with `step` first, followed by the members of `fields`.
+fn event_line(step: &str, fields: serde_json::Value) -> String {
+    let mut line = format!("{{\"step\":{}", serde_json::Value::from(step));
+    if let serde_json::Value::Object(fields) = fields {
+        for (name, value) in fields {
+            line.push_str(&format!(",{}:{}", serde_json::Value::from(name), value));
+        }
+    }
+    line.push('}');
+    line
 }
 
 #[async_trait]
 pub trait TandemAction: Sync + Send {
     async fn run(&self) -> Result<()>;
+
+    /// Whether the action talks to the PLC directory, in which case the
+    /// directory is checked for reachability before the action runs.
+    fn uses_plc_directory(&self) -> bool {
+        true
+    }
+
+    /// Whether the action would still prompt for input its options do not
+    /// supply, in which case `--action` refuses to run it without a terminal.
+    /// Actions that can take every input from `Options` override this.
+    fn needs_terminal(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_theme_has_no_ansi_codes() {
+        let colorful_theme = ColorfulTheme::default();
+        let theme = Theme::plain(&colorful_theme);
+        for style in [
+            &theme.red_bold,
+            &theme.yellow_bold,
+            &theme.green,
+            &theme.white_dim,
+        ] {
+            let styled = style.apply_to("✔ Operation submitted").to_string();
+            assert_eq!(styled, "✔ Operation submitted");
+            assert!(!styled.contains('\x1b'));
+        }
+
+        let colored = Theme::colored(&colorful_theme);
+        let styled = colored
+            .green
+            .clone()
+            .force_styling(true)
+            .apply_to("✔ Operation submitted")
+            .to_string();
+        assert!(styled.contains('\x1b'));
+    }
+
+    #[test]
+    fn json_events() {
+        assert_eq!(
+            event_line(
+                "resolved_handle",
+                serde_json::json!({ "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2", "pds": "https://pds.example.com" })
+            ),
+            r#"{"step":"resolved_handle","did":"did:plc:cbkjy5n7bk3ax2wplmtjofq2","pds":"https://pds.example.com"}"#
+        );
+        assert_eq!(
+            event_line("submitted", serde_json::json!({})),
+            r#"{"step":"submitted"}"#
+        );
+
+        let colorful_theme = ColorfulTheme::default();
+        assert!(Theme::json(&colorful_theme).json);
+        assert!(!Theme::plain(&colorful_theme).json);
+    }
 }
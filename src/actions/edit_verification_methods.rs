@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::{Input, Select};
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::get_signer_input,
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{
+        build_edit_verification_methods, check_signer_is_rotation_key, last_operation, sign,
+        submit, VerificationMethodEdit,
+    },
+    multikey::did_key_to_verifying_key,
+    plc::{curl_command, get_verification_methods},
+    state::DidLock,
+};
+
+pub(crate) struct ActionEditVerificationMethods<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionEditVerificationMethods<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionEditVerificationMethods<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Edit Verification Methods' action adds, replaces, or removes named verification methods in your DID-PLC document in a single operation. The atproto method can be replaced but not removed. This action requires your tandem private key."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+
+        check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+        warn_verification_method_drift(self.theme, &last.operation);
+        let mut edits: Vec<VerificationMethodEdit> = Vec::new();
+        let mut operation = build_edit_verification_methods(&last, &edits)?;
+        loop {
+            self.theme
+                .println(self.theme.green.apply_to("Verification methods"));
+            let methods = get_verification_methods(&operation)?;
+            for (id, did_key) in &methods {
+                self.theme.println(
+                    self.theme
+                        .white_dim
+                        .apply_to(format!("{}: {}", id, did_key)),
+                );
+            }
+
+            let choices = &[
+                "add or replace a verification method",
+                "remove a verification method",
+                "done",
+            ];
+            let choice = Select::with_theme(self.theme.colorful_theme)
+                .with_prompt("Change")
+                .default(0)
+                .items(&choices[..])
+                .interact()?;
+
+            let edit = match choice {
+                0 => {
+                    let id = Input::<String>::with_theme(self.theme.colorful_theme)
+                        .with_prompt("Verification method name")
+                        .interact()?;
+                    let did_key = Input::<String>::with_theme(self.theme.colorful_theme)
+                        .with_prompt("did:key")
+                        .validate_with(|input: &String| {
+                            did_key_to_verifying_key(input)
+                                .map(|_| ())
+                                .map_err(|err| err.to_string())
+                        })
+                        .interact()?;
+                    VerificationMethodEdit::Set {
+                        id: id.trim().to_string(),
+                        did_key: did_key.trim().to_string(),
+                    }
+                }
+                1 => {
+                    let ids = methods
+                        .iter()
+                        .map(|(id, _)| id.as_str())
+                        .collect::<Vec<_>>();
+                    let index = Select::with_theme(self.theme.colorful_theme)
+                        .with_prompt("Verification method to remove")
+                        .items(&ids[..])
+                        .interact()?;
+                    VerificationMethodEdit::Remove {
+                        id: ids[index].to_string(),
+                    }
+                }
+                _ => break,
+            };
+
+            edits.push(edit);
+            match build_edit_verification_methods(&last, &edits) {
+                Ok(edited) => operation = edited,
+                Err(err) => {
+                    edits.pop();
+                    self.theme
+                        .println(self.theme.red_bold.apply_to(format!("{:#}", err)));
+                }
+            }
+        }
+
+        if edits.is_empty() {
+            self.theme.println(
+                self.theme
+                    .yellow_bold
+                    .apply_to("No changes were made, so nothing was submitted."),
+            );
+            return Ok(());
+        }
+
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use dialoguer::Input;
+use serde_json::json;
+use std::path::PathBuf;
+
+use crate::{
+    actions::{get_did_plc_input, TandemAction, Theme},
+    bundle::export_identity,
+    plc::ensure_did_exists,
+};
+
+pub(crate) struct ActionExportIdentity<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+}
+
+impl<'a> ActionExportIdentity<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionExportIdentity<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Export Identity' action writes your DID document, audit log, and public keys to a bundle file. Private keys are never included."),
+        );
+
+        let did = get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?;
+        let path = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("Bundle file")
+            .default(format!("{}.json", did.replace(':', "_")))
+            .interact()?;
+        let path = PathBuf::from(path);
+
+        ensure_did_exists(&self.http_client, &self.plc, &did).await?;
+
+        let bundle = export_identity(&self.http_client, &self.plc, &did).await?;
+        bundle.write(&path)?;
+
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Exported {} with {} operations and {} keys to {}",
+            did,
+            bundle.audit_log.len(),
+            bundle.did_keys.len(),
+            path.display()
+        )));
+        self.theme.event(
+            "exported",
+            json!({
+                "did": did,
+                "operations": bundle.audit_log.len(),
+                "keys": bundle.did_keys.len(),
+                "path": path,
+            }),
+        );
+
+        Ok(())
+    }
+}
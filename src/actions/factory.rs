@@ -1,13 +1,32 @@
 use super::{
-    ActionAppendHandle, ActionCreateAccount, ActionMigrate, ActionPrepare, TandemAction, Theme,
+    ActionAddRecoveryKey, ActionAppendHandle, ActionBatchAppendHandle, ActionCreateAccount,
+    ActionCreateInvite, ActionDeactivateAccount, ActionEditVerificationMethods,
+    ActionExportIdentity, ActionHistory, ActionIdentity, ActionListRepos, ActionMigrate,
+    ActionPrepare, ActionRemoveHandle, ActionRotateKey, ActionSelfTest, ActionSetHandles,
+    ActionTombstone, ActionVerifyBundle, Options, TandemAction, Theme,
 };
 use anyhow::{anyhow, Result};
 
-pub const SUPPORTED_ACTIONS: &[&str; 4] = &[
+pub const SUPPORTED_ACTIONS: &[&str; 19] = &[
     "Upgrade Account",
     "Create Account",
     "Migrate Account",
     "Append Handle",
+    "View History",
+    "Set Handles",
+    "Tombstone Identity",
+    "View Identity",
+    "Batch Append Handle",
+    "Export Identity",
+    "Verify Bundle",
+    "Self Test",
+    "Create Invite",
+    "List Repos",
+    "Edit Verification Methods",
+    "Add Recovery Key",
+    "Deactivate Account",
+    "Rotate Key",
+    "Remove Handle",
 ];
 
 pub fn get_action<'a>(
@@ -15,14 +34,76 @@ pub fn get_action<'a>(
     theme: &'a Theme<'a>,
     http_client: &reqwest::Client,
     plc: &str,
+    options: &Options,
 ) -> Result<Box<dyn TandemAction + 'a>> {
     match selected_operation {
-        0 => Ok(Box::new(ActionPrepare::new(theme, http_client, plc)) as Box<dyn TandemAction>),
-        1 => Ok(Box::new(ActionCreateAccount::new(theme, http_client)) as Box<dyn TandemAction>),
-        2 => Ok(Box::new(ActionMigrate::new(theme, http_client, plc)) as Box<dyn TandemAction>),
-        3 => {
-            Ok(Box::new(ActionAppendHandle::new(theme, http_client, plc)) as Box<dyn TandemAction>)
+        0 => Ok(
+            Box::new(ActionPrepare::new(theme, http_client, plc, options)) as Box<dyn TandemAction>,
+        ),
+        1 => Ok(
+            Box::new(ActionCreateAccount::new(theme, http_client, options))
+                as Box<dyn TandemAction>,
+        ),
+        2 => Ok(
+            Box::new(ActionMigrate::new(theme, http_client, plc, options)) as Box<dyn TandemAction>,
+        ),
+        3 => Ok(
+            Box::new(ActionAppendHandle::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
+        4 => Ok(Box::new(ActionHistory::new(theme, http_client, plc)) as Box<dyn TandemAction>),
+        5 => Ok(
+            Box::new(ActionSetHandles::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
+        6 => Ok(
+            Box::new(ActionTombstone::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
+        7 => Ok(Box::new(ActionIdentity::new(theme, http_client, plc)) as Box<dyn TandemAction>),
+        8 => Ok(Box::new(ActionBatchAppendHandle::new(
+            theme,
+            http_client,
+            plc,
+            options,
+        )) as Box<dyn TandemAction>),
+        9 => {
+            Ok(Box::new(ActionExportIdentity::new(theme, http_client, plc))
+                as Box<dyn TandemAction>)
         }
+        10 => {
+            Ok(Box::new(ActionVerifyBundle::new(theme, http_client, plc)) as Box<dyn TandemAction>)
+        }
+        11 => Ok(Box::new(ActionSelfTest::new(theme, options)) as Box<dyn TandemAction>),
+        12 => Ok(Box::new(ActionCreateInvite::new(theme, http_client)) as Box<dyn TandemAction>),
+        13 => {
+            Ok(Box::new(ActionListRepos::new(theme, http_client, options))
+                as Box<dyn TandemAction>)
+        }
+        14 => Ok(Box::new(ActionEditVerificationMethods::new(
+            theme,
+            http_client,
+            plc,
+            options,
+        )) as Box<dyn TandemAction>),
+        15 => Ok(
+            Box::new(ActionAddRecoveryKey::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
+        16 => Ok(Box::new(ActionDeactivateAccount::new(
+            theme,
+            http_client,
+            plc,
+            options,
+        )) as Box<dyn TandemAction>),
+        17 => Ok(
+            Box::new(ActionRotateKey::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
+        18 => Ok(
+            Box::new(ActionRemoveHandle::new(theme, http_client, plc, options))
+                as Box<dyn TandemAction>,
+        ),
         _ => Err(anyhow!("Unsupported operation")),
     }
 }
@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use crate::{
+    actions::{inputs::get_new_passphrase_input, interrupt::note_generated_key, Options, Theme},
+    crypto::{did_key_fingerprint, encrypt_secret, reencode_multikey},
+};
+
+/// Hands a freshly generated private key to the user: written to the
+/// `--key-out` file when one is given, encrypted with a passphrase under
+/// `--encrypt-key`, and printed otherwise. `warning` is shown above a printed
+/// key.
+pub(crate) fn output_generated_key(
+    theme: &Theme<'_>,
+    options: &Options,
+    secret_key: &str,
+    encoded_public_key: &str,
+    warning: &str,
+) -> Result<()> {
+    let did_key = format!("did:key:{}", encoded_public_key);
+
+    match &options.key_out {
+        Some(path) if options.encrypt_key => {
+            let passphrase = get_new_passphrase_input(theme.colorful_theme)?;
+            let plaintext = self_describing_secret_key(secret_key, &did_key)?;
+            write_secret_key(path, &encrypt_secret(&plaintext, &passphrase)?)?;
+            theme.println(theme.green.apply_to(format!(
+                "✔ Wrote the encrypted private key for {} to {}",
+                did_key,
+                path.display()
+            )));
+            theme.event(
+                "generated_key",
+                json!({ "did_key": did_key, "path": path, "encrypted": true }),
+            );
+        }
+        Some(path) => {
+            write_secret_key(path, secret_key)?;
+            theme.println(theme.green.apply_to(format!(
+                "✔ Wrote the private key for {} to {}",
+                did_key,
+                path.display()
+            )));
+            theme.event("generated_key", json!({ "did_key": did_key, "path": path }));
+        }
+        None => {
+            theme.println(theme.yellow_bold.apply_to(warning));
+            theme.println(theme.red_bold.apply_to(secret_key));
+            theme.event(
+                "generated_key",
+                json!({ "did_key": did_key, "private_key": secret_key }),
+            );
+        }
+    }
+    note_generated_key(&did_key);
+
+    if is_hex_secret_key(secret_key) && !options.encrypt_key {
+        let (curve, _) = did_key_fingerprint(&did_key)?;
+        theme.println(theme.white_dim.apply_to(format!(
+            "Hex keys do not record their curve. Give this key to tandem as {}:<hex>.",
+            curve
+        )));
+    }
+
+    if let Some(encoding) = options.key_encoding {
+        theme.println(theme.white_dim.apply_to(format!(
+            "{} ({:?}: {})",
+            did_key,
+            encoding,
+            reencode_multikey(encoded_public_key, encoding)?
+        )));
+    }
+    Ok(())
+}
+
+fn is_hex_secret_key(secret_key: &str) -> bool {
+    secret_key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the key to encrypt in a form tandem can load again on its own: a
+/// hex key is prefixed with its curve, and every other format is kept.
+fn self_describing_secret_key(secret_key: &str, did_key: &str) -> Result<String> {
+    if !is_hex_secret_key(secret_key) {
+        return Ok(secret_key.to_string());
+    }
+    let (curve, _) = did_key_fingerprint(did_key)?;
+    Ok(format!("{}:{}", curve, secret_key))
+}
+
+/// Writes a private key to a new file readable only by its owner, and syncs
+/// it to disk before returning. An existing file is never replaced.
+fn write_secret_key(path: &Path, secret_key: &str) -> Result<()> {
+    let mut file = OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+
+    file.open(path)
+        .and_then(|mut file| {
+            file.write_all(secret_key.as_bytes())?;
+            file.write_all(b"\n")?;
+            file.sync_all()
+        })
+        .with_context(|| format!("failed to write private key to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{decode_any_secret_key, KeyFormat};
+
+    #[test]
+    fn encrypted_keys_are_self_describing() {
+        for key_format in KeyFormat::ALL {
+            let (secret_key, encoded_public_key) =
+                crate::crypto::k256::gen_key(key_format).unwrap();
+            let did_key = format!("did:key:{}", encoded_public_key);
+            let plaintext = self_describing_secret_key(&secret_key, &did_key).unwrap();
+            let jwk = decode_any_secret_key(&plaintext).unwrap();
+            assert_eq!(
+                crate::crypto::jwk_to_did_key(&jwk).unwrap(),
+                encoded_public_key,
+                "{}",
+                key_format.label()
+            );
+        }
+    }
+
+    #[test]
+    fn secret_keys_are_written_once() {
+        let dir = std::env::temp_dir().join(format!("tandem-key-out-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rotation.jwk");
+        let _ = std::fs::remove_file(&path);
+
+        write_secret_key(&path, "secret").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let err = write_secret_key(&path, "other").unwrap_err();
+        assert!(format!("{:#}", err).contains("failed to write private key"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{
+    actions::{get_did_plc_input, TandemAction, Theme},
+    plc::{operation_history, OperationChange},
+};
+
+pub(crate) struct ActionHistory<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+}
+
+impl<'a> ActionHistory<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionHistory<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(self.theme.white_dim.apply_to(
+            "The 'View History' action displays the operation history of your DID-PLC document.",
+        ));
+
+        let did = get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?;
+
+        let history = operation_history(&self.http_client, &self.plc, &did)
+            .await
+            .context("failed to get operation history")?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to(format!("✔ Retrieved {} operations", history.len())),
+        );
+
+        for (index, summary) in history.iter().enumerate() {
+            let labels = if summary.changes.is_empty() {
+                "no changes".to_string()
+            } else {
+                summary
+                    .changes
+                    .iter()
+                    .map(|change| change.label())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            };
+            self.theme.event(
+                "operation",
+                json!({
+                    "index": index,
+                    "changes": summary.changes.iter().map(|change| change.label()).collect::<Vec<_>>(),
+                    "operation": summary.operation,
+                }),
+            );
+            self.theme.println(format!(
+                "{}. {}",
+                index,
+                self.theme.yellow_bold.apply_to(labels)
+            ));
+
+            for change in &summary.changes {
+                let field = match change {
+                    OperationChange::RotationKeys => "rotationKeys",
+                    OperationChange::VerificationMethods => "verificationMethods",
+                    OperationChange::Handles => "alsoKnownAs",
+                    OperationChange::Services => "services",
+                    OperationChange::Genesis | OperationChange::Tombstone => continue,
+                };
+                self.theme.println(format!(
+                    "   {}: {}",
+                    field,
+                    self.theme.white_dim.apply_to(&summary.operation[field])
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::{Confirm, Input};
+use elliptic_curve::JwkEcKey;
+use serde_json::json;
+use std::str::FromStr;
+
+use crate::{
+    actions::{inputs::get_subject_input, TandemAction, Theme},
+    crypto::{did_key_fingerprint, jwk_to_did_key},
+    plc::{did_plc_data, ensure_did_exists, get_verification_methods, ATPROTO_VERIFICATION_METHOD},
+    resolve::{resolve_did_only, resolve_handle},
+    xrpc::describe_server,
+};
+
+pub(crate) struct ActionIdentity<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+}
+
+impl<'a> ActionIdentity<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionIdentity<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'View Identity' action displays the current state of your DID-PLC document. It does not change anything."),
+        );
+
+        let subject = get_subject_input(self.theme.colorful_theme, "What is your handle or DID?")?;
+
+        let jwk = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("JWK to locate in the rotation keys (optional)")
+            .allow_empty(true)
+            .interact()?;
+        let supplied_key = if jwk.trim().is_empty() {
+            None
+        } else {
+            let jwk = JwkEcKey::from_str(jwk.trim()).context("failed to parse JWK")?;
+            Some(format!("did:key:{}", jwk_to_did_key(&jwk)?))
+        };
+
+        let resolved = if subject.starts_with("did:") {
+            resolve_did_only(&self.http_client, &self.plc, &subject).await
+        } else {
+            resolve_handle(&self.http_client, &self.plc, &subject).await
+        }
+        .context("failed to resolve identity")?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to(format!("✔ Resolved {}", resolved.did)),
+        );
+        self.theme.event(
+            "resolved",
+            json!({
+                "did": resolved.did,
+                "pds": resolved.pds,
+                "handles": resolved.handles,
+                "unverified_handles": resolved.unverified_handles,
+            }),
+        );
+
+        ensure_did_exists(&self.http_client, &self.plc, &resolved.did).await?;
+
+        let document = did_plc_data(&self.http_client, &self.plc, &resolved.did)
+            .await
+            .context("failed to get DID document")?;
+        self.theme
+            .println(self.theme.green.apply_to("✔ Retrieved DID document"));
+        self.theme
+            .event("retrieved_did_document", json!({ "document": document }));
+
+        self.theme
+            .println(self.theme.yellow_bold.apply_to("Handles"));
+        match document["alsoKnownAs"].as_array() {
+            Some(handles) if !handles.is_empty() => {
+                for handle in handles {
+                    self.theme
+                        .println(format!("  {}", handle.as_str().unwrap_or_default()));
+                }
+            }
+            _ => self
+                .theme
+                .println(format!("  {}", self.theme.white_dim.apply_to("none"))),
+        }
+
+        let rotation_keys = document["rotationKeys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|key| key.as_str())
+            .collect::<Vec<&str>>();
+
+        self.theme.println(
+            self.theme
+                .yellow_bold
+                .apply_to("Rotation keys (highest precedence first)"),
+        );
+        for (index, key) in rotation_keys.iter().enumerate() {
+            let details = match did_key_fingerprint(key) {
+                Ok((curve, fingerprint)) => format!("{} {}", curve, fingerprint),
+                Err(_) => "unrecognized key".to_string(),
+            };
+            let marker = if supplied_key.as_deref() == Some(*key) {
+                self.theme.green.apply_to(" ← supplied key").to_string()
+            } else {
+                String::new()
+            };
+            self.theme.println(format!(
+                "  {}. {} {}{}",
+                index,
+                key,
+                self.theme.white_dim.apply_to(details),
+                marker
+            ));
+        }
+
+        if let Some(supplied_key) = &supplied_key {
+            match rotation_key_precedence(&rotation_keys, supplied_key) {
+                Some(0) => self.theme.println(self.theme.green.apply_to(
+                    "✔ The supplied key has the highest precedence and can override operations signed by any other rotation key.",
+                )),
+                Some(index) => self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                    "The supplied key is at precedence {}. Operations it signs can be overridden by the {} key(s) listed above it within 72 hours.",
+                    index, index
+                ))),
+                None => self.theme.println(self.theme.red_bold.apply_to(format!(
+                    "The supplied key {} is not a rotation key for this identity.",
+                    supplied_key
+                ))),
+            }
+        }
+
+        print_verification_methods(self.theme, &document);
+
+        self.theme.println(self.theme.yellow_bold.apply_to("PDS"));
+        self.theme.println(format!("  {}", resolved.pds));
+
+        if Confirm::with_theme(self.theme.colorful_theme)
+            .with_prompt("Check that the PDS is reachable?")
+            .default(true)
+            .interact()?
+        {
+            self.print_pds_health(&resolved.pds).await;
+        }
+
+        Ok(())
+    }
+}
+
+impl ActionIdentity<'_> {
+    /// Probes the PDS with `describeServer` and reports whether it responded
+    /// and whether the DID it advertises is the one its endpoint implies.
+    async fn print_pds_health(&self, pds: &str) {
+        let pds_did = match describe_server(&self.http_client, pds).await {
+            Ok((pds_did, _, _)) => pds_did,
+            Err(err) => {
+                self.theme.println(self.theme.red_bold.apply_to(format!(
+                    "  ✘ PDS did not respond to describeServer: {:#}",
+                    err
+                )));
+                return;
+            }
+        };
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to(format!("  ✔ PDS responded as {}", pds_did)),
+        );
+        self.theme
+            .event("pds_responded", json!({ "pds": pds, "did": pds_did }));
+
+        match expected_pds_did(pds) {
+            Some(expected) if expected == pds_did => self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("  ✔ PDS DID matches the endpoint in the DID document"),
+            ),
+            Some(expected) => self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                "  PDS DID {} does not match {}, which the endpoint in the DID document implies. The endpoint may point at a proxy or the wrong server.",
+                pds_did, expected
+            ))),
+            None => self.theme.println(
+                self.theme
+                    .white_dim
+                    .apply_to("  The endpoint is not a URL, so the PDS DID was not checked"),
+            ),
+        }
+    }
+}
+
+/// The `did:web` a PDS at `endpoint` is expected to advertise, with any port
+/// percent-encoded as the did:web method requires.
+fn expected_pds_did(endpoint: &str) -> Option<String> {
+    let url = reqwest::Url::parse(endpoint).ok()?;
+    let host = url.host_str().filter(|host| !host.is_empty())?;
+    Some(match url.port() {
+        Some(port) => format!("did:web:{}%3A{}", host, port),
+        None => format!("did:web:{}", host),
+    })
+}
+
+/// Prints each verification method with its id and key curve, marking the
+/// `atproto` method that signs repository commits.
+pub(crate) fn print_verification_methods(theme: &Theme<'_>, document: &serde_json::Value) {
+    theme.println(theme.yellow_bold.apply_to("Verification methods"));
+    let verification_methods = get_verification_methods(document).unwrap_or_default();
+    if verification_methods.is_empty() {
+        theme.println(format!("  {}", theme.white_dim.apply_to("none")));
+    }
+    for (id, key) in &verification_methods {
+        let details = match did_key_fingerprint(key) {
+            Ok((curve, fingerprint)) => format!("{} {}", curve, fingerprint),
+            Err(_) => "unrecognized key".to_string(),
+        };
+        let marker = if id == ATPROTO_VERIFICATION_METHOD {
+            theme.green.apply_to(" ← signing key").to_string()
+        } else {
+            String::new()
+        };
+        theme.println(format!(
+            "  {}: {} {}{}",
+            id,
+            key,
+            theme.white_dim.apply_to(details),
+            marker
+        ));
+    }
+}
+
+/// Returns the position of a `did:key` in the rotation keys, where a lower
+/// position takes precedence when the directory resolves conflicting forks.
+fn rotation_key_precedence(rotation_keys: &[&str], did_key: &str) -> Option<usize> {
+    rotation_keys.iter().position(|key| *key == did_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_key_precedences() {
+        let rotation_keys = ["did:key:zQ3shA", "did:key:zDnaeB"];
+        assert_eq!(
+            rotation_key_precedence(&rotation_keys, "did:key:zQ3shA"),
+            Some(0)
+        );
+        assert_eq!(
+            rotation_key_precedence(&rotation_keys, "did:key:zDnaeB"),
+            Some(1)
+        );
+        assert_eq!(
+            rotation_key_precedence(&rotation_keys, "did:key:zDnaeC"),
+            None
+        );
+    }
+
+    #[test]
+    fn expected_pds_dids() {
+        assert_eq!(
+            expected_pds_did("https://pds.example.com"),
+            Some("did:web:pds.example.com".to_string())
+        );
+        assert_eq!(
+            expected_pds_did("https://pds.example.com/"),
+            Some("did:web:pds.example.com".to_string())
+        );
+        assert_eq!(
+            expected_pds_did("http://localhost:2583"),
+            Some("did:web:localhost%3A2583".to_string())
+        );
+        assert_eq!(expected_pds_did("pds.example.com"), None);
+    }
+}
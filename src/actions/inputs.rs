@@ -1,14 +1,154 @@
 use anyhow::{anyhow, Context, Result};
-use dialoguer::{theme::ColorfulTheme, Input};
+use dialoguer::{theme::ColorfulTheme, Input, Password, Select};
 use elliptic_curve::JwkEcKey;
-use std::str::FromStr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    actions::Options,
+    crypto::{decode_any_secret_key, decrypt_secret, is_encrypted_secret, KeyFormat},
+    signer::{JwkSigner, Signer},
+};
+
+/// Environment variable holding a private key to use instead of prompting for
+/// one.
+const JWK_ENV_VAR: &str = "TANDEM_JWK";
+
+/// Environment variable holding the passphrase of an encrypted JWK file.
+const PASSPHRASE_ENV_VAR: &str = "TANDEM_KEY_PASSPHRASE";
+
+/// Loads the rotation key from `jwk_file` or `TANDEM_JWK` when either is set,
+/// and prompts for it otherwise. The key may be in any `KeyFormat`, and an
+/// encrypted `jwk_file` is decrypted with `get_encrypted_jwk_input`.
+pub(crate) fn get_jwk_input(theme: &ColorfulTheme, jwk_file: Option<&Path>) -> Result<JwkEcKey> {
+    if let Some(jwk_file) = jwk_file {
+        if std::fs::read_to_string(jwk_file).is_ok_and(|contents| is_encrypted_secret(&contents)) {
+            return get_encrypted_jwk_input(theme, jwk_file, None);
+        }
+        return read_jwk_file(jwk_file);
+    }
+
+    if let Ok(secret_jwk) = std::env::var(JWK_ENV_VAR) {
+        return parse_secret_key(&secret_jwk)
+            .with_context(|| format!("failed to load {}", JWK_ENV_VAR));
+    }
 
-pub(crate) fn get_jwk_input(theme: &ColorfulTheme) -> Result<JwkEcKey> {
     let secret_jwk = Input::<String>::with_theme(theme)
-        .with_prompt("JWK")
+        .with_prompt("Private key (JWK or p256:/k256: hex)")
         .interact()?;
 
-    JwkEcKey::from_str(&secret_jwk).context("failed to parse JWK")
+    parse_secret_key(&secret_jwk)
+}
+
+/// Loads a key file written with `--encrypt-key` in any `KeyFormat`,
+/// decrypting it with `passphrase`, `TANDEM_KEY_PASSPHRASE`, or a prompted
+/// passphrase, in that order.
+pub(crate) fn get_encrypted_jwk_input(
+    theme: &ColorfulTheme,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> Result<JwkEcKey> {
+    let encrypted = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read JWK file {}", path.display()))?;
+    let passphrase = match passphrase {
+        Some(passphrase) => passphrase.to_string(),
+        None => match std::env::var(PASSPHRASE_ENV_VAR) {
+            Ok(passphrase) => passphrase,
+            Err(_) => Password::with_theme(theme)
+                .with_prompt(format!("Passphrase for {}", path.display()))
+                .interact()?,
+        },
+    };
+    let secret_jwk = decrypt_secret(&encrypted, &passphrase)
+        .with_context(|| format!("failed to load JWK file {}", path.display()))?;
+    parse_secret_key(&secret_jwk)
+        .with_context(|| format!("failed to load JWK file {}", path.display()))
+}
+
+/// Returns the passphrase to encrypt a generated key with, from
+/// `TANDEM_KEY_PASSPHRASE` or prompted for twice.
+pub(crate) fn get_new_passphrase_input(theme: &ColorfulTheme) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV_VAR) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = Password::with_theme(theme)
+        .with_prompt("Passphrase to encrypt the private key with")
+        .with_confirmation("Repeat the passphrase", "The passphrases do not match.")
+        .interact()?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("the passphrase must not be empty"));
+    }
+    Ok(passphrase)
+}
+
+/// Prompts for a JWK other than the rotation key, ignoring `--jwk-file` and
+/// `TANDEM_JWK`, which always hold the key that signs the operation.
+pub(crate) fn get_other_jwk_input(theme: &ColorfulTheme, prompt: &str) -> Result<JwkEcKey> {
+    let secret_jwk = Input::<String>::with_theme(theme)
+        .with_prompt(prompt)
+        .interact()?;
+
+    parse_secret_key(&secret_jwk)
+}
+
+/// Returns the signer supplied by the embedding application, or a JWK signer
+/// for a key loaded by `get_jwk_input`.
+pub(crate) fn get_signer_input(
+    theme: &ColorfulTheme,
+    options: &Options,
+) -> Result<Arc<dyn Signer>> {
+    if let Some(signer) = &options.signer {
+        return Ok(signer.clone());
+    }
+
+    let jwk = get_jwk_input(theme, options.jwk_file.as_deref())?;
+    Ok(Arc::new(JwkSigner::new(jwk)?))
+}
+
+/// Whether `get_signer_input` would have to prompt, for the key itself or for
+/// the passphrase of an encrypted `--jwk-file`.
+pub(crate) fn signer_input_prompts(options: &Options) -> bool {
+    if options.signer.is_some() {
+        return false;
+    }
+    match &options.jwk_file {
+        Some(jwk_file) => {
+            std::fs::read_to_string(jwk_file).is_ok_and(|contents| is_encrypted_secret(&contents))
+                && std::env::var_os(PASSPHRASE_ENV_VAR).is_none()
+        }
+        None => std::env::var_os(JWK_ENV_VAR).is_none(),
+    }
+}
+
+pub(crate) fn read_jwk_file(path: &Path) -> Result<JwkEcKey> {
+    let secret_jwk = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read JWK file {}", path.display()))?;
+    parse_secret_key(&secret_jwk)
+        .with_context(|| format!("failed to load JWK file {}", path.display()))
+}
+
+/// Parses a private key in any `KeyFormat` on a curve PLC supports. Errors
+/// never include the key itself.
+fn parse_secret_key(secret: &str) -> Result<JwkEcKey> {
+    let jwk = decode_any_secret_key(secret)?;
+    match jwk.crv() {
+        "P-256" | "secp256k1" => Ok(jwk),
+        crv => Err(anyhow!("unsupported JWK curve {}", crv)),
+    }
+}
+
+pub(crate) fn get_key_format_input(theme: &ColorfulTheme) -> Result<KeyFormat> {
+    let key_formats = KeyFormat::ALL.map(|key_format| key_format.label());
+
+    let key_format = Select::with_theme(theme)
+        .with_prompt("Select private key format")
+        .default(0)
+        .items(&key_formats[..])
+        .interact()?;
+
+    Ok(KeyFormat::ALL[key_format])
 }
 
 pub(crate) fn get_handle_input(theme: &ColorfulTheme, prompt: &str) -> Result<String> {
@@ -16,7 +156,7 @@ pub(crate) fn get_handle_input(theme: &ColorfulTheme, prompt: &str) -> Result<St
         .with_prompt(prompt)
         .interact()?;
 
-    is_valid_handle(&handle).ok_or(anyhow!("invalid handle"))
+    is_valid_handle(&handle).ok_or_else(|| anyhow!("invalid handle {}", at_uri_authority(&handle)))
 }
 
 pub(crate) fn get_did_plc_input(theme: &ColorfulTheme, prompt: &str) -> Result<String> {
@@ -24,10 +164,64 @@ pub(crate) fn get_did_plc_input(theme: &ColorfulTheme, prompt: &str) -> Result<S
         .with_prompt(prompt)
         .interact()?;
 
-    is_valid_did_plc(&handle).ok_or(anyhow!("invalid DID-PLC"))
+    is_valid_did_plc(&handle)
+        .ok_or_else(|| anyhow!("invalid DID-PLC {}", at_uri_authority(&handle)))
+}
+
+pub(crate) fn get_subject_input(theme: &ColorfulTheme, prompt: &str) -> Result<String> {
+    let subject = Input::<String>::with_theme(theme)
+        .with_prompt(prompt)
+        .interact()?;
+
+    is_valid_did_plc(&subject)
+        .or_else(|| is_valid_handle(&subject))
+        .ok_or_else(|| anyhow!("invalid handle or DID-PLC {}", at_uri_authority(&subject)))
+}
+
+/// Returns the identity an input names, accepting a bare handle or DID, an
+/// `@handle`, or an AT-URI such as `at://did:plc:xyz/app.bsky.feed.post/123`
+/// whose path, query, and fragment are discarded.
+fn at_uri_authority(input: &str) -> &str {
+    let input = input.trim();
+    match input.strip_prefix("at://") {
+        Some(uri) => uri.split(['/', '?', '#']).next().unwrap_or_default(),
+        None => input.strip_prefix('@').unwrap_or(input),
+    }
 }
 
-fn is_valid_hostname(hostname: &str) -> bool {
+/// Waits for a confirmation code to be written to `path` by an external
+/// process, ignoring any file last modified before `since`.
+pub(crate) async fn wait_for_token_file(
+    path: &Path,
+    since: SystemTime,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<String> {
+    let started = Instant::now();
+
+    loop {
+        let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified());
+        if modified.is_ok_and(|modified| modified >= since) {
+            let token = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let token = token.trim();
+            if !token.is_empty() {
+                return Ok(token.to_string());
+            }
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(anyhow!(
+                "timed out waiting for a confirmation code in {}",
+                path.display()
+            ));
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+pub(crate) fn is_valid_hostname(hostname: &str) -> bool {
     fn is_valid_char(byte: u8) -> bool {
         byte.is_ascii_lowercase()
             || byte.is_ascii_uppercase()
@@ -47,33 +241,50 @@ fn is_valid_hostname(hostname: &str) -> bool {
         || hostname.len() > 253)
 }
 
-fn is_valid_handle(handle: &str) -> Option<String> {
-    let trimmed = {
-        if let Some(value) = handle.strip_prefix("at://") {
-            value
-        } else if let Some(value) = handle.strip_prefix('@') {
-            value
-        } else {
-            handle
-        }
-    };
-    let trimmed = trimmed.to_lowercase();
-    if is_valid_hostname(&trimmed) && trimmed.chars().any(|c| c == '.') {
+/// Top-level domains the atproto handle specification disallows.
+const DISALLOWED_HANDLE_TLDS: &[&str] = &[
+    "alt",
+    "arpa",
+    "example",
+    "internal",
+    "invalid",
+    "local",
+    "localhost",
+    "onion",
+    "test",
+];
+
+pub(crate) fn is_valid_handle(handle: &str) -> Option<String> {
+    let trimmed = at_uri_authority(handle).to_lowercase();
+    let tld = trimmed.rsplit('.').next().unwrap_or_default();
+    if is_valid_hostname(&trimmed)
+        && trimmed.chars().any(|c| c == '.')
+        && !tld.starts_with(|c: char| c.is_ascii_digit())
+        && !DISALLOWED_HANDLE_TLDS.contains(&tld)
+    {
         Some(trimmed.to_string())
     } else {
         None
     }
 }
 
-fn is_valid_did_plc(input: &str) -> Option<String> {
-    let trimmed = {
-        if let Some(value) = input.strip_prefix("at://") {
-            value
-        } else {
-            input
+/// A deliberately loose email check: a single `@` between a non-empty local
+/// part and a domain with at least one dot. The PDS does the real validation.
+pub(crate) fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !local.chars().any(|c| c.is_whitespace() || c == '@')
+                && domain.contains('.')
+                && is_valid_hostname(domain)
         }
-    };
-    if trimmed.starts_with("did:plc:") {
+        None => false,
+    }
+}
+
+pub(crate) fn is_valid_did_plc(input: &str) -> Option<String> {
+    let trimmed = at_uri_authority(input);
+    if trimmed.starts_with("did:plc:") && trimmed.len() > "did:plc:".len() {
         Some(trimmed.to_string())
     } else {
         None
@@ -84,6 +295,86 @@ fn is_valid_did_plc(input: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn jwk_files() {
+        let dir = std::env::temp_dir().join(format!("tandem-jwk-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (secret_jwk, encoded_public_key) =
+            crate::crypto::p256::gen_key(KeyFormat::Jwk).unwrap();
+        let valid = dir.join("valid.jwk");
+        std::fs::write(&valid, format!("{}\n", secret_jwk)).unwrap();
+        let jwk = read_jwk_file(&valid).unwrap();
+        assert_eq!(
+            crate::crypto::jwk_to_did_key(&jwk).unwrap(),
+            encoded_public_key
+        );
+
+        let malformed = dir.join("malformed.jwk");
+        std::fs::write(&malformed, "{\"kty\": \"EC\"").unwrap();
+        let err = format!("{:#}", read_jwk_file(&malformed).unwrap_err());
+        assert!(err.contains("failed to parse JWK"), "{}", err);
+
+        let missing = dir.join("missing.jwk");
+        let err = format!("{:#}", read_jwk_file(&missing).unwrap_err());
+        assert!(err.contains("failed to read JWK file"), "{}", err);
+
+        let encrypted = dir.join("encrypted.jwk");
+        std::fs::write(
+            &encrypted,
+            crate::crypto::encrypt_secret_with_iterations(&secret_jwk, "correct horse", 1_000)
+                .unwrap(),
+        )
+        .unwrap();
+        let theme = ColorfulTheme::default();
+        let jwk = get_encrypted_jwk_input(&theme, &encrypted, Some("correct horse")).unwrap();
+        assert_eq!(
+            crate::crypto::jwk_to_did_key(&jwk).unwrap(),
+            encoded_public_key
+        );
+        let err = format!(
+            "{:#}",
+            get_encrypted_jwk_input(&theme, &encrypted, Some("battery staple")).unwrap_err()
+        );
+        assert!(err.contains("wrong passphrase"), "{}", err);
+
+        let (secret_pem, encoded_public_key) =
+            crate::crypto::k256::gen_key(KeyFormat::Pkcs8Pem).unwrap();
+        let encrypted_pem = dir.join("encrypted.pem");
+        std::fs::write(
+            &encrypted_pem,
+            crate::crypto::encrypt_secret_with_iterations(&secret_pem, "correct horse", 1_000)
+                .unwrap(),
+        )
+        .unwrap();
+        let jwk = get_encrypted_jwk_input(&theme, &encrypted_pem, Some("correct horse")).unwrap();
+        assert_eq!(
+            crate::crypto::jwk_to_did_key(&jwk).unwrap(),
+            encoded_public_key
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn emails() {
+        for email in ["alice@example.com", "alice+tandem@mail.example.co.uk"] {
+            assert!(is_valid_email(email), "{}", email);
+        }
+        for email in [
+            "",
+            "alice",
+            "@example.com",
+            "alice@",
+            "alice@localhost",
+            "alice@@example.com",
+            "alice smith@example.com",
+            "alice@exa mple.com",
+        ] {
+            assert!(!is_valid_email(email), "{}", email);
+        }
+    }
+
     #[test]
     fn valid_hostnames() {
         for hostname in &[
@@ -124,4 +415,138 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn valid_handles() {
+        for handle in &[
+            "alice.example.com",
+            "at://alice.example.com",
+            "@Alice.Example.Com",
+            "xn--ls8h.test-domain.org",
+            "123.example.com",
+            "alice.com2",
+        ] {
+            assert!(
+                is_valid_handle(handle).is_some(),
+                "{} is not a valid handle",
+                handle
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_handles() {
+        for handle in &[
+            "alice",
+            "alice.test",
+            "alice.example",
+            "alice.invalid",
+            "alice.onion",
+            "alice.alt",
+            "alice.local",
+            "alice.localhost",
+            "alice.internal",
+            "1.0.0.127.in-addr.arpa",
+            "alice.123",
+            "alice.1com",
+            "alice..com",
+        ] {
+            assert!(
+                is_valid_handle(handle).is_none(),
+                "{} should not be a valid handle",
+                handle
+            );
+        }
+    }
+
+    #[test]
+    fn at_uri_inputs() {
+        for (input, expected) in [
+            ("at://alice.example.com", "alice.example.com"),
+            ("at://Alice.Example.com/", "alice.example.com"),
+            (
+                "at://alice.example.com/app.bsky.feed.post/3k2a",
+                "alice.example.com",
+            ),
+            ("at://alice.example.com#profile", "alice.example.com"),
+            ("@alice.example.com", "alice.example.com"),
+            (" alice.example.com ", "alice.example.com"),
+        ] {
+            assert_eq!(
+                is_valid_handle(input).as_deref(),
+                Some(expected),
+                "{}",
+                input
+            );
+        }
+
+        for (input, expected) in [
+            (
+                "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            ),
+            (
+                "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/3k2a",
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            ),
+            (
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            ),
+        ] {
+            assert_eq!(
+                is_valid_did_plc(input).as_deref(),
+                Some(expected),
+                "{}",
+                input
+            );
+        }
+
+        assert!(
+            is_valid_handle("at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/3k2a")
+                .is_none()
+        );
+        assert!(is_valid_did_plc("at://alice.example.com/app.bsky.feed.post/3k2a").is_none());
+        assert!(is_valid_did_plc("at:///app.bsky.feed.post/3k2a").is_none());
+    }
+
+    #[tokio::test]
+    async fn token_file_written_later() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tandem-token-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let writer_path = path.clone();
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            std::fs::write(writer_path, "ABCDE-12345\n")
+        });
+
+        let token = wait_for_token_file(
+            &path,
+            SystemTime::now() - Duration::from_secs(1),
+            Duration::from_secs(5),
+            Duration::from_millis(10),
+        )
+        .await?;
+        assert_eq!(token, "ABCDE-12345");
+
+        writer.await??;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token_file_timeout() {
+        let path = std::env::temp_dir().join("tandem-token-never-written");
+
+        let err = wait_for_token_file(
+            &path,
+            SystemTime::now(),
+            Duration::from_millis(30),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
 }
@@ -0,0 +1,48 @@
+use std::sync::Mutex;
+
+/// What an interrupted action leaves behind that the user should know about.
+#[derive(Clone, Default)]
+pub struct InterruptReminders {
+    /// The `did:key` of a private key that was handed to the user but not yet
+    /// confirmed as installed.
+    pub generated_key: Option<String>,
+
+    /// The DID a PLC operation signature was requested for but not yet used.
+    pub pending_signature_request: Option<String>,
+}
+
+static REMINDERS: Mutex<InterruptReminders> = Mutex::new(InterruptReminders {
+    generated_key: None,
+    pending_signature_request: None,
+});
+
+fn update(f: impl FnOnce(&mut InterruptReminders)) {
+    let mut reminders = REMINDERS.lock().unwrap_or_else(|err| err.into_inner());
+    f(&mut reminders);
+}
+
+pub(crate) fn note_generated_key(did_key: &str) {
+    update(|reminders| reminders.generated_key = Some(did_key.to_string()));
+}
+
+pub(crate) fn note_pending_signature_request(did: &str) {
+    update(|reminders| reminders.pending_signature_request = Some(did.to_string()));
+}
+
+/// Clears reminders once the action reaches a point where nothing is left dangling.
+pub(crate) fn clear_reminders() {
+    update(|reminders| *reminders = InterruptReminders::default());
+}
+
+pub fn interrupt_reminders() -> InterruptReminders {
+    REMINDERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+/// Removes the DID locks held by the running action, for an interrupt that
+/// exits without unwinding.
+pub fn release_did_locks() {
+    crate::state::release_held_locks();
+}
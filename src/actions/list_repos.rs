@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::Input;
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{Options, TandemAction, Theme},
+    xrpc::PdsClient,
+};
+
+/// The page size requested from `listRepos`, which servers cap at 1000.
+const LIST_REPOS_PAGE_SIZE: u32 = 500;
+
+pub(crate) struct ActionListRepos<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    options: Options,
+}
+
+impl<'a> ActionListRepos<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionListRepos<'_> {
+    fn uses_plc_directory(&self) -> bool {
+        false
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'List Repos' action lists every repository hosted on a PDS, with its DID, head commit, and revision."),
+        );
+
+        let pds = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("What is the hostname or URL of the PDS?")
+            .interact()?;
+
+        let pds_client = PdsClient::unauthenticated(&self.http_client, &pds)?;
+
+        let started = Instant::now();
+        let mut cursor = None;
+        let mut count = 0;
+        loop {
+            let (repos, next_cursor) = pds_client
+                .list_repos(cursor, LIST_REPOS_PAGE_SIZE)
+                .await
+                .context("failed to list repos")?;
+            for repo in &repos {
+                let status = match (repo.active, &repo.status) {
+                    (_, Some(status)) => format!(" ({})", status),
+                    (Some(false), None) => " (inactive)".to_string(),
+                    _ => String::new(),
+                };
+                self.theme.println(self.theme.white_dim.apply_to(format!(
+                    "{} head {} rev {}{}",
+                    repo.did, repo.head, repo.rev, status
+                )));
+                self.theme.event(
+                    "repo",
+                    json!({
+                        "did": repo.did,
+                        "head": repo.head,
+                        "rev": repo.rev,
+                        "active": repo.active,
+                        "status": repo.status,
+                    }),
+                );
+            }
+            count += repos.len();
+
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Listed {} repos on {}{}",
+            count,
+            pds_client.pds,
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "listed_repos",
+            json!({ "count": count, "pds": pds_client.pds }),
+        );
+
+        Ok(())
+    }
+}
@@ -1,46 +1,300 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use chrono::Utc;
+use dialoguer::{Confirm, Input, MultiSelect, Password};
+use serde_json::json;
+use std::time::Instant;
 
-use crate::actions::{TandemAction, Theme};
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::get_signer_input,
+        interrupt::clear_reminders,
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{build_migrate, check_signer_is_rotation_key, last_operation, sign, submit},
+    plc::curl_command,
+    state::{DidLock, MigrationProgress, MigrationProgressStore, MigrationStep},
+    xrpc::{pds_base_url, PdsClient},
+};
 
 pub(crate) struct ActionMigrate<'a> {
     theme: &'a Theme<'a>,
-    #[allow(dead_code)]
     http_client: reqwest::Client,
-    #[allow(dead_code)]
     plc: String,
+    options: Options,
 }
 
 impl<'a> ActionMigrate<'a> {
-    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
         Self {
             theme,
             http_client: http_client.clone(),
             plc: plc.to_string(),
+            options: options.clone(),
         }
     }
+
+    /// Returns saved progress for the DID if the user chooses to resume it,
+    /// clearing it otherwise.
+    fn resume(
+        &self,
+        store: &MigrationProgressStore,
+        did: &str,
+    ) -> Result<Option<MigrationProgress>> {
+        let Some(progress) = store.get(did)? else {
+            return Ok(None);
+        };
+
+        self.theme.println(self.theme.yellow_bold.apply_to(format!(
+            "A migration of {} to {} stopped after {:?} at {}.",
+            did, progress.destination_pds, progress.step, progress.updated_at
+        )));
+        if Confirm::with_theme(self.theme.colorful_theme)
+            .with_prompt("Resume it?")
+            .default(true)
+            .interact()?
+        {
+            Ok(Some(progress))
+        } else {
+            store.clear(did)?;
+            Ok(None)
+        }
+    }
+
+    fn print_json(&self, message: &str, value: &serde_json::Value) -> Result<()> {
+        self.theme.println(self.theme.green.apply_to(message));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(value).context("failed to serialize DID document")?,
+        ));
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl TandemAction for ActionMigrate<'_> {
     async fn run(&self) -> Result<()> {
-        println!(
-        "{}",
+        self.theme.println(
             self.theme
                 .white_dim
-                .apply_to("The 'Migrate' action performs a migration of your DID-PLC identity to a different PDS.")
+                .apply_to("The 'Migrate' action performs a migration of your DID-PLC identity to a different PDS. The destination PDS must already have an account for your DID. This action requires your tandem private key to authorize the change."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let store = MigrationProgressStore::open()?;
+        let resumed = self.resume(&store, &did)?;
+
+        let destination_pds = match &resumed {
+            Some(progress) => progress.destination_pds.clone(),
+            None => pds_base_url(
+                &Input::<String>::with_theme(self.theme.colorful_theme)
+                    .with_prompt("What is the hostname or URL of the destination PDS?")
+                    .interact()?,
+            )?,
+        };
+        let mut progress = resumed.unwrap_or_else(|| MigrationProgress {
+            did: did.clone(),
+            destination_pds: destination_pds.clone(),
+            step: MigrationStep::CredentialsFetched,
+            updated_at: Utc::now(),
+            recommended_credentials: None,
+            prev: None,
+            operation: None,
+        });
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+
+        check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+
+        // A saved operation is only reusable while its prev is still the head.
+        if progress
+            .prev
+            .as_deref()
+            .is_some_and(|prev| prev != last.cid)
+        {
+            self.theme.println(self.theme.yellow_bold.apply_to(
+                "The DID has changed since the saved operation was built, so it will be rebuilt.",
+            ));
+            progress.prev = None;
+            progress.operation = None;
+            progress.step = MigrationStep::CredentialsFetched;
+        }
+
+        let recommended = match progress.recommended_credentials.clone() {
+            Some(recommended) => recommended,
+            None => {
+                let password = Password::with_theme(self.theme.colorful_theme)
+                    .with_prompt("Destination PDS password")
+                    .interact()?;
+
+                let started = Instant::now();
+                let pds_client = PdsClient::from_credentials(
+                    &self.http_client,
+                    &destination_pds,
+                    &did,
+                    &password,
+                )
+                .await
+                .context("Unable to create a session with the destination PDS.")?;
+                self.theme.println(self.theme.green.apply_to(format!(
+                    "✔ Created session with {}{}",
+                    destination_pds,
+                    self.options.elapsed(started)
+                )));
+                self.theme
+                    .event("authenticated", json!({ "pds": destination_pds }));
+
+                let recommended = pds_client.get_recommended_did_credentials().await?;
+                progress.recommended_credentials = Some(recommended.clone());
+                progress.step = MigrationStep::CredentialsFetched;
+                store.save(&progress)?;
+                recommended
+            }
+        };
+        self.print_json(
+            "✔ Retrieved recommended DID credentials",
+            &serde_json::to_value(&recommended)?,
+        )?;
+        self.theme.event(
+            "retrieved_recommended_credentials",
+            json!({ "credentials": recommended }),
         );
 
-        // TODO: User input DID
-        // TODO: User input jwk
-        // TODO: User input destination_pds
-        // TODO: User input destination_password
-        // TODO: Create session with destination_pds
-        // TODO: Get the PLC credentails from the destination PDS /xrpc/com.atproto.identity.getRecommendedDidCredentials
-        // TODO: Get last PLC operation for DID
-        // TODO: Create operation with updated PDS keys and service endpoints
-        // TODO: Sign operation with jwk
-        // TODO: Submit operation to PLC
+        let operation = match (progress.step, &progress.operation) {
+            (MigrationStep::OperationBuilt | MigrationStep::OperationSigned, Some(operation)) => {
+                operation.clone()
+            }
+            _ => {
+                warn_verification_method_drift(self.theme, &last.operation);
+                let rotation_keys: Vec<String> = last.operation["rotationKeys"]
+                    .as_array()
+                    .map(|keys| {
+                        keys.iter()
+                            .filter_map(|key| key.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let keep = MultiSelect::with_theme(self.theme.colorful_theme)
+                    .with_prompt("Select the existing rotation keys to keep (the source PDS's key should usually be removed)")
+                    .items(&rotation_keys)
+                    .defaults(&vec![true; rotation_keys.len()])
+                    .interact()?;
+                let kept_rotation_keys: Vec<String> = keep
+                    .into_iter()
+                    .map(|index| rotation_keys[index].clone())
+                    .collect();
+
+                let operation = build_migrate(&last, &recommended, &kept_rotation_keys)?;
+                progress.prev = Some(last.cid.clone());
+                progress.operation = Some(operation.clone());
+                progress.step = MigrationStep::OperationBuilt;
+                store.save(&progress)?;
+                operation
+            }
+        };
+
+        let signed_operation = if progress.step == MigrationStep::OperationSigned {
+            self.print_json("✔ Resumed signed operation", &operation)?;
+            self.theme
+                .event("signed_operation", json!({ "operation": operation }));
+            operation
+        } else {
+            self.print_json("✔ Prepared operation for signing", &operation)?;
+            self.theme
+                .event("prepared_operation", json!({ "operation": operation }));
+
+            let started = Instant::now();
+            let signed_operation = sign(signer.as_ref(), &operation)?;
+            self.print_json(
+                &format!("✔ Signed operation{}", self.options.elapsed(started)),
+                &signed_operation,
+            )?;
+            self.theme
+                .event("signed_operation", json!({ "operation": signed_operation }));
+            progress.operation = Some(signed_operation.clone());
+            progress.step = MigrationStep::OperationSigned;
+            store.save(&progress)?;
+            signed_operation
+        };
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        store.clear(&did)?;
+        clear_reminders();
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+        self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                "Your DID now points at {}. Activate your account there and deactivate it on the previous PDS to finish the move.",
+                destination_pds
+            ))
+        );
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
         Ok(())
     }
 }
@@ -0,0 +1,396 @@
+use anyhow::{anyhow, Result};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    actions::inputs::{is_valid_did_plc, is_valid_email, is_valid_handle},
+    rate_limit::{RateLimiter, DEFAULT_BATCH_RATE_LIMIT},
+    signer::Signer,
+};
+
+/// Where the DID state an operation is built on is read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OperationSource {
+    /// The PLC directory's current document.
+    #[default]
+    Plc,
+
+    /// The credentials the account's PDS recommends, checked against the directory.
+    Pds,
+}
+
+/// Settings supplied on the command line that adjust how actions run.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Operation CID to build on instead of the current head of the audit log.
+    pub prev: Option<String>,
+
+    /// Service that PDS requests are proxied to via the `atproto-proxy` header.
+    pub atproto_proxy: Option<String>,
+
+    /// Your own handle, used instead of prompting for it.
+    pub handle: Option<String>,
+
+    /// Handle for Append Handle to add instead of prompting for one.
+    pub new_handle: Option<String>,
+
+    /// Handle for Remove Handle to remove instead of prompting for one.
+    pub remove_handle: Option<String>,
+
+    /// Email to create accounts with instead of prompting for one.
+    pub email: Option<String>,
+
+    /// Read the email to create accounts with from standard input.
+    pub email_stdin: bool,
+
+    /// File polled for the emailed PLC confirmation code instead of prompting.
+    pub token_file: Option<PathBuf>,
+
+    /// Print a curl command that submits each locally signed operation.
+    pub emit_curl: bool,
+
+    /// Acknowledges that a tombstone permanently retires the identity.
+    pub confirm_permanent: bool,
+
+    /// Print how long each network-bound step took.
+    pub timings: bool,
+
+    /// Skip probing the PLC directory before running an action.
+    pub skip_plc_health_check: bool,
+
+    /// File the rotation JWK is loaded from instead of prompting.
+    pub jwk_file: Option<PathBuf>,
+
+    /// Signer used for operations instead of a JWK, for keys held elsewhere.
+    pub signer: Option<Arc<dyn Signer>>,
+
+    /// New file generated private keys are written to instead of printed.
+    pub key_out: Option<PathBuf>,
+
+    /// Encrypt generated private keys written to `key_out` with a passphrase.
+    pub encrypt_key: bool,
+
+    /// Additional multibase encoding to print generated public keys in.
+    pub key_encoding: Option<multibase::Base>,
+
+    /// Requests per second batch flows are limited to.
+    pub rate_limit: Option<u32>,
+
+    /// Where the DID state an operation is built on is read from.
+    pub operation_source: OperationSource,
+
+    /// Proceed even when another run appears to hold the DID's lock.
+    pub force: bool,
+
+    /// Where a receipt for each submitted operation, or for each operation
+    /// `dry_run` signed without submitting, is appended.
+    pub receipt: Option<PathBuf>,
+
+    /// Sign operations without submitting them, and print what a destructive
+    /// action would do instead of doing it.
+    pub dry_run: bool,
+
+    /// DID to act on instead of prompting for one.
+    pub did: Option<String>,
+
+    /// Action to run without the menu or the opening confirmation, named
+    /// as in the menu or by its slug, such as `append-handle`.
+    pub action: Option<String>,
+}
+
+impl Options {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut options = Options::default();
+
+        for arg in args {
+            if arg == "--emit-curl" {
+                options.emit_curl = true;
+            } else if arg == "--skip-plc-health-check" {
+                options.skip_plc_health_check = true;
+            } else if arg == "--email-stdin" {
+                options.email_stdin = true;
+            } else if arg == "--force" {
+                options.force = true;
+            } else if arg == "--encrypt-key" {
+                options.encrypt_key = true;
+            } else if arg == "--dry-run" {
+                options.dry_run = true;
+            } else if arg == "--timings" {
+                options.timings = true;
+            } else if arg == "--i-understand-this-is-permanent" {
+                options.confirm_permanent = true;
+            } else if let Some(key_encoding) = arg.strip_prefix("--key-encoding=") {
+                options.key_encoding = Some(parse_key_encoding(key_encoding)?);
+            } else if let Some(rate_limit) = arg.strip_prefix("--rate-limit=") {
+                options.rate_limit = Some(
+                    rate_limit
+                        .parse()
+                        .ok()
+                        .filter(|rate_limit| *rate_limit > 0)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "invalid --rate-limit value {}: expected a positive number of requests per second",
+                                rate_limit
+                            )
+                        })?,
+                );
+            } else if let Some(source) = arg.strip_prefix("--operation-source=") {
+                options.operation_source = match source {
+                    "plc" => OperationSource::Plc,
+                    "pds" => OperationSource::Pds,
+                    _ => {
+                        return Err(anyhow!(
+                            "invalid --operation-source value {}: expected plc or pds",
+                            source
+                        ))
+                    }
+                };
+            } else if let Some(prev) = arg.strip_prefix("--prev=") {
+                options.prev = Some(prev.to_string());
+            } else if let Some(email) = arg.strip_prefix("--email=") {
+                if !is_valid_email(email) {
+                    return Err(anyhow!(
+                        "invalid --email value {}: expected an address such as alice@example.com",
+                        email
+                    ));
+                }
+                options.email = Some(email.to_string());
+            } else if let Some(jwk_file) = arg.strip_prefix("--jwk-file=") {
+                options.jwk_file = Some(PathBuf::from(jwk_file));
+            } else if let Some(key_out) = arg.strip_prefix("--key-out=") {
+                options.key_out = Some(PathBuf::from(key_out));
+            } else if let Some(receipt) = arg.strip_prefix("--receipt=") {
+                options.receipt = Some(PathBuf::from(receipt));
+            } else if let Some(token_file) = arg.strip_prefix("--token-file=") {
+                options.token_file = Some(PathBuf::from(token_file));
+            } else if let Some(did) = arg.strip_prefix("--did=") {
+                options.did = Some(is_valid_did_plc(did).ok_or_else(|| {
+                    anyhow!(
+                        "invalid --did value {}: expected a DID such as did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                        did
+                    )
+                })?);
+            } else if let Some(action) = arg.strip_prefix("--action=") {
+                options.action = Some(action.to_string());
+            } else if let Some(handle) = arg.strip_prefix("--handle=") {
+                options.handle = Some(parse_handle("--handle", handle)?);
+            } else if let Some(handle) = arg.strip_prefix("--new-handle=") {
+                options.new_handle = Some(parse_handle("--new-handle", handle)?);
+            } else if let Some(handle) = arg.strip_prefix("--remove-handle=") {
+                options.remove_handle = Some(parse_handle("--remove-handle", handle)?);
+            } else if let Some(atproto_proxy) = arg.strip_prefix("--atproto-proxy=") {
+                match atproto_proxy.split_once('#') {
+                    Some((did, service)) if did.starts_with("did:") && !service.is_empty() => {
+                        options.atproto_proxy = Some(atproto_proxy.to_string());
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "invalid --atproto-proxy value {}: expected DID#service",
+                            atproto_proxy
+                        ))
+                    }
+                }
+            }
+        }
+
+        if options.encrypt_key && options.key_out.is_none() {
+            return Err(anyhow!("--encrypt-key requires --key-out=FILE"));
+        }
+
+        Ok(options)
+    }
+
+    /// Returns the time elapsed since `started` as a suffix for a `✔` line
+    /// when `--timings` is set, and an empty string otherwise.
+    pub(crate) fn elapsed(&self, started: Instant) -> String {
+        if self.timings {
+            format_elapsed(started.elapsed())
+        } else {
+            String::new()
+        }
+    }
+
+    /// Returns the limiter batch flows share for the whole run.
+    pub(crate) fn batch_rate_limiter(&self) -> RateLimiter {
+        RateLimiter::per_second(self.rate_limit.unwrap_or(DEFAULT_BATCH_RATE_LIMIT))
+    }
+}
+
+fn parse_handle(flag: &str, value: &str) -> Result<String> {
+    is_valid_handle(value).ok_or_else(|| {
+        anyhow!(
+            "invalid {} value {}: expected a domain name such as alice.example.com",
+            flag,
+            value
+        )
+    })
+}
+
+fn parse_key_encoding(value: &str) -> Result<multibase::Base> {
+    match value {
+        "base16" => Ok(multibase::Base::Base16Lower),
+        "base32" => Ok(multibase::Base::Base32Lower),
+        "base58btc" => Ok(multibase::Base::Base58Btc),
+        "base64url" => Ok(multibase::Base::Base64Url),
+        _ => Err(anyhow!(
+            "invalid --key-encoding value {}: expected base16, base32, base58btc, or base64url",
+            value
+        )),
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    format!(" ({}ms)", elapsed.as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn atproto_proxy_option() {
+        let options = Options::from_args(&args(&[
+            "--atproto-proxy=did:web:api.example.com#bsky_appview",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.atproto_proxy.as_deref(),
+            Some("did:web:api.example.com#bsky_appview")
+        );
+
+        assert!(Options::from_args(&args(&["--atproto-proxy=did:web:api.example.com"])).is_err());
+        assert!(Options::from_args(&args(&["--atproto-proxy=api.example.com#svc"])).is_err());
+        assert!(Options::from_args(&args(&[]))
+            .unwrap()
+            .atproto_proxy
+            .is_none());
+    }
+
+    #[test]
+    fn handle_option() {
+        let options = Options::from_args(&args(&["--handle=@Alice.Example.com"])).unwrap();
+        assert_eq!(options.handle.as_deref(), Some("alice.example.com"));
+
+        let err = Options::from_args(&args(&["--handle=alice"]))
+            .err()
+            .expect("invalid handle");
+        assert!(err.to_string().contains("invalid --handle value alice"));
+
+        let options = Options::from_args(&args(&[
+            "--handle=alice.example.com",
+            "--new-handle=alice.example.org",
+            "--remove-handle=alice.example.net",
+        ]))
+        .unwrap();
+        assert_eq!(options.handle.as_deref(), Some("alice.example.com"));
+        assert_eq!(options.new_handle.as_deref(), Some("alice.example.org"));
+        assert_eq!(options.remove_handle.as_deref(), Some("alice.example.net"));
+
+        let err = Options::from_args(&args(&["--remove-handle=alice"]))
+            .err()
+            .expect("invalid handle");
+        assert!(err
+            .to_string()
+            .contains("invalid --remove-handle value alice"));
+    }
+
+    #[test]
+    fn scripted_options() {
+        let options = Options::from_args(&args(&[
+            "--action=append-handle",
+            "--did=did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+        ]))
+        .unwrap();
+        assert_eq!(options.action.as_deref(), Some("append-handle"));
+        assert_eq!(
+            options.did.as_deref(),
+            Some("did:plc:cbkjy5n7bk3ax2wplmtjofq2")
+        );
+
+        let err = Options::from_args(&args(&["--did=did:web:example.com"]))
+            .err()
+            .expect("invalid DID");
+        assert!(err
+            .to_string()
+            .contains("invalid --did value did:web:example.com"));
+    }
+
+    #[test]
+    fn encrypt_key_option() {
+        let options =
+            Options::from_args(&args(&["--encrypt-key", "--key-out=rotation.jwk"])).unwrap();
+        assert!(options.encrypt_key);
+        assert!(Options::from_args(&args(&["--encrypt-key"])).is_err());
+    }
+
+    #[test]
+    fn timings_option() {
+        assert_eq!(format_elapsed(Duration::from_millis(412)), " (412ms)");
+
+        let options = Options::from_args(&args(&[])).unwrap();
+        assert_eq!(options.elapsed(Instant::now()), "");
+
+        let options = Options::from_args(&args(&["--timings"])).unwrap();
+        assert!(options.timings);
+        assert!(options.elapsed(Instant::now()).ends_with("ms)"));
+    }
+
+    #[test]
+    fn key_encoding_option() {
+        assert_eq!(Options::from_args(&args(&[])).unwrap().key_encoding, None);
+        assert_eq!(
+            Options::from_args(&args(&["--key-encoding=base64url"]))
+                .unwrap()
+                .key_encoding,
+            Some(multibase::Base::Base64Url)
+        );
+        assert!(Options::from_args(&args(&["--key-encoding=base2"])).is_err());
+    }
+
+    #[test]
+    fn email_option() {
+        let options = Options::from_args(&args(&["--email=alice@example.com"])).unwrap();
+        assert_eq!(options.email.as_deref(), Some("alice@example.com"));
+
+        let err = Options::from_args(&args(&["--email=alice"]))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("invalid --email value alice"), "{}", err);
+    }
+
+    #[test]
+    fn rate_limit_option() {
+        assert_eq!(Options::from_args(&args(&[])).unwrap().rate_limit, None);
+        assert_eq!(
+            Options::from_args(&args(&["--rate-limit=2"]))
+                .unwrap()
+                .rate_limit,
+            Some(2)
+        );
+        assert!(Options::from_args(&args(&["--rate-limit=0"])).is_err());
+        assert!(Options::from_args(&args(&["--rate-limit=fast"])).is_err());
+    }
+
+    #[test]
+    fn operation_source_option() {
+        assert_eq!(
+            Options::from_args(&args(&[])).unwrap().operation_source,
+            OperationSource::Plc
+        );
+        assert_eq!(
+            Options::from_args(&args(&["--operation-source=pds"]))
+                .unwrap()
+                .operation_source,
+            OperationSource::Pds
+        );
+        assert!(Options::from_args(&args(&["--operation-source=dns"])).is_err());
+    }
+}
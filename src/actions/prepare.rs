@@ -1,28 +1,50 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use dialoguer::{Input, Password, Select};
+use chrono::Utc;
+use dialoguer::{console::user_attended, Confirm, Input, Password, Select};
 use json_patch::{patch, Patch};
 use serde_json::{from_value, json};
+use std::time::{Duration, Instant};
 
 use crate::{
-    actions::{get_handle_input, TandemAction, Theme},
-    plc::did_plc_data,
+    actions::{
+        generated_key::output_generated_key,
+        get_handle_input, get_key_format_input,
+        inputs::wait_for_token_file,
+        interrupt::{clear_reminders, note_pending_signature_request},
+        verify::{offer_head_verification, stop_before_submission, write_receipt},
+        OperationSource, Options, TandemAction, Theme,
+    },
+    crypto::check_generated_key,
+    multikey::Curve,
+    plc::{
+        check_rotation_key_capacity, did_plc_data, did_state_disagreements, document_pds_endpoint,
+        ensure_did_exists, validate_did_state,
+    },
     resolve::resolve_handle,
-    xrpc::PdsClient,
+    state::{DidLock, PendingSignatureRequest, PendingSignatureRequests},
+    xrpc::{is_expired_token_error, PdsClient},
 };
 
 pub(crate) struct ActionPrepare<'a> {
     theme: &'a Theme<'a>,
     http_client: reqwest::Client,
     plc: String,
+    options: Options,
 }
 
 impl<'a> ActionPrepare<'a> {
-    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
         Self {
             theme,
             http_client: http_client.clone(),
             plc: plc.to_string(),
+            options: options.clone(),
         }
     }
 }
@@ -30,20 +52,21 @@ impl<'a> ActionPrepare<'a> {
 #[async_trait]
 impl TandemAction for ActionPrepare<'_> {
     async fn run(&self) -> Result<()> {
-        println!(
-        "{}",
+        self.theme.println(
             self.theme
                 .white_dim
-                .apply_to("The 'Install Tandem Key' action will generate a rotation key and update your DID-PLC document with it.")
+                .apply_to("The 'Install Tandem Key' action will generate a rotation key and update your DID-PLC document with it."),
         );
 
-        let handle = get_handle_input(self.theme.colorful_theme, "What is your handle?")?;
+        let handle = match &self.options.handle {
+            Some(handle) => handle.clone(),
+            None => get_handle_input(self.theme.colorful_theme, "What is your handle?")?,
+        };
 
-        println!(
-            "{}",
+        self.theme.println(
             self.theme
                 .yellow_bold
-                .apply_to("Your password is required to authenticate with your PDS. This cannot be an application password or OAuth token.")
+                .apply_to("Your password is required to authenticate with your PDS. This cannot be an application password or OAuth token."),
         );
 
         let password = Password::with_theme(self.theme.colorful_theme)
@@ -58,6 +81,8 @@ impl TandemAction for ActionPrepare<'_> {
             .items(&key_types[..])
             .interact()?;
 
+        let key_format = get_key_format_input(self.theme.colorful_theme)?;
+
         let key_positions = &["first", "last"];
 
         let key_position = Select::with_theme(self.theme.colorful_theme)
@@ -66,20 +91,64 @@ impl TandemAction for ActionPrepare<'_> {
             .items(&key_positions[..])
             .interact()?;
 
+        let started = Instant::now();
         let resolved_handle = resolve_handle(&self.http_client, &self.plc, &handle)
             .await
             .context("failed to resolve handle")?;
 
-        println!(
-            "{}",
-            self.theme.green.apply_to(format!(
-                "✔ Resolved {} ({}) known as {}",
-                resolved_handle.did,
-                resolved_handle.pds,
-                resolved_handle.handles.join(" ")
-            )),
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Resolved {} ({}) known as {}{}",
+            resolved_handle.did,
+            resolved_handle.pds,
+            resolved_handle.handles.join(" "),
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "resolved_handle",
+            json!({
+                "did": resolved_handle.did,
+                "pds": resolved_handle.pds,
+                "handles": resolved_handle.handles,
+                "unverified_handles": resolved_handle.unverified_handles,
+            }),
         );
+        if !resolved_handle.unverified_handles.is_empty() {
+            self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                "Warning! These handles do not resolve back to {}: {}",
+                resolved_handle.did,
+                resolved_handle.unverified_handles.join(" ")
+            )));
+        }
+
+        ensure_did_exists(&self.http_client, &self.plc, &resolved_handle.did).await?;
+        let _lock = DidLock::acquire(&resolved_handle.did, self.options.force)?;
+
+        let pending_requests = PendingSignatureRequests::open()?;
+        let reused_pending_request = match pending_requests.get(&resolved_handle.did)? {
+            Some(pending) => {
+                self.theme.println(self.theme.yellow_bold.apply_to(format!(
+                        "A PLC signature request for {} was sent at {}. Its confirmation code is valid until {}.",
+                        pending.did,
+                        pending.requested_at.to_rfc3339(),
+                        pending.expires_at().to_rfc3339()
+                    ))
+                );
+
+                let pending_choices = &[
+                    "reuse the pending confirmation code",
+                    "request a new confirmation code",
+                ];
+                let pending_choice = Select::with_theme(self.theme.colorful_theme)
+                    .with_prompt("Pending PLC signature request")
+                    .default(0)
+                    .items(&pending_choices[..])
+                    .interact()?;
+                (pending_choice == 0).then_some(pending)
+            }
+            None => None,
+        };
 
+        let started = Instant::now();
         let pds_client = PdsClient::from_credentials(
             &self.http_client,
             &resolved_handle.pds,
@@ -88,24 +157,37 @@ impl TandemAction for ActionPrepare<'_> {
         )
         .await
         .context("failed to authenticate against PDS")?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Authenticated with PDS{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("authenticated", json!({ "pds": resolved_handle.pds }));
+        let mut pds_client = match &self.options.atproto_proxy {
+            Some(atproto_proxy) => pds_client.with_atproto_proxy(atproto_proxy)?,
+            None => pds_client,
+        };
 
-        let (secret_pem, encoded_public_key) = if key_type == 0 {
-            crate::crypto::p256::gen_key()?
+        let mut did_doc_data = self
+            .last_did_state(&pds_client, &resolved_handle.did)
+            .await?;
+        check_rotation_key_capacity(&did_doc_data, 1)?;
+
+        let (curve, (secret_pem, encoded_public_key)) = if key_type == 0 {
+            (Curve::P256, crate::crypto::p256::gen_key(key_format)?)
         } else {
-            crate::crypto::k256::gen_key()?
+            (Curve::K256, crate::crypto::k256::gen_key(key_format)?)
         };
+        check_generated_key(curve, &secret_pem, key_format, &encoded_public_key)
+            .context("generated key failed its consistency check; nothing was changed")?;
 
-        println!(
-            "{}",
-            self.theme
-                .yellow_bold
-                .apply_to("Important! Securely store the following private key."),
-        );
-        println!("{}", self.theme.red_bold.apply_to(&secret_pem));
-
-        let mut did_doc_data = did_plc_data(&self.http_client, &self.plc, &resolved_handle.did)
-            .await
-            .context("failed to get DID document")?;
+        output_generated_key(
+            self.theme,
+            &self.options,
+            &secret_pem,
+            &encoded_public_key,
+            "Important! Securely store the following private key.",
+        )?;
 
         let key_path = if key_position == 0 {
             "/rotationKeys/0".to_string()
@@ -119,62 +201,238 @@ impl TandemAction for ActionPrepare<'_> {
         .context("failed to create patch")?;
 
         patch(&mut did_doc_data, &did_doc_data_patch).context("failed to apply patch")?;
+        validate_did_state(&did_doc_data)?;
 
-        println!("{}", self.theme.green.apply_to("✔ Created patch document"));
-        println!(
-            "{}",
+        self.theme
+            .println(self.theme.green.apply_to("✔ Created patch document"));
+        self.theme
+            .event("created_patch", json!({ "document": did_doc_data }));
+        self.theme.println(
             self.theme.white_dim.apply_to(
                 serde_json::to_string_pretty(&did_doc_data)
-                    .context("failed to serialize DID document")?
-            )
+                    .context("failed to serialize DID document")?,
+            ),
         );
 
-        pds_client
-            .request_plc_op_sig()
-            .await
-            .context("failed to request PLC signing operation")?;
-
-        println!(
-            "{}",
-            self.theme.yellow_bold.apply_to(
-                "Important! Check your email for a confirmation code. Enter it below to continue."
-            )
-        );
+        let mut pending_request = match reused_pending_request {
+            Some(pending_request) => {
+                self.theme.println(self.theme.yellow_bold.apply_to(
+                    "Enter the confirmation code from the pending request below to continue.",
+                ));
+                pending_request
+            }
+            None => {
+                self.request_signature(&pds_client, &pending_requests, &resolved_handle.did)
+                    .await?
+            }
+        };
 
-        let token = Input::<String>::with_theme(self.theme.colorful_theme)
-            .with_prompt("Confirmation code")
-            .interact()
-            .context("failed to get confirmation code")?;
+        let (plc_operation, started) = loop {
+            let token = self.confirmation_code(&pending_request).await?;
 
-        let plc_operation = pds_client
-            .sign_plc_op(&did_doc_data, &token)
-            .await
-            .context("failed to request PLC signing operation")?;
+            let started = Instant::now();
+            match pds_client.sign_plc_op(&did_doc_data, &token).await {
+                Ok(plc_operation) => break (plc_operation, started),
+                Err(err) if is_expired_token_error(&err) => {
+                    self.theme.println(
+                        self.theme
+                            .yellow_bold
+                            .apply_to("The confirmation code has expired."),
+                    );
+                    pending_requests.clear(&resolved_handle.did)?;
+                    if !user_attended()
+                        || !Confirm::with_theme(self.theme.colorful_theme)
+                            .with_prompt("Request a new confirmation code?")
+                            .default(true)
+                            .show_default(true)
+                            .wait_for_newline(true)
+                            .interact()?
+                    {
+                        return Err(err).context("failed to request PLC signing operation");
+                    }
+                    pending_request = self
+                        .request_signature(&pds_client, &pending_requests, &resolved_handle.did)
+                        .await?;
+                }
+                Err(err) => return Err(err).context("failed to request PLC signing operation"),
+            }
+        };
 
-        println!(
-            "{}",
-            self.theme.green.apply_to("✔ Acquired signed PLC operation")
-        );
-        println!(
-            "{}",
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Acquired signed PLC operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": plc_operation }));
+        self.theme.println(
             self.theme.white_dim.apply_to(
                 serde_json::to_string_pretty(&plc_operation)
-                    .context("failed to serialize PLC operation")?
-            )
+                    .context("failed to serialize PLC operation")?,
+            ),
         );
 
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &resolved_handle.did,
+            &plc_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
         pds_client
             .submit_plc_op(&plc_operation)
             .await
             .context("failed to submit PLC operation")?;
+        pending_requests.clear(&resolved_handle.did)?;
+        clear_reminders();
 
-        println!(
-            "{}",
-            self.theme
-                .green
-                .apply_to("✔ Submitted signed PLC operation")
-        );
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Submitted signed PLC operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("submitted", json!({ "did": resolved_handle.did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &resolved_handle.did,
+            &plc_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &resolved_handle.did,
+            &plc_operation,
+        )?;
 
         Ok(())
     }
 }
+
+impl ActionPrepare<'_> {
+    /// Asks the PDS to email a PLC operation confirmation code and records
+    /// the request so a later run can reuse the code.
+    async fn request_signature(
+        &self,
+        pds_client: &PdsClient,
+        pending_requests: &PendingSignatureRequests,
+        did: &str,
+    ) -> Result<PendingSignatureRequest> {
+        pds_client
+            .request_plc_op_sig()
+            .await
+            .context("failed to request PLC signing operation")?;
+        let pending_request = pending_requests.record(did)?;
+        note_pending_signature_request(did);
+
+        self.theme.println(self.theme.yellow_bold.apply_to(
+            "Important! Check your email for a confirmation code. Enter it below to continue.",
+        ));
+        Ok(pending_request)
+    }
+
+    /// Reads the confirmation code for `pending_request` from `--token-file` or
+    /// the terminal, after showing how long the code remains valid.
+    async fn confirmation_code(&self, pending_request: &PendingSignatureRequest) -> Result<String> {
+        let remaining = pending_request.expires_at() - Utc::now();
+        self.theme.println(self.theme.white_dim.apply_to(format!(
+            "The confirmation code is valid until {} (about {} minutes from now).",
+            pending_request.expires_at().to_rfc3339(),
+            remaining.num_minutes().max(0)
+        )));
+
+        match &self.options.token_file {
+            Some(token_file) => {
+                self.theme.println(self.theme.white_dim.apply_to(format!(
+                    "Waiting for the confirmation code to be written to {}",
+                    token_file.display()
+                )));
+                wait_for_token_file(
+                    token_file,
+                    pending_request.requested_at.into(),
+                    remaining.to_std().unwrap_or_default(),
+                    Duration::from_secs(1),
+                )
+                .await
+            }
+            None => Input::<String>::with_theme(self.theme.colorful_theme)
+                .with_prompt("Confirmation code")
+                .interact()
+                .context("failed to get confirmation code"),
+        }
+    }
+
+    /// Returns the DID state to build the operation on, read from the source
+    /// chosen with `--operation-source`. When the PDS is the source, its
+    /// recommended credentials and DID document are compared with the
+    /// directory and each disagreement is printed as a warning.
+    async fn last_did_state(&self, pds_client: &PdsClient, did: &str) -> Result<serde_json::Value> {
+        let directory_state = did_plc_data(&self.http_client, &self.plc, did)
+            .await
+            .context("failed to get DID document")?;
+        if self.options.operation_source == OperationSource::Plc {
+            return Ok(directory_state);
+        }
+
+        let started = Instant::now();
+        let pds_state = pds_client
+            .get_recommended_did_credentials()
+            .await
+            .context("failed to get recommended DID credentials from the PDS")?;
+        let pds_state = serde_json::to_value(&pds_state)?;
+        let pds_did_doc = pds_client.get_session_did_document().await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retrieved recommended DID credentials from PDS{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_recommended_credentials",
+            json!({ "credentials": pds_state }),
+        );
+
+        let mut disagreements = did_state_disagreements(&directory_state, &pds_state);
+        if let Some(pds_did_doc) = &pds_did_doc {
+            if pds_did_doc["id"].as_str() != Some(did) {
+                disagreements.push(format!(
+                    "the PDS's DID document is for {}",
+                    pds_did_doc["id"]
+                ));
+            }
+            let directory_endpoint = document_pds_endpoint(&directory_state);
+            let pds_endpoint = document_pds_endpoint(pds_did_doc);
+            if directory_endpoint != pds_endpoint {
+                disagreements.push(format!(
+                    "PDS endpoint differs: directory has {}, PDS's DID document has {}",
+                    directory_endpoint.unwrap_or("none"),
+                    pds_endpoint.unwrap_or("none")
+                ));
+            }
+        }
+
+        if disagreements.is_empty() {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ PDS and PLC directory agree on the DID state"),
+            );
+        } else {
+            self.theme.println(self.theme.yellow_bold.apply_to(
+                    "Warning! The PDS and PLC directory disagree. The operation will be built on the PDS's view."
+                )
+            );
+            for disagreement in &disagreements {
+                self.theme
+                    .println(format!("  {}", self.theme.white_dim.apply_to(disagreement)));
+            }
+        }
+
+        Ok(pds_state)
+    }
+}
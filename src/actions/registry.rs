@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+
+use super::{get_action, Options, TandemAction, Theme, SUPPORTED_ACTIONS};
+
+/// Builds an action from the shared theme, HTTP client, PLC hostname, and
+/// command line options.
+pub type ActionConstructor = Box<
+    dyn for<'a> Fn(
+            &'a Theme<'a>,
+            &reqwest::Client,
+            &str,
+            &Options,
+        ) -> Result<Box<dyn TandemAction + 'a>>
+        + Send
+        + Sync,
+>;
+
+/// The named actions offered in the menu, in the order they are listed.
+/// Starts with the built-in actions; crates embedding tandem can register
+/// their own.
+pub struct ActionRegistry {
+    actions: Vec<(String, ActionConstructor)>,
+}
+
+impl Default for ActionRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        for (index, name) in SUPPORTED_ACTIONS.iter().enumerate() {
+            registry.register(*name, move |theme, http_client, plc, options| {
+                get_action(index, theme, http_client, plc, options)
+            });
+        }
+        registry
+    }
+}
+
+impl ActionRegistry {
+    /// Creates a registry with the built-in actions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry without any actions.
+    pub fn empty() -> Self {
+        Self {
+            actions: Vec::new(),
+        }
+    }
+
+    /// Adds an action to the end of the menu, replacing any action already
+    /// registered under the same name in place.
+    pub fn register<F>(&mut self, name: impl Into<String>, constructor: F)
+    where
+        F: for<'a> Fn(
+                &'a Theme<'a>,
+                &reqwest::Client,
+                &str,
+                &Options,
+            ) -> Result<Box<dyn TandemAction + 'a>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let name = name.into();
+        let constructor: ActionConstructor = Box::new(constructor);
+        match self
+            .actions
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some(entry) => entry.1 = constructor,
+            None => self.actions.push((name, constructor)),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.actions.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Builds the action at `index` in the menu order returned by `names`.
+    pub fn build<'a>(
+        &self,
+        index: usize,
+        theme: &'a Theme<'a>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Result<Box<dyn TandemAction + 'a>> {
+        let (_, constructor) = self
+            .actions
+            .get(index)
+            .ok_or_else(|| anyhow!("Unsupported operation"))?;
+        constructor(theme, http_client, plc, options)
+    }
+
+    /// Builds the action registered under `name` or its `action_slug`.
+    pub fn build_named<'a>(
+        &self,
+        name: &str,
+        theme: &'a Theme<'a>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Result<Box<dyn TandemAction + 'a>> {
+        let index = self
+            .actions
+            .iter()
+            .position(|(existing, _)| existing == name || action_slug(existing) == name)
+            .ok_or_else(|| anyhow!("Unsupported operation {}", name))?;
+        self.build(index, theme, http_client, plc, options)
+    }
+}
+
+/// The command line name of an action: its menu name in lowercase, with
+/// spaces replaced by dashes.
+pub fn action_slug(name: &str) -> String {
+    name.trim().to_lowercase().replace(' ', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use dialoguer::{console::Style, theme::ColorfulTheme};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct ActionCustom {
+        ran: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl TandemAction for ActionCustom {
+        async fn run(&self) -> Result<()> {
+            self.ran.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn register_custom_action() -> Result<()> {
+        let mut registry = ActionRegistry::new();
+        assert_eq!(registry.names(), SUPPORTED_ACTIONS.to_vec());
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let custom_ran = ran.clone();
+        registry.register("Custom", move |_, _, _, _| {
+            Ok(Box::new(ActionCustom {
+                ran: custom_ran.clone(),
+            }) as Box<dyn TandemAction>)
+        });
+        assert_eq!(registry.names().last(), Some(&"Custom"));
+
+        let colorful_theme = ColorfulTheme::default();
+        let theme = Theme {
+            red_bold: Style::new(),
+            yellow_bold: Style::new(),
+            green: Style::new(),
+            white_dim: Style::new(),
+            colorful_theme: &colorful_theme,
+            json: false,
+        };
+        let action = registry.build_named(
+            "Custom",
+            &theme,
+            &reqwest::Client::new(),
+            "plc.example.com",
+            &Options::default(),
+        )?;
+        action.run().await?;
+        assert!(ran.load(Ordering::SeqCst));
+
+        assert_eq!(action_slug("Append Handle"), "append-handle");
+        ran.store(false, Ordering::SeqCst);
+        registry
+            .build_named(
+                "custom",
+                &theme,
+                &reqwest::Client::new(),
+                "plc.example.com",
+                &Options::default(),
+            )?
+            .run()
+            .await?;
+        assert!(ran.load(Ordering::SeqCst));
+
+        assert!(registry
+            .build(
+                registry.names().len(),
+                &theme,
+                &reqwest::Client::new(),
+                "plc.example.com",
+                &Options::default(),
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_actions_without_prompts() -> Result<()> {
+        let colorful_theme = ColorfulTheme::default();
+        let theme = Theme::plain(&colorful_theme);
+        let registry = ActionRegistry::new();
+        let needs_terminal = |name: &str, options: &Options| -> Result<bool> {
+            Ok(registry
+                .build_named(
+                    name,
+                    &theme,
+                    &reqwest::Client::new(),
+                    "plc.example.com",
+                    options,
+                )?
+                .needs_terminal())
+        };
+
+        let (secret_jwk, _) = crate::crypto::p256::gen_key(crate::crypto::KeyFormat::Jwk)?;
+        let mut options = Options::from_args(&[
+            "--did=did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+            "--new-handle=alice.example.org".to_string(),
+        ])?;
+        options.signer = None;
+        assert!(needs_terminal("set-handles", &options)?);
+
+        options.signer = Some(Arc::new(crate::signer::JwkSigner::new(
+            secret_jwk.parse()?,
+        )?));
+        assert!(!needs_terminal("append-handle", &options)?);
+        assert!(needs_terminal("remove-handle", &options)?);
+        assert!(needs_terminal("tombstone-identity", &options)?);
+        assert!(!needs_terminal("self-test", &options)?);
+
+        options.confirm_permanent = true;
+        assert!(!needs_terminal("tombstone-identity", &options)?);
+        Ok(())
+    }
+}
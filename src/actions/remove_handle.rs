@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use dialoguer::Select;
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::{get_signer_input, signer_input_prompts},
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{build_remove_handle, check_signer_is_rotation_key, last_operation, sign, submit},
+    plc::curl_command,
+    state::DidLock,
+};
+
+pub(crate) struct ActionRemoveHandle<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionRemoveHandle<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionRemoveHandle<'_> {
+    fn needs_terminal(&self) -> bool {
+        self.options.did.is_none()
+            || self.options.remove_handle.is_none()
+            || signer_input_prompts(&self.options)
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Remove Handle' action removes a handle from the 'alsoKnownAs' field in your DID-PLC document. This action requires your tandem private key."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+
+        let handles = last.operation["alsoKnownAs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| value.as_str())
+            .map(|value| value.strip_prefix("at://").unwrap_or(value).to_string())
+            .collect::<Vec<String>>();
+        if handles.is_empty() {
+            return Err(anyhow!("{} has no handles to remove", did));
+        }
+
+        let handle = match &self.options.remove_handle {
+            Some(handle) => handle.clone(),
+            None => {
+                let index = Select::with_theme(self.theme.colorful_theme)
+                    .with_prompt("Select the handle to remove")
+                    .items(&handles)
+                    .interact()?;
+                handles[index].clone()
+            }
+        };
+
+        check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+        warn_verification_method_drift(self.theme, &last.operation);
+        let operation = build_remove_handle(&last, &handle)?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use dialoguer::{Confirm, Select};
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::get_signer_input,
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{
+        build_remove_rotation_key, check_signer_is_rotation_key, last_operation, sign, submit,
+    },
+    plc::curl_command,
+    state::DidLock,
+};
+
+pub(crate) struct ActionRotateKey<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionRotateKey<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionRotateKey<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Rotate Key' action removes a rotation key you no longer control, such as a compromised one, from your DID-PLC document. This action requires a rotation key you still hold."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+
+        let signer_position = check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+        let rotation_keys = last.operation["rotationKeys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|key| key.as_str())
+            .collect::<Vec<&str>>();
+        let items = rotation_keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| {
+                if index == signer_position {
+                    format!("{}: {} (signing key)", index, key)
+                } else {
+                    format!("{}: {}", index, key)
+                }
+            })
+            .collect::<Vec<String>>();
+
+        let index = Select::with_theme(self.theme.colorful_theme)
+            .with_prompt("Select the rotation key to remove")
+            .items(&items)
+            .interact()?;
+
+        if index == signer_position {
+            self.theme.println(self.theme.red_bold.apply_to(
+                "Warning! This removes the key signing this operation. Only the remaining rotation keys will be able to change your DID afterwards.",
+            ));
+            if !Confirm::with_theme(self.theme.colorful_theme)
+                .with_prompt("Remove the signing key?")
+                .default(false)
+                .show_default(true)
+                .wait_for_newline(true)
+                .interact()?
+            {
+                return Ok(());
+            }
+        }
+
+        warn_verification_method_drift(self.theme, &last.operation);
+        let operation = build_remove_rotation_key(&last, index, &signer.did_key())?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{
+    actions::{
+        inputs::{get_signer_input, signer_input_prompts},
+        Options, TandemAction, Theme,
+    },
+    signer::self_test,
+};
+
+pub(crate) struct ActionSelfTest<'a> {
+    theme: &'a Theme<'a>,
+    options: Options,
+}
+
+impl<'a> ActionSelfTest<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, options: &Options) -> Self {
+        Self {
+            theme,
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionSelfTest<'_> {
+    fn needs_terminal(&self) -> bool {
+        signer_input_prompts(&self.options)
+    }
+
+    fn uses_plc_directory(&self) -> bool {
+        false
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Self Test' action signs a test operation with your private key and verifies the signature locally. Nothing is sent over the network."),
+        );
+
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+
+        let curve = self_test(signer.as_ref()).context("self-test failed")?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Self-test passed for {} ({})",
+            signer.did_key(),
+            curve
+        )));
+        self.theme.event(
+            "self_test_passed",
+            json!({ "did_key": signer.did_key(), "curve": curve.to_string() }),
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use dialoguer::Input;
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::{get_signer_input, is_valid_handle},
+        verify::{
+            offer_head_verification, stop_before_submission, warn_verification_method_drift,
+            write_receipt,
+        },
+        Options, TandemAction, Theme,
+    },
+    flows::{build_set_handles, check_signer_is_rotation_key, last_operation, sign, submit},
+    plc::curl_command,
+    state::DidLock,
+};
+
+pub(crate) struct ActionSetHandles<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionSetHandles<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionSetHandles<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Set Handles' action replaces the entire 'alsoKnownAs' field in your DID-PLC document in a single operation. This action requires your tandem private key."),
+        );
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        let started = Instant::now();
+        let last = last_operation(&self.http_client, &self.plc, &did, None).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last.cid }),
+        );
+        self.theme.println(self.theme.white_dim.apply_to(&last.cid));
+        self.theme.println(self.theme.white_dim.apply_to(format!(
+            "Current handles: {}",
+            last.operation["alsoKnownAs"]
+        )));
+
+        let handles_input = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("New handles (comma separated, primary first)")
+            .interact()?;
+        let handles = parse_handle_list(&handles_input)?;
+
+        check_signer_is_rotation_key(signer.as_ref(), &last.operation)?;
+        warn_verification_method_drift(self.theme, &last.operation);
+        let operation = build_set_handles(&last, &handles)?;
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize DID document")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize DID document")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        submit(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Parses a comma or whitespace separated list of handles, validating each
+/// and dropping duplicates while keeping the order they were given in.
+fn parse_handle_list(input: &str) -> Result<Vec<String>> {
+    let mut handles: Vec<String> = Vec::new();
+
+    for value in input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+    {
+        let handle = is_valid_handle(value).ok_or_else(|| anyhow!("invalid handle {}", value))?;
+        if !handles.contains(&handle) {
+            handles.push(handle);
+        }
+    }
+
+    if handles.is_empty() {
+        return Err(anyhow!("at least one handle is required"));
+    }
+
+    Ok(handles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_handle_lists() {
+        assert_eq!(
+            parse_handle_list("alice.example.com, at://alice.example.net @Alice.Example.com")
+                .unwrap(),
+            vec!["alice.example.com", "alice.example.net"]
+        );
+        assert!(parse_handle_list(" , ").is_err());
+        assert!(parse_handle_list("alice.example.com, alice").is_err());
+    }
+}
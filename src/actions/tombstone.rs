@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use dialoguer::{console::user_attended, Confirm, Input};
+use serde_json::json;
+use std::time::Instant;
+
+use crate::{
+    actions::{
+        get_did_plc_input,
+        inputs::{get_signer_input, signer_input_prompts},
+        verify::{offer_head_verification, stop_before_submission, write_receipt},
+        Options, TandemAction, Theme,
+    },
+    crypto::sign_operation,
+    flows::check_signer_is_rotation_key,
+    plc::{
+        check_prepared_operation, curl_command, did_plc_last_operation, ensure_did_exists,
+        submit_operation, validate_operation,
+    },
+    state::DidLock,
+};
+
+pub(crate) struct ActionTombstone<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+    options: Options,
+}
+
+impl<'a> ActionTombstone<'a> {
+    pub(crate) fn new(
+        theme: &'a Theme<'_>,
+        http_client: &reqwest::Client,
+        plc: &str,
+        options: &Options,
+    ) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+            options: options.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionTombstone<'_> {
+    fn needs_terminal(&self) -> bool {
+        self.options.did.is_none()
+            || !(self.options.confirm_permanent || self.options.dry_run)
+            || signer_input_prompts(&self.options)
+    }
+
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Tombstone Identity' action permanently deactivates your DID-PLC identity. This action requires a rotation key and cannot be undone."),
+        );
+
+        let confirmed_by_flags = confirmed_by_flags(&self.options, user_attended())?;
+
+        let did = match &self.options.did {
+            Some(did) => did.clone(),
+            None => get_did_plc_input(self.theme.colorful_theme, "What is your DID?")?,
+        };
+        let signer = get_signer_input(self.theme.colorful_theme, &self.options)?;
+        let _lock = DidLock::acquire(&did, self.options.force)?;
+
+        ensure_did_exists(&self.http_client, &self.plc, &did).await?;
+
+        let started = Instant::now();
+        let (last_commit, last_operation) =
+            did_plc_last_operation(&self.http_client, &self.plc, &did, true).await?;
+        check_signer_is_rotation_key(signer.as_ref(), &last_operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Retreived last operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event(
+            "retrieved_last_operation",
+            json!({ "did": did, "cid": last_commit }),
+        );
+        self.theme
+            .println(self.theme.white_dim.apply_to(&last_commit));
+
+        if last_operation["type"] == "plc_tombstone" {
+            return Err(anyhow!("{} is already tombstoned", did));
+        }
+
+        let operation = json!({
+            "type": "plc_tombstone",
+            "prev": last_commit,
+        });
+        check_prepared_operation(&operation, &last_commit)?;
+        validate_operation(&operation)?;
+
+        self.theme.println(
+            self.theme
+                .green
+                .apply_to("✔ Prepared operation for signing"),
+        );
+        self.theme
+            .event("prepared_operation", json!({ "operation": operation }));
+        self.theme.println(self.theme.white_dim.apply_to(
+            serde_json::to_string_pretty(&operation).context("failed to serialize operation")?,
+        ));
+
+        let started = Instant::now();
+        let signed_operation = sign_operation(signer.as_ref(), &operation)?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Signed operation{}",
+            self.options.elapsed(started)
+        )));
+        self.theme
+            .event("signed_operation", json!({ "operation": signed_operation }));
+        self.theme.println(
+            self.theme.white_dim.apply_to(
+                serde_json::to_string_pretty(&signed_operation)
+                    .context("failed to serialize operation")?,
+            ),
+        );
+
+        if self.options.emit_curl {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ Equivalent curl command for submitting the operation"),
+            );
+            let command = curl_command(&self.plc, &did, &signed_operation)?;
+            self.theme.println(&command);
+            self.theme
+                .event("curl_command", json!({ "command": command }));
+        }
+
+        if stop_before_submission(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )? {
+            return Ok(());
+        }
+
+        self.theme.println(self.theme.red_bold.apply_to(format!(
+                "Warning! Submitting this operation permanently retires {}. It cannot be resolved or updated afterwards.",
+                did
+            ))
+        );
+
+        if !confirmed_by_flags {
+            if !Confirm::with_theme(self.theme.colorful_theme)
+                .with_prompt(format!("Permanently tombstone {}?", did))
+                .default(false)
+                .show_default(true)
+                .wait_for_newline(true)
+                .interact()?
+            {
+                return Ok(());
+            }
+
+            let confirmed_did = Input::<String>::with_theme(self.theme.colorful_theme)
+                .with_prompt("Type the full DID to confirm")
+                .interact()?;
+            if confirmed_did.trim() != did {
+                return Err(anyhow!("confirmation did not match {}", did));
+            }
+        }
+
+        let started = Instant::now();
+        submit_operation(&self.http_client, &self.plc, &did, &signed_operation).await?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Operation submitted{}",
+            self.options.elapsed(started)
+        )));
+        self.theme.event("submitted", json!({ "did": did }));
+
+        offer_head_verification(
+            self.theme,
+            &self.http_client,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )
+        .await?;
+        write_receipt(
+            self.theme,
+            &self.options,
+            &self.plc,
+            &did,
+            &signed_operation,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Returns whether `--i-understand-this-is-permanent` stands in for the
+/// confirmation prompts. The flag names no DID, so it only counts alongside
+/// `--did`. Without it a terminal is required, except under `--dry-run`,
+/// which never submits.
+fn confirmed_by_flags(options: &Options, attended: bool) -> Result<bool> {
+    if options.confirm_permanent {
+        if options.did.is_none() {
+            return Err(anyhow!(
+                "--i-understand-this-is-permanent requires --did=DID to name the DID to tombstone"
+            ));
+        }
+        return Ok(true);
+    }
+    if !attended && !options.dry_run {
+        return Err(anyhow!(
+            "refusing to tombstone without a terminal; pass --did=DID and --i-understand-this-is-permanent to proceed"
+        ));
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        Options::from_args(
+            &args
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<String>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn permanent_flag_gate() {
+        let did = "--did=did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+
+        assert!(!confirmed_by_flags(&options(&[]), true).unwrap());
+        assert!(confirmed_by_flags(&options(&[]), false).is_err());
+        assert!(!confirmed_by_flags(&options(&["--dry-run"]), false).unwrap());
+
+        let confirmed = options(&[did, "--i-understand-this-is-permanent"]);
+        assert!(confirmed_by_flags(&confirmed, false).unwrap());
+        assert!(confirmed_by_flags(&confirmed, true).unwrap());
+
+        let err = confirmed_by_flags(&options(&["--i-understand-this-is-permanent"]), false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requires --did"), "{}", err);
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use dialoguer::{console::user_attended, Confirm};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::{
+    actions::{Options, Theme},
+    flows::verification_method_drift,
+    plc::wait_for_head,
+    receipt::{append_receipt, Receipt},
+};
+
+/// Offers to poll the PLC directory until the submitted operation is the
+/// head of the DID's operation chain. Without a terminal it polls without
+/// asking.
+pub(crate) async fn offer_head_verification(
+    theme: &Theme<'_>,
+    http_client: &reqwest::Client,
+    plc: &str,
+    did: &str,
+    operation: &serde_json::Value,
+) -> Result<()> {
+    if user_attended()
+        && !Confirm::with_theme(theme.colorful_theme)
+            .with_prompt("Verify that the operation reached the PLC directory?")
+            .default(true)
+            .show_default(true)
+            .wait_for_newline(true)
+            .interact()?
+    {
+        return Ok(());
+    }
+
+    if wait_for_head(http_client, plc, did, operation, 5, Duration::from_secs(2)).await? {
+        theme.println(
+            theme
+                .green
+                .apply_to("✔ Verified operation is now the DID head"),
+        );
+        theme.event("verified_head", json!({ "did": did, "is_head": true }));
+    } else {
+        theme.println(
+            theme.yellow_bold.apply_to(
+                "The operation is not yet the DID head. Propagation may still be pending.",
+            ),
+        );
+        theme.event("verified_head", json!({ "did": did, "is_head": false }));
+    }
+
+    Ok(())
+}
+
+/// Stops an action after signing when `--dry-run` was given, reporting that
+/// the signed operation was not submitted and writing a would-submit receipt
+/// when `--receipt` was given. Returns whether the caller should stop before
+/// submission.
+pub(crate) fn stop_before_submission(
+    theme: &Theme<'_>,
+    options: &Options,
+    plc: &str,
+    did: &str,
+    operation: &serde_json::Value,
+) -> Result<bool> {
+    if !options.dry_run {
+        return Ok(false);
+    }
+    theme.println(theme.yellow_bold.apply_to(format!(
+        "Dry run: the signed operation for {} was not submitted.",
+        did
+    )));
+    theme.event("not_submitted", json!({ "did": did }));
+    append_receipt_option(theme, options, Receipt::would_submit(plc, did, operation)?)?;
+    Ok(true)
+}
+
+/// Warns about verification methods in the last operation that use a legacy
+/// encoding, which the new operation will rewrite in the canonical form.
+pub(crate) fn warn_verification_method_drift(
+    theme: &Theme<'_>,
+    last_operation: &serde_json::Value,
+) {
+    for drift in verification_method_drift(last_operation) {
+        theme.println(theme.yellow_bold.apply_to(format!(
+                "The {} verification method uses a legacy encoding ({}). It will be rewritten as {}, which is the same key.",
+                drift.id, drift.found, drift.canonical
+            ))
+        );
+    }
+}
+
+/// Writes a receipt for a submitted operation when `--receipt` was given.
+pub(crate) fn write_receipt(
+    theme: &Theme<'_>,
+    options: &Options,
+    plc: &str,
+    did: &str,
+    operation: &serde_json::Value,
+) -> Result<()> {
+    if options.receipt.is_none() {
+        return Ok(());
+    }
+    append_receipt_option(theme, options, Receipt::new(plc, did, operation)?)
+}
+
+/// Appends `receipt` to the `--receipt` file, if one was given.
+fn append_receipt_option(theme: &Theme<'_>, options: &Options, receipt: Receipt) -> Result<()> {
+    let Some(path) = &options.receipt else {
+        return Ok(());
+    };
+    append_receipt(path, &receipt)?;
+    theme.println(
+        theme
+            .green
+            .apply_to(format!("✔ Wrote receipt to {}", path.display())),
+    );
+    theme.event(
+        "wrote_receipt",
+        json!({ "path": path, "submitted": receipt.submitted }),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dialoguer::theme::ColorfulTheme;
+
+    #[test]
+    fn dry_run_stops_before_submission() -> Result<()> {
+        let colorful_theme = ColorfulTheme::default();
+        let theme = Theme::json(&colorful_theme);
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+        let operation = json!({
+            "type": "plc_tombstone",
+            "prev": "bafyreid",
+            "sig": "abc",
+        });
+        let path = std::env::temp_dir().join(format!(
+            "tandem-dry-run-receipts-{}.jsonl",
+            std::process::id()
+        ));
+        let receipt_arg = format!("--receipt={}", path.display());
+
+        let options = Options::from_args(std::slice::from_ref(&receipt_arg))?;
+        assert!(!stop_before_submission(
+            &theme,
+            &options,
+            "plc.directory",
+            did,
+            &operation
+        )?);
+        assert!(!path.exists());
+
+        let options = Options::from_args(&["--dry-run".to_string(), receipt_arg])?;
+        assert!(stop_before_submission(
+            &theme,
+            &options,
+            "plc.directory",
+            did,
+            &operation
+        )?);
+        let receipts = crate::receipt::read_receipts(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].did, did);
+        assert_eq!(receipts[0].operation, operation);
+        assert!(!receipts[0].submitted);
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use dialoguer::Input;
+use serde_json::json;
+use std::path::PathBuf;
+
+use crate::{
+    actions::{TandemAction, Theme},
+    bundle::{bundle_drift, export_identity, IdentityBundle},
+};
+
+pub(crate) struct ActionVerifyBundle<'a> {
+    theme: &'a Theme<'a>,
+    http_client: reqwest::Client,
+    plc: String,
+}
+
+impl<'a> ActionVerifyBundle<'a> {
+    pub(crate) fn new(theme: &'a Theme<'_>, http_client: &reqwest::Client, plc: &str) -> Self {
+        Self {
+            theme,
+            http_client: http_client.clone(),
+            plc: plc.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl TandemAction for ActionVerifyBundle<'_> {
+    async fn run(&self) -> Result<()> {
+        self.theme.println(
+            self.theme
+                .white_dim
+                .apply_to("The 'Verify Bundle' action compares an exported identity bundle with the live state of the identity."),
+        );
+
+        let path = Input::<String>::with_theme(self.theme.colorful_theme)
+            .with_prompt("Bundle file")
+            .interact()?;
+        let bundle = IdentityBundle::read(&PathBuf::from(path))?;
+        self.theme.println(self.theme.green.apply_to(format!(
+            "✔ Loaded bundle for {} exported at {}",
+            bundle.did,
+            bundle.exported_at.to_rfc3339()
+        )));
+        self.theme.event(
+            "loaded_bundle",
+            json!({ "did": bundle.did, "exported_at": bundle.exported_at }),
+        );
+
+        let live = export_identity(&self.http_client, &self.plc, &bundle.did).await?;
+        let drift = bundle_drift(&bundle, &live);
+
+        if drift.is_empty() {
+            self.theme.println(
+                self.theme
+                    .green
+                    .apply_to("✔ The live identity matches the bundle"),
+            );
+            self.theme
+                .event("bundle_verified", json!({ "did": bundle.did, "drift": [] }));
+            return Ok(());
+        }
+
+        for change in &drift {
+            self.theme.println(self.theme.yellow_bold.apply_to(change));
+        }
+        Err(anyhow!(
+            "{} differs from the bundle in {} way(s)",
+            bundle.did,
+            drift.len()
+        ))
+    }
+}
@@ -1,31 +1,664 @@
-use anyhow::{Context, Result};
-use dialoguer::{console::Style, theme::ColorfulTheme, Confirm, Input, Select};
-use std::{env, process::ExitCode};
-use tandem::actions::{get_action, Theme, SUPPORTED_ACTIONS};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{
+    console::{
+        colors_enabled, set_colors_enabled, set_colors_enabled_stderr, user_attended, Style, Term,
+    },
+    theme::ColorfulTheme,
+    Confirm, Input, Select,
+};
+use serde_json::json;
+use std::{env, path::Path, process::ExitCode};
+use tandem::actions::{
+    interrupt::{interrupt_reminders, release_did_locks},
+    ActionRegistry, Options, Theme,
+};
+use tandem::bundle::export_identity;
+use tandem::http_client::HttpClientOptions;
+use tandem::multikey::{decode_did_key, did_key_to_verifying_key};
+use tandem::output::{InspectOutput, OutputFormat, ResolveOutput};
+use tandem::plc::{
+    check_directory_health, check_invariants, check_signed_operation, did_from_genesis,
+    genesis_operation, normalize_plc_hostname, operation_cid, plc_hostname_from_args,
+    sign_unsigned_operation, submit_operation,
+};
+use tandem::receipt::{append_receipt, Receipt};
+use tandem::resolve::{check_handle_on_pds, resolve_did_only, ResolveOptions, Resolver};
+use tandem::signer::{self_test, JwkSigner, SignatureEncoding, Signer};
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    if let Err(err) = real_main().await {
-        let red_bold = Style::new().red().bold();
-        println!("{}: {:?}", red_bold.apply_to("Error"), err);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if !color_requested(&args) {
+        set_colors_enabled(false);
+        set_colors_enabled_stderr(false);
+    }
+
+    if args.first().is_some_and(|arg| arg == "resolve") {
+        return match resolve_main(&args[1..]).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "inspect") {
+        return match inspect_main(&args[1..]).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "selftest") {
+        return match selftest_main(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "export") {
+        return match export_main(&args[1..]).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "sign-op") {
+        return match sign_op_main(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "submit-op") {
+        return match submit_op_main(&args[1..]).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "compute-did") {
+        return match compute_did_main(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "check-handle") {
+        return match check_handle_main(&args[1..]).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "validate-doc") {
+        return match validate_doc_main(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if args.first().is_some_and(|arg| arg == "decode-key") {
+        return match decode_key_main(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{:#}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            handle_interrupt();
+        }
+    });
+
+    let json_events = json_requested(&args);
+    if let Err(err) = real_main(&args).await {
+        if json_events {
+            println!(
+                "{}",
+                json!({ "step": "error", "error": format!("{:#}", err) })
+            );
+        } else {
+            let red_bold = Style::new().red().bold();
+            println!("{}: {:?}", red_bold.apply_to("Error"), err);
+        }
         return ExitCode::FAILURE;
     }
 
+    if json_events {
+        println!("{}", json!({ "step": "success" }));
+        return ExitCode::SUCCESS;
+    }
     let green_bold = Style::new().green().bold();
     println!("{}", green_bold.apply_to("Success"));
     ExitCode::SUCCESS
 }
 
-async fn real_main() -> Result<()> {
-    let args: Vec<String> = env::args().skip(1).collect();
+/// Color is used unless `--no-color` or `--json` is given, `NO_COLOR` is set,
+/// or stdout is not a terminal.
+fn color_requested(args: &[String]) -> bool {
+    !args.iter().any(|arg| arg == "--no-color")
+        && !json_requested(args)
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && Term::stdout().is_term()
+}
+
+/// Whether actions print JSON events instead of formatted output.
+fn json_requested(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--json")
+}
+
+/// Runs when the user presses Ctrl-C. Prompts block the main task, so this is
+/// invoked from a separate task and exits the process itself.
+fn handle_interrupt() -> ! {
+    let term = Term::stdout();
+    let _ = term.show_cursor();
+    release_did_locks();
+
+    let reminders = interrupt_reminders();
+    let yellow_bold = Style::new().yellow().bold();
+
+    if let Some(generated_key) = &reminders.generated_key {
+        // The request installing the key may already have been applied.
+        println!(
+            "{} The private key for {} may already have been installed. Keep it until you have checked the rotation keys of your DID.",
+            yellow_bold.apply_to("Interrupted!"),
+            generated_key
+        );
+    }
+
+    if let Some(did) = &reminders.pending_signature_request {
+        println!(
+            "{} A PLC signature request for {} is pending. The emailed confirmation code can be reused on the next run or ignored.",
+            yellow_bold.apply_to("Interrupted!"),
+            did
+        );
+    }
+
+    if reminders.generated_key.is_none() && reminders.pending_signature_request.is_none() {
+        println!("{}", yellow_bold.apply_to("Interrupted!"));
+    }
+
+    std::process::exit(130);
+}
+
+fn build_http_client(args: &[String]) -> Result<reqwest::Client> {
+    HttpClientOptions::from_args(args)?.build()
+}
+
+/// Returns the arguments that are not options or the values of options.
+fn positional_args(args: &[String]) -> Vec<&String> {
+    let mut positional = Vec::new();
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if arg == "--format" {
+            remaining.next();
+        } else if !arg.starts_with("--") {
+            positional.push(arg);
+        }
+    }
+    positional
+}
+
+async fn resolve_main(args: &[String]) -> Result<()> {
+    let subject = positional_args(args).into_iter().next().ok_or_else(|| {
+        anyhow!(
+            "usage: tandem resolve [--all] [--did-only] [--doh[=URL]] [--nameserver=IP[:PORT]] [--format json] [--plc=HOST] <handle>"
+        )
+    })?;
+    let plc = plc_hostname_from_args(args)?;
+    let display_all = args.iter().any(|arg| arg == "--all");
+    let did_only = args.iter().any(|arg| arg == "--did-only");
+    let format = OutputFormat::from_args(args)?;
+
+    let http_client = build_http_client(args)?;
+    let resolver = Resolver::with_options(&http_client, &plc, &ResolveOptions::from_args(args)?);
+
+    let resolved = if did_only {
+        resolve_did_only(&http_client, &plc, subject).await?
+    } else {
+        resolver.resolve(subject).await?
+    };
+
+    if format == OutputFormat::Json {
+        let output = ResolveOutput::verify(&resolver, &resolved).await;
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else if display_all {
+        let output = json!({
+            "did": resolved.did,
+            "pds": resolved.pds,
+            "handles": resolved.handles,
+            "unverified_handles": resolved.unverified_handles,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        println!("{}", resolved.did);
+    }
+
+    Ok(())
+}
+
+/// Prints the current state of an identity's DID document.
+async fn inspect_main(args: &[String]) -> Result<()> {
+    let subject = positional_args(args).into_iter().next().ok_or_else(|| {
+        anyhow!("usage: tandem inspect [--format json] [--plc=HOST] <handle or did>")
+    })?;
+    let plc = plc_hostname_from_args(args)?;
+    let format = OutputFormat::from_args(args)?;
+
+    let http_client = build_http_client(args)?;
+    let did = if subject.starts_with("did:") {
+        subject.to_string()
+    } else {
+        Resolver::with_options(&http_client, &plc, &ResolveOptions::from_args(args)?)
+            .resolve(subject)
+            .await?
+            .did
+    };
+    let output = InspectOutput::fetch(&http_client, &plc, &did).await?;
+
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!("did: {}", output.did);
+    println!("pds: {}", output.pds.as_deref().unwrap_or("none"));
+    for handle in &output.handles {
+        println!("handle: {}", handle);
+    }
+    for (index, key) in output.rotation_keys.iter().enumerate() {
+        println!(
+            "rotation key {}: {} ({})",
+            index,
+            key.did_key,
+            key.curve.as_deref().unwrap_or("unrecognized")
+        );
+    }
+    for method in &output.verification_methods {
+        println!(
+            "verification method {}: {} ({})",
+            method.id,
+            method.key.did_key,
+            method.key.curve.as_deref().unwrap_or("unrecognized")
+        );
+    }
+
+    Ok(())
+}
+
+async fn export_main(args: &[String]) -> Result<()> {
+    let usage = || anyhow!("usage: tandem export [--plc=HOST] <did> -o <file>");
+
+    let mut did = None;
+    let mut output = None;
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if arg == "-o" {
+            output = Some(remaining.next().ok_or_else(usage)?);
+        } else if !arg.starts_with("--") {
+            did = Some(arg);
+        }
+    }
+    let did = did.ok_or_else(usage)?;
+    let output = output.ok_or_else(usage)?;
+    let plc = plc_hostname_from_args(args)?;
 
+    let http_client = build_http_client(args)?;
+
+    let bundle = export_identity(&http_client, &plc, did).await?;
+    bundle.write(Path::new(output))?;
+    eprintln!("Exported {} to {}", did, output);
+
+    Ok(())
+}
+
+fn selftest_main(args: &[String]) -> Result<()> {
+    let jwk_file = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--jwk-file="))
+        .ok_or_else(|| anyhow!("usage: tandem selftest --jwk-file=FILE"))?;
+
+    let signer = JwkSigner::from_file(Path::new(jwk_file))?;
+    let curve = self_test(&signer).context("self-test failed")?;
+    println!("pass {} ({})", signer.did_key(), curve);
+
+    Ok(())
+}
+
+/// Checks whether a handle is available on a PDS before creating an account.
+async fn check_handle_main(args: &[String]) -> Result<()> {
+    let usage = || anyhow!("usage: tandem check-handle <handle> --pds=HOST");
+
+    let mut handle = None;
+    let mut pds = None;
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if arg == "--pds" {
+            pds = remaining.next().map(String::as_str);
+        } else if let Some(value) = arg.strip_prefix("--pds=") {
+            pds = Some(value);
+        } else if !arg.starts_with("--") {
+            handle = Some(arg);
+        }
+    }
+    let handle = handle.ok_or_else(usage)?;
+    let pds = pds.ok_or_else(usage)?;
+
+    let http_client = build_http_client(args)?;
+    match check_handle_on_pds(&http_client, pds, handle).await? {
+        Some(did) => Err(anyhow!("{} is already taken by {}", handle, did)),
+        None => {
+            println!("{} is available", handle);
+            Ok(())
+        }
+    }
+}
+
+/// Checks an operation or operation data file against the PLC invariants
+/// without contacting the directory, reporting every invariant.
+fn validate_doc_main(args: &[String]) -> Result<()> {
+    let usage = || anyhow!("usage: tandem validate-doc --in FILE");
+
+    let mut input = None;
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if arg == "--in" {
+            input = remaining.next().map(String::as_str);
+        } else if let Some(value) = arg.strip_prefix("--in=") {
+            input = Some(value);
+        }
+    }
+    let input = input.ok_or_else(usage)?;
+
+    let operation: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(input).with_context(|| format!("failed to read {}", input))?,
+    )
+    .with_context(|| format!("failed to parse {}", input))?;
+    if operation.get("verificationMethod").is_some() || operation.get("@context").is_some() {
+        return Err(anyhow!(
+            "{} is a resolved DID document; validate the operation data from the directory's /data endpoint instead",
+            input
+        ));
+    }
+
+    let checks = check_invariants(&operation);
+    for check in &checks {
+        match &check.result {
+            Ok(found) => println!("pass {}: {}", check.name, found),
+            Err(err) => println!("fail {}: {}", check.name, err),
+        }
+    }
+
+    let failed = checks.iter().filter(|check| !check.passed()).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} invariants failed", failed, checks.len()));
+    }
+    Ok(())
+}
+
+/// Prints the curve and public key of a did:key, and whether the key is a
+/// valid point on its curve.
+fn decode_key_main(args: &[String]) -> Result<()> {
+    let did_key = args
+        .first()
+        .ok_or_else(|| anyhow!("usage: tandem decode-key <did:key>"))?;
+
+    let decoded = decode_did_key(did_key)?;
+    println!("curve: {}", decoded.curve);
+    println!("public key: {}", decoded.public_key_hex());
+    match did_key_to_verifying_key(did_key) {
+        Ok(_) => {
+            println!("valid point: yes");
+            Ok(())
+        }
+        Err(err) => {
+            println!("valid point: no");
+            Err(err)
+        }
+    }
+}
+
+/// Signs an unsigned operation read from a file, without network access.
+fn sign_op_main(args: &[String]) -> Result<()> {
+    let usage = || {
+        anyhow!("usage: tandem sign-op --jwk-file=FILE --in=FILE --out=FILE [--signature-encoding=raw|der]")
+    };
+    let flag = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    let jwk_file = flag("--jwk-file=").ok_or_else(usage)?;
+    let input = flag("--in=").ok_or_else(usage)?;
+    let output = flag("--out=").ok_or_else(usage)?;
+    let encoding = match flag("--signature-encoding=") {
+        None | Some("raw") => SignatureEncoding::RawBase64Url,
+        Some("der") => SignatureEncoding::DerBase64,
+        Some(value) => {
+            return Err(anyhow!(
+                "invalid --signature-encoding value {}: expected raw or der",
+                value
+            ))
+        }
+    };
+    if encoding != SignatureEncoding::RawBase64Url {
+        eprintln!("PLC directories only accept raw signatures; the signed operation cannot be submitted as-is.");
+    }
+
+    let signer = JwkSigner::from_file(Path::new(jwk_file))?;
+    let operation: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(input).with_context(|| format!("failed to read {}", input))?,
+    )
+    .with_context(|| format!("failed to parse {}", input))?;
+
+    let signed_operation = sign_unsigned_operation(&signer, &operation, encoding)
+        .with_context(|| format!("failed to sign {}", input))?;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output)
+        .and_then(|mut file| {
+            std::io::Write::write_all(
+                &mut file,
+                serde_json::to_string_pretty(&signed_operation)?.as_bytes(),
+            )
+        })
+        .with_context(|| format!("failed to write {}", output))?;
+    eprintln!("Signed {} with {} to {}", input, signer.did_key(), output);
+
+    Ok(())
+}
+
+/// Submits an operation signed elsewhere, such as by `sign-op` on an offline
+/// machine, after confirming unless --yes is given.
+async fn submit_op_main(args: &[String]) -> Result<()> {
+    let usage = || {
+        anyhow!("usage: tandem submit-op [--plc=HOST] --did=DID --in=FILE [--yes] [--receipt=FILE]")
+    };
+    let flag = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    let did = flag("--did=")
+        .filter(|did| did.starts_with("did:plc:"))
+        .ok_or_else(usage)?;
+    let input = flag("--in=").ok_or_else(usage)?;
+    let plc = plc_hostname_from_args(args)?;
+    let confirmed = args.iter().any(|arg| arg == "--yes");
+
+    let operation: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(input).with_context(|| format!("failed to read {}", input))?,
+    )
+    .with_context(|| format!("failed to parse {}", input))?;
+    check_signed_operation(did, &operation).with_context(|| format!("cannot submit {}", input))?;
+
+    eprintln!("{}", serde_json::to_string_pretty(&operation)?);
+    if !confirmed {
+        if !user_attended() {
+            return Err(anyhow!(
+                "submitting an operation without a terminal requires --yes"
+            ));
+        }
+        if !Confirm::new()
+            .with_prompt(format!("Submit this operation for {} to {}?", did, plc))
+            .default(false)
+            .interact()?
+        {
+            return Err(anyhow!("submission aborted"));
+        }
+    }
+
+    let http_client = build_http_client(args)?;
+    submit_operation(&http_client, &plc, did, &operation).await?;
+    eprintln!("Submitted {} for {}", operation_cid(&operation)?, did);
+    if let Some(receipt) = flag("--receipt=") {
+        append_receipt(Path::new(receipt), &Receipt::new(&plc, did, &operation)?)?;
+        eprintln!("Wrote receipt to {}", receipt);
+    }
+
+    Ok(())
+}
+
+/// Prints the DID a genesis operation would create without submitting it.
+/// The key in --jwk-file signs the operation and is the first rotation key.
+fn compute_did_main(args: &[String]) -> Result<()> {
+    let jwk_file = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--jwk-file="))
+        .ok_or_else(|| {
+            anyhow!("usage: tandem compute-did --jwk-file=FILE [--rotation-key=DID_KEY]... [--signing-key=DID_KEY] [--handle=HANDLE] [--pds=URL]")
+        })?;
+    let signer = JwkSigner::from_file(Path::new(jwk_file))?;
+
+    let flag_or_prompt = |flag: &str, prompt: &str| -> Result<String> {
+        match args.iter().find_map(|arg| arg.strip_prefix(flag)) {
+            Some(value) => Ok(value.to_string()),
+            None => Ok(Input::<String>::new().with_prompt(prompt).interact()?),
+        }
+    };
+    let signing_key = flag_or_prompt("--signing-key=", "Signing key (did:key)")?;
+    let handle = flag_or_prompt("--handle=", "Handle")?;
+    let pds = flag_or_prompt("--pds=", "PDS endpoint")?;
+
+    let rotation_keys = std::iter::once(signer.did_key())
+        .chain(
+            args.iter()
+                .filter_map(|arg| arg.strip_prefix("--rotation-key="))
+                .map(str::to_string),
+        )
+        .collect::<Vec<String>>();
+
+    let operation = genesis_operation(&rotation_keys, &signing_key, &handle, &pds)?;
+    let signed_operation =
+        sign_unsigned_operation(&signer, &operation, SignatureEncoding::RawBase64Url)?;
+    println!("{}", did_from_genesis(&signed_operation)?);
+
+    Ok(())
+}
+
+async fn real_main(args: &[String]) -> Result<()> {
     let display_help = args.iter().any(|arg| arg == "--help");
 
     if display_help {
         println!("Usage: tandem [options]");
+        println!("       tandem resolve [--all] [--did-only] [--doh[=URL]] [--nameserver=IP[:PORT]] [--format json] [--plc=HOST] <handle>");
+        println!("       tandem inspect [--format json] [--plc=HOST] <handle or did>");
+        println!("       tandem export [--plc=HOST] <did> -o <file>");
+        println!("       tandem selftest --jwk-file=FILE");
+        println!("       tandem sign-op --jwk-file=FILE --in=FILE --out=FILE [--signature-encoding=raw|der]");
+        println!(
+            "       tandem submit-op [--plc=HOST] --did=DID --in=FILE [--yes] [--receipt=FILE]"
+        );
+        println!("       tandem check-handle <handle> --pds=HOST");
+        println!("       tandem decode-key <did:key>");
+        println!("       tandem validate-doc --in FILE");
+        println!("       tandem compute-did --jwk-file=FILE [--rotation-key=DID_KEY]... [--signing-key=DID_KEY] [--handle=HANDLE] [--pds=URL]");
         println!("Options:");
         println!("\t--help\t\t\tDisplays this message.");
-        println!("\t--ca-certificate=FILE\tAllows one or more CA certificate to be used for HTTPS connections.");
+        println!("\t--env=ENV\t\tUses the production (plc.directory) or sandbox (default) PLC directory. --plc=HOST overrides it.");
+        println!("\t--ca-certificate=FILE\tTrusts the CA certificate in FILE for HTTPS connections, in addition to the system roots. May be repeated.");
+        println!(
+            "\t--use-system-roots\tTrusts the system's root certificates. This is the default."
+        );
+        println!(
+            "\t--no-system-roots\tTrusts only the --ca-certificate roots, for pinned environments."
+        );
+        println!("\t--min-tls=1.2|1.3\tRefuses connections that negotiate an older TLS version. Defaults to 1.2.");
+        println!(
+            "\t--prev=CID\t\tBuilds the appended handle operation on a specific prior operation."
+        );
+        println!("\t--atproto-proxy=DID#SVC\tSends the atproto-proxy header with authenticated PDS requests.");
+        println!("\t--action=NAME\t\tRuns the named action, such as append-handle, without the opening confirmation or menus. Without a terminal, only Append Handle, Remove Handle, Tombstone Identity, and Self Test run, once their options supply every input.");
+        println!("\t--did=DID\t\tUses DID instead of prompting for your DID.");
+        println!("\t--handle=HANDLE\t\tUses HANDLE as your own handle instead of prompting for it, in Upgrade Account, Create Account, and Deactivate Account.");
+        println!(
+            "\t--new-handle=HANDLE\tAppends HANDLE in Append Handle instead of prompting for it."
+        );
+        println!("\t--remove-handle=HANDLE\tRemoves HANDLE in Remove Handle instead of prompting for it.");
+        println!(
+            "\t--token-file=FILE\tWaits for the emailed confirmation code to be written to FILE."
+        );
+        println!(
+            "\t--emit-curl\t\tPrints a curl command that submits each locally signed operation."
+        );
+        println!("\t--i-understand-this-is-permanent\n\t\t\t\tTombstones the identity in --did without the confirmation prompts, as needed without a terminal.");
+        println!("\t--timings\t\tPrints how long each network-bound step took.");
+        println!("\t--key-out=FILE\t\tWrites generated private keys to FILE, readable only by you, instead of printing them. FILE must not exist.");
+        println!("\t--encrypt-key\t\tEncrypts the --key-out file with a passphrase, read from TANDEM_KEY_PASSPHRASE or prompted for. --jwk-file accepts encrypted files.");
+        println!("\t--key-encoding=BASE\tAlso prints generated public keys in base16, base32, or base64url.");
+        println!("\t--jwk-file=FILE\t\tLoads the rotation key from FILE instead of prompting, as a JWK, PEM, or p256:/k256: hex key. TANDEM_JWK may hold the key instead.");
+        println!("\t--skip-plc-health-check\n\t\t\t\tSkips checking that the PLC directory responds before an action runs.");
+        println!("\t--email=EMAIL\t\tUses EMAIL when creating an account instead of prompting.");
+        println!("\t--email-stdin\t\tReads the email for a new account from standard input.");
+        println!("\t--no-color\t\tDisables colored output. Also disabled by NO_COLOR or when output is not a terminal.");
+        println!(
+            "\t--rate-limit=N\t\tLimits batch actions to N requests per second, allowing bursts of up to N. Defaults to 5."
+        );
+        println!("\t--operation-source=SRC\tBuilds operations on the DID state from plc (default) or from the PDS's recommended credentials.");
+        println!("\t--force\t\t\tProceeds even when another tandem run appears to be changing the same DID.");
+        println!("\t--dry-run\t\tSigns operations without submitting them, and prints what Deactivate Account would do without deactivating.");
+        println!("\t--receipt=FILE\t\tAppends a JSON receipt with the DID, operation, CID, time, and PLC directory of each submitted operation, or a receipt marked not submitted under --dry-run.");
+        println!("\t--doh[=URL]\t\tLooks up handle TXT records over DNS-over-HTTPS, through Cloudflare unless URL is given.");
+        println!("\t--nameserver=IP[:PORT]\tSends handle TXT lookups to this nameserver; may be repeated.");
+        println!(
+            "\t--format json\t\tPrints resolve and inspect results as versioned JSON for tooling."
+        );
+        println!("\t--json\t\t\tPrints each step of an action as a line of JSON instead of formatted output. Implies --format json.");
+        println!("\t--debug-responses\tPrints the full createAccount, createSession, and describeServer responses to stderr, with tokens redacted.");
+        println!("\t--trace-requests[=FILE]\tWrites every HTTP request and response status to stderr or FILE, with credentials redacted.");
+        println!("Commands:");
+        println!("\texport <did> -o <file>\tWrites the DID document, audit log, and public keys of a DID to a bundle file.");
+        println!("\tsign-op\t\t\tSigns the unsigned operation in --in with --jwk-file and writes it to --out, without network access. --signature-encoding=der writes a DER signature for other verifiers.");
+        println!("\tsubmit-op\t\tSubmits the signed operation in --in for --did after confirming, or immediately with --yes.");
+        println!("\tcompute-did\t\tPrints the DID a genesis operation signed by --jwk-file would create, without submitting it.");
+        println!("\tcheck-handle <handle>\tReports whether a handle is available on the PDS given with --pds.");
+        println!("\tdecode-key <did:key>\tPrints the curve and public key of a did:key and whether it is a valid point.");
+        println!("\tselftest\t\tSigns and verifies a test operation with the key in --jwk-file.");
+        println!("\tresolve <handle>\tPrints the DID of a handle. With --all, prints the DID, PDS, and handles as JSON. With --did-only, resolves a DID even if it has no handle.");
         return Ok(());
     }
 
@@ -33,54 +666,73 @@ async fn real_main() -> Result<()> {
         values_style: Style::new().white().bold(),
         ..ColorfulTheme::default()
     };
-    let theme = Theme {
-        colorful_theme: &colorful_theme,
-        red_bold: Style::new().red().bold(),
-        yellow_bold: Style::new().yellow().bold(),
-        green: Style::new().green(),
-        white_dim: Style::new().white().dim(),
+    let theme = if json_requested(args) {
+        Theme::json(&colorful_theme)
+    } else if colors_enabled() {
+        Theme::colored(&colorful_theme)
+    } else {
+        Theme::plain(&colorful_theme)
     };
 
-    println!("{} This tool will perform potentially dangerous operations on your behalf. Do not proceed unless you know what you are doing.", theme.red_bold.apply_to("Warning!"));
+    let options = Options::from_args(args)?;
+    let http_client = build_http_client(args)?;
+    let registry = ActionRegistry::new();
 
-    if !Confirm::with_theme(theme.colorful_theme)
-        .with_prompt("Do you want to proceed?")
-        .default(true)
-        .show_default(true)
-        .wait_for_newline(true)
-        .interact()?
-    {
-        return Ok(());
-    }
-
-    let mut client_builder = reqwest::Client::builder();
-
-    for arg in &args {
-        if let Some(ca_certificate) = arg.strip_prefix("--ca-certificate=") {
-            let cert_data = std::fs::read(ca_certificate)
-                .with_context(|| format!("failed to read CA certificate: {}", ca_certificate))?;
-            let cert = reqwest::Certificate::from_pem(&cert_data)
-                .with_context(|| format!("failed to parse CA certificate: {}", ca_certificate))?;
-            client_builder = client_builder.add_root_certificate(cert);
+    // A named action runs without the opening confirmation or any menus, so
+    // that it can be scripted.
+    let (plc, action) = match &options.action {
+        Some(name) => {
+            let plc = normalize_plc_hostname(&plc_hostname_from_args(args)?)?;
+            let action = registry.build_named(name, &theme, &http_client, &plc, &options)?;
+            if !user_attended() && action.needs_terminal() {
+                return Err(anyhow!(
+                    "{} still prompts for input its options do not supply, so it cannot run without a terminal",
+                    name
+                ));
+            }
+            (plc, action)
         }
-    }
+        None => {
+            theme.println(format!("{} This tool will perform potentially dangerous operations on your behalf. Do not proceed unless you know what you are doing.", theme.red_bold.apply_to("Warning!")));
 
-    let http_client = client_builder
-        .build()
-        .context("failed to create HTTP client")?;
+            if !Confirm::with_theme(theme.colorful_theme)
+                .with_prompt("Do you want to proceed?")
+                .default(true)
+                .show_default(true)
+                .wait_for_newline(true)
+                .interact()?
+            {
+                return Ok(());
+            }
 
-    let plc = Input::<String>::with_theme(theme.colorful_theme)
-        .with_prompt("PLC Directory")
-        .default("plc.pyroclastic.cloud".parse().unwrap())
-        .interact()?;
+            let plc = Input::<String>::with_theme(theme.colorful_theme)
+                .with_prompt("PLC Directory")
+                .default(plc_hostname_from_args(args)?)
+                .validate_with(|input: &String| {
+                    normalize_plc_hostname(input)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                })
+                .interact()?;
+            let plc = normalize_plc_hostname(&plc)?;
 
-    let selected_operation = Select::with_theme(theme.colorful_theme)
-        .with_prompt("Supported Operations")
-        .default(0)
-        .items(&SUPPORTED_ACTIONS[..])
-        .interact()?;
+            let selected_operation = Select::with_theme(theme.colorful_theme)
+                .with_prompt("Supported Operations")
+                .default(0)
+                .items(&registry.names()[..])
+                .interact()?;
 
-    let action = get_action(selected_operation, &theme, &http_client, &plc)?;
+            let action =
+                registry.build(selected_operation, &theme, &http_client, &plc, &options)?;
+            (plc, action)
+        }
+    };
+
+    if action.uses_plc_directory() && !options.skip_plc_health_check {
+        check_directory_health(&http_client, &plc)
+            .await
+            .map_err(|err| anyhow!("{:#}; pass --skip-plc-health-check to continue anyway", err))?;
+    }
 
     action.run().await
 }
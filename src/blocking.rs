@@ -0,0 +1,603 @@
+//! Blocking counterparts of the handle resolution, PLC directory, and PDS
+//! APIs, for consumers that do not run a tokio runtime of their own.
+//!
+//! HTTP requests go through `reqwest::blocking` and DNS lookups through
+//! hickory's synchronous resolver, while the resolution rules, response
+//! parsing, and audit log checks are shared with the async API. Like
+//! `reqwest::blocking`, these functions must not be called from within an
+//! async context.
+
+use anyhow::{anyhow, Context, Result};
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    Resolver,
+};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::HeaderMap,
+};
+
+use crate::{
+    plc::{plc_url, verified_last_operation, ResolveDid},
+    resolve::{
+        did_document_url, did_from_txt_records, did_from_well_known, did_only_resolution,
+        txt_lookup_name, well_known_url, SubjectResolution, HTTP_LOOKUP_TIMEOUT,
+    },
+    xrpc::{
+        insert_header, is_retryable_status, pds_base_url, retry_delay, XrpcCall, XrpcResponse,
+        RETRY_ATTEMPTS,
+    },
+};
+
+pub use crate::plc::AuditEntry;
+pub use crate::resolve::ResolvedHandle;
+pub use crate::xrpc::{RecommendedCredentials, RepoInfo, XrpcRequestError};
+
+/// Resolves a handle or DID to its DID, PDS, and handles, as
+/// `tandem::resolve::resolve_handle` does.
+pub fn resolve_handle(
+    http_client: &Client,
+    plc_hostname: &str,
+    subject: &str,
+) -> Result<ResolvedHandle> {
+    let dns_resolver = Resolver::new(ResolverConfig::default(), ResolverOpts::default())
+        .context("failed to create DNS resolver")?;
+
+    let mut resolution = SubjectResolution::new(subject);
+    while let Some((next_did, next_handle)) = resolution.next_lookups()? {
+        if let Some(next_did) = next_did {
+            let query_res = did_document_url(plc_hostname, &next_did)
+                .and_then(|url| did_document_query(http_client, &url));
+            resolution.add_did_document(query_res.ok());
+        }
+
+        if let Some(next_handle) = next_handle {
+            // Both lookups run at once so a handle with only one method does
+            // not wait out the other's timeout first.
+            let (http_resolve, dns_resolve) = std::thread::scope(|scope| {
+                let http = scope.spawn(|| resolve_handle_http(http_client, &next_handle));
+                let dns = resolve_handle_dns_with(&dns_resolver, &next_handle);
+                (
+                    http.join()
+                        .unwrap_or_else(|_| Err(anyhow!("HTTPS lookup panicked"))),
+                    dns,
+                )
+            });
+            resolution.add_handle_lookups(&next_handle, http_resolve.ok(), dns_resolve.ok())?;
+        }
+    }
+    resolution.finish()
+}
+
+pub fn resolve_handle_dns(handle: &str) -> Result<String> {
+    let resolver = Resolver::new(ResolverConfig::default(), ResolverOpts::default())
+        .context("failed to create DNS resolver")?;
+    resolve_handle_dns_with(&resolver, handle)
+}
+
+fn resolve_handle_dns_with(resolver: &Resolver, handle: &str) -> Result<String> {
+    let records = resolver
+        .txt_lookup(txt_lookup_name(handle))?
+        .iter()
+        .map(|record| record.to_string())
+        .collect::<Vec<String>>();
+    did_from_txt_records(handle, &records)
+}
+
+pub fn resolve_handle_http(http_client: &Client, handle: &str) -> Result<String> {
+    let lookup_url = well_known_url(handle);
+
+    let body = http_client
+        .get(lookup_url.clone())
+        .timeout(HTTP_LOOKUP_TIMEOUT)
+        .send()?
+        .text()?;
+    did_from_well_known(&lookup_url, body)
+}
+
+/// Resolves a DID from its document alone, as
+/// `tandem::resolve::resolve_did_only` does.
+pub fn resolve_did_only(
+    http_client: &Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<ResolvedHandle> {
+    if !did.starts_with("did:") {
+        return Err(anyhow!("{} is not a DID", did));
+    }
+
+    let (pds, handles) = did_document_query(http_client, &did_document_url(plc_hostname, did)?)?;
+    did_only_resolution(did, pds, handles)
+}
+
+fn did_document_query(http_client: &Client, url: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let resolved_did: ResolveDid = json_body(http_client.get(url).send()?)?;
+    Ok(resolved_did.endpoints())
+}
+
+pub fn did_plc_data(
+    http_client: &Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<serde_json::Value> {
+    let url = plc_url(plc_hostname, &format!("{}/data", did));
+
+    json_body(
+        http_client
+            .get(url)
+            .send()
+            .context("unable to get DID document")?,
+    )
+    .context("unable to deserialize DID document")
+}
+
+pub fn did_plc_audit_log(
+    http_client: &Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<Vec<AuditEntry>> {
+    let url = plc_url(plc_hostname, &format!("{}/log/audit", did));
+
+    let mut operations: Vec<AuditEntry> = json_body(
+        http_client
+            .get(url)
+            .send()
+            .context("unable to get DID audit log")?,
+    )
+    .context("unable to deserialize DID audit log")?;
+
+    operations.sort_by_key(|entry| entry.created_at);
+
+    Ok(operations)
+}
+
+/// Fetches the CID and operation at the head of the DID's audit log. With
+/// `verify_chain`, the signatures along the log are checked first.
+pub fn did_plc_last_operation(
+    http_client: &Client,
+    plc_hostname: &str,
+    did: &str,
+    verify_chain: bool,
+) -> Result<(String, serde_json::Value)> {
+    let operations = did_plc_audit_log(http_client, plc_hostname, did)?;
+    verified_last_operation(did, operations, verify_chain)
+}
+
+/// Deserializes a JSON response body after checking its `Content-Type`.
+fn json_body<T: serde::de::DeserializeOwned>(response: Response) -> Result<T> {
+    read_response(response)?.json()
+}
+
+fn read_response(response: Response) -> Result<XrpcResponse> {
+    let url = response.url().clone();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes()?.to_vec();
+    Ok(XrpcResponse::new(url, status, headers, body))
+}
+
+/// Sends the request built by `make_request`, retrying connection failures,
+/// timeouts, 429s, and 5xx responses with the async client's backoff.
+fn send_with_backoff<F>(make_request: F) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        let result = make_request().send();
+
+        let retryable = match &result {
+            Ok(response) => is_retryable_status(response.status()),
+            Err(err) => err.is_connect() || err.is_timeout(),
+        };
+
+        if !retryable || attempt >= RETRY_ATTEMPTS {
+            return result.map_err(|err| err.into());
+        }
+
+        std::thread::sleep(retry_delay(attempt));
+        attempt += 1;
+    }
+}
+
+/// A PDS session with the methods of `tandem::xrpc::PdsClient`, which builds
+/// the same requests and reads their responses with the same code.
+pub struct PdsClient {
+    pub http_client: Client,
+    pub pds: String,
+    pub access_jwt: String,
+    refresh_jwt: Option<String>,
+    headers: HeaderMap,
+}
+
+impl PdsClient {
+    pub fn from_credentials(
+        http_client: &Client,
+        pds: &str,
+        did: &str,
+        password: &str,
+    ) -> Result<Self> {
+        let mut client = Self {
+            http_client: http_client.clone(),
+            pds: pds.to_string(),
+            access_jwt: String::new(),
+            refresh_jwt: None,
+            headers: HeaderMap::new(),
+        };
+
+        let (access_jwt, refresh_jwt) = client
+            .send(&XrpcCall::create_session(did, password), "")?
+            .session_tokens("com.atproto.server.createSession")?;
+        client.access_jwt = access_jwt;
+        client.refresh_jwt = refresh_jwt;
+        Ok(client)
+    }
+
+    /// Sends `call` with `token` as its bearer token, and with backoff when
+    /// the call is safe to repeat.
+    fn send(&self, call: &XrpcCall, token: &str) -> Result<XrpcResponse> {
+        let uri = call.url(&self.pds);
+        let headers = call.headers(&self.headers, token)?;
+        let make_request = || {
+            let request = self
+                .http_client
+                .request(call.method.clone(), &uri)
+                .headers(headers.clone())
+                .query(&call.query);
+            match &call.body {
+                Some(body) => request.json(body),
+                None => request,
+            }
+        };
+
+        let response = if call.retry {
+            send_with_backoff(make_request)?
+        } else {
+            make_request().send()?
+        };
+        read_response(response)
+    }
+
+    /// Exchanges the session's refresh token for new access and refresh
+    /// tokens with `com.atproto.server.refreshSession`.
+    pub fn refresh_session(&mut self) -> Result<()> {
+        let current_refresh_jwt = self
+            .refresh_jwt
+            .clone()
+            .ok_or_else(|| anyhow!("the PDS session cannot be refreshed"))?;
+
+        let (access_jwt, refresh_jwt) = self
+            .send(&XrpcCall::refresh_session(), &current_refresh_jwt)?
+            .refreshed_session_tokens()?;
+        self.access_jwt = access_jwt;
+        self.refresh_jwt = refresh_jwt.or(Some(current_refresh_jwt));
+        Ok(())
+    }
+
+    /// Sends `call`, refreshing the session and sending it once more when the
+    /// PDS reports an expired access token with a 401. Any other error status
+    /// is returned as an `XrpcRequestError`.
+    fn send_refreshing(&mut self, call: &XrpcCall) -> Result<XrpcResponse> {
+        let response = self.send(call, &self.access_jwt)?;
+        if let Some(response) = response.check_session_status(self.refresh_jwt.is_some())? {
+            return Ok(response);
+        }
+        self.refresh_session()?;
+        Ok(self.send(call, &self.access_jwt)?.check_status()?)
+    }
+
+    /// Creates a client without a session, for endpoints such as `listRepos`
+    /// that do not require authentication.
+    pub fn unauthenticated(http_client: &Client, pds: &str) -> Result<Self> {
+        Ok(Self {
+            http_client: http_client.clone(),
+            pds: pds_base_url(pds)?,
+            access_jwt: String::new(),
+            refresh_jwt: None,
+            headers: HeaderMap::new(),
+        })
+    }
+
+    /// Adds a header that is sent with every subsequent request to the PDS.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self> {
+        insert_header(&mut self.headers, name, value)?;
+        Ok(self)
+    }
+
+    /// Routes requests through the service identified by `proxy`, in the
+    /// `did:...#service_id` form expected by the `atproto-proxy` header.
+    pub fn with_atproto_proxy(self, proxy: &str) -> Result<Self> {
+        self.with_header("atproto-proxy", proxy)
+    }
+
+    /// Returns the rotation keys, handles, verification methods, and services
+    /// the PDS recommends for the account's DID.
+    pub fn get_recommended_did_credentials(&self) -> Result<RecommendedCredentials> {
+        self.send(
+            &XrpcCall::get_recommended_did_credentials(),
+            &self.access_jwt,
+        )?
+        .recommended_credentials()
+    }
+
+    /// Returns the DID document the PDS holds for the session's account.
+    pub fn get_session_did_document(&self) -> Result<Option<serde_json::Value>> {
+        self.send(&XrpcCall::get_session(), &self.access_jwt)?
+            .session_did_document()
+    }
+
+    /// Returns the DID a handle belongs to, or `None` when the PDS cannot
+    /// resolve it.
+    pub fn resolve_handle(&self, handle: &str) -> Result<Option<String>> {
+        self.send(&XrpcCall::resolve_handle(handle), &self.access_jwt)?
+            .resolved_handle(handle)
+    }
+
+    /// Returns one page of up to `limit` repositories hosted on the PDS and
+    /// the cursor for the next page, which is `None` after the last page.
+    pub fn list_repos(
+        &self,
+        cursor: Option<String>,
+        limit: u32,
+    ) -> Result<(Vec<RepoInfo>, Option<String>)> {
+        self.send(&XrpcCall::list_repos(cursor, limit), &self.access_jwt)?
+            .repo_list()
+    }
+
+    /// Deactivates the session's account with
+    /// `com.atproto.server.deactivateAccount`.
+    pub fn deactivate_account(&self) -> Result<()> {
+        self.send(&XrpcCall::deactivate_account(), &self.access_jwt)?
+            .check_status()?;
+        Ok(())
+    }
+
+    pub fn request_plc_op_sig(&self) -> Result<()> {
+        self.send(&XrpcCall::request_plc_op_sig(), &self.access_jwt)
+            .map(|_| ())
+    }
+
+    pub fn sign_plc_op(
+        &mut self,
+        did_doc: &serde_json::Value,
+        token: &str,
+    ) -> Result<serde_json::Value> {
+        self.send_refreshing(&XrpcCall::sign_plc_op(did_doc, token))?
+            .signed_operation()
+    }
+
+    pub fn submit_plc_op(&mut self, operation: &serde_json::Value) -> Result<()> {
+        self.send_refreshing(&XrpcCall::submit_plc_op(operation))
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc::operation_cid;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, body_partial_json, header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    /// Starts a mock server on a runtime of its own, leaving the test thread
+    /// outside any async context as blocking callers are.
+    fn mock_server(mocks: Vec<Mock>) -> (tokio::runtime::Runtime, MockServer) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime.block_on(async {
+            let server = MockServer::start().await;
+            for mock in mocks {
+                mock.mount(&server).await;
+            }
+            server
+        });
+        (runtime, server)
+    }
+
+    #[test]
+    fn plc_reads() -> Result<()> {
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+        let genesis = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {},
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {},
+            "prev": null,
+            "sig": "sig",
+        });
+        let genesis_cid = operation_cid(&genesis)?;
+        let update = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {},
+            "alsoKnownAs": ["at://bob.example.com"],
+            "services": {},
+            "prev": genesis_cid,
+            "sig": "sig",
+        });
+        let update_cid = operation_cid(&update)?;
+
+        let (_runtime, server) = mock_server(vec![
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", did)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "alsoKnownAs": ["at://alice.example.com", "https://alice.example.com"],
+                    "service": [{
+                        "id": "#atproto_pds",
+                        "type": "AtprotoPersonalDataServer",
+                        "serviceEndpoint": "https://pds.example.com",
+                    }],
+                }))),
+            Mock::given(method("GET"))
+                .and(path(format!("/{}/log/audit", did)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                    { "did": did, "operation": update, "cid": update_cid, "createdAt": "2024-01-01T00:01:00Z", "nullified": false },
+                    { "did": did, "operation": genesis, "cid": genesis_cid, "createdAt": "2024-01-01T00:00:00Z", "nullified": false },
+                ]))),
+            Mock::given(method("GET"))
+                .and(path(format!("/{}/data", did)))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_string("<html>not found</html>"),
+                ),
+        ]);
+        let http_client = Client::new();
+
+        let resolved = resolve_did_only(&http_client, &server.uri(), did)?;
+        assert_eq!(resolved.pds, "https://pds.example.com");
+        assert_eq!(resolved.unverified_handles, vec!["alice.example.com"]);
+
+        let (cid, operation) = did_plc_last_operation(&http_client, &server.uri(), did, false)?;
+        assert_eq!(cid, update_cid);
+        assert_eq!(operation, update);
+
+        let err = did_plc_data(&http_client, &server.uri(), did).unwrap_err();
+        assert!(format!("{:#}", err).contains("expected JSON"));
+        Ok(())
+    }
+
+    #[test]
+    fn pds_session_refreshes_and_reports_rejections() -> Result<()> {
+        let session = |access: &str, refresh: &str| {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "accessJwt": access,
+                "refreshJwt": refresh,
+                "handle": "alice.example.com",
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            }))
+        };
+        let (_runtime, server) = mock_server(vec![
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.server.createSession"))
+                .respond_with(ResponseTemplate::new(503))
+                .up_to_n_times(1)
+                .with_priority(1),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.server.createSession"))
+                .respond_with(session("access", "refresh")),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.server.refreshSession"))
+                .and(header("Authorization", "Bearer refresh"))
+                .respond_with(session("access2", "refresh2"))
+                .expect(1),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.identity.signPlcOperation"))
+                .and(header("Authorization", "Bearer access"))
+                .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                    "error": "ExpiredToken",
+                    "message": "Token has expired",
+                }))),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.identity.signPlcOperation"))
+                .and(header("Authorization", "Bearer access2"))
+                .and(body_partial_json(json!({ "token": "ABCDE-12345" })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "operation": { "type": "plc_operation", "sig": "sig" },
+                }))),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+                .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                    "error": "InvalidRequest",
+                    "message": "Invalid rotation key",
+                }))),
+        ]);
+
+        let mut pds_client = PdsClient::from_credentials(
+            &Client::new(),
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "password",
+        )?;
+        assert_eq!(pds_client.access_jwt, "access");
+
+        let operation = pds_client.sign_plc_op(&json!({ "alsoKnownAs": [] }), "ABCDE-12345")?;
+        assert_eq!(operation["sig"], "sig");
+        assert_eq!(pds_client.access_jwt, "access2");
+
+        let err = pds_client.submit_plc_op(&operation).unwrap_err();
+        let err = err.downcast_ref::<XrpcRequestError>().unwrap();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.error, "InvalidRequest");
+        Ok(())
+    }
+
+    #[test]
+    fn pds_reads_and_account_changes() -> Result<()> {
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+        let proxy = "did:web:api.example.com#atproto_labeler";
+        let (_runtime, server) =
+            mock_server(vec![
+            Mock::given(method("GET"))
+                .and(path("/xrpc/com.atproto.identity.resolveHandle"))
+                .and(query_param("handle", "alice.example.com"))
+                .and(header("atproto-proxy", proxy))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "did": did }))),
+            Mock::given(method("GET"))
+                .and(path("/xrpc/com.atproto.identity.resolveHandle"))
+                .and(query_param("handle", "free.example.com"))
+                .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                    "error": "InvalidRequest",
+                    "message": "Unable to resolve handle",
+                }))),
+            Mock::given(method("GET"))
+                .and(path("/xrpc/com.atproto.sync.listRepos"))
+                .and(query_param("limit", "2"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "cursor": "next",
+                    "repos": [{ "did": did, "head": "bafyhead", "rev": "3jzfcijpj2z2a" }],
+                }))),
+            Mock::given(method("GET"))
+                .and(path("/xrpc/com.atproto.identity.getRecommendedDidCredentials"))
+                .and(header("Authorization", "Bearer access"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+                    "alsoKnownAs": null,
+                    "verificationMethods": {},
+                    "services": {},
+                }))),
+            Mock::given(method("GET"))
+                .and(path("/xrpc/com.atproto.server.getSession"))
+                .and(header("Authorization", "Bearer access"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "did": did,
+                    "handle": "alice.example.com",
+                    "didDoc": { "id": did },
+                }))),
+            Mock::given(method("POST"))
+                .and(path("/xrpc/com.atproto.server.deactivateAccount"))
+                .and(header("Authorization", "Bearer access"))
+                .and(body_json(json!({})))
+                .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                    "error": "InvalidRequest",
+                    "message": "Account is already deactivated",
+                }))),
+        ]);
+
+        let pds_client =
+            PdsClient::unauthenticated(&Client::new(), &server.uri())?.with_atproto_proxy(proxy)?;
+        assert_eq!(
+            pds_client.resolve_handle("alice.example.com")?,
+            Some(did.to_string())
+        );
+        assert_eq!(pds_client.resolve_handle("free.example.com")?, None);
+        let (repos, cursor) = pds_client.list_repos(None, 2)?;
+        assert_eq!(repos[0].head, "bafyhead");
+        assert_eq!(cursor.as_deref(), Some("next"));
+
+        let pds_client = PdsClient {
+            access_jwt: "access".to_string(),
+            ..PdsClient::unauthenticated(&Client::new(), &server.uri())?
+        };
+        let credentials = pds_client.get_recommended_did_credentials()?;
+        assert!(credentials.also_known_as.is_empty());
+        assert_eq!(
+            pds_client.get_session_did_document()?,
+            Some(json!({ "id": did }))
+        );
+        let err = pds_client.deactivate_account().unwrap_err();
+        assert_eq!(err.downcast_ref::<XrpcRequestError>().unwrap().status, 400);
+        Ok(())
+    }
+}
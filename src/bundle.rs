@@ -0,0 +1,223 @@
+//! Portable snapshots of an identity's public state for backup, and checks of
+//! a snapshot against the live directory. Bundles never contain private keys.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::plc::{did_plc_audit_log, did_plc_data, AuditEntry};
+
+/// The bundle format version written by this release.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Document fields compared when checking a bundle for drift.
+const DOCUMENT_FIELDS: [&str; 4] = [
+    "rotationKeys",
+    "verificationMethods",
+    "alsoKnownAs",
+    "services",
+];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityBundle {
+    pub version: u32,
+    pub did: String,
+    pub exported_at: DateTime<Utc>,
+    pub document: serde_json::Value,
+    pub audit_log: Vec<AuditEntry>,
+    pub did_keys: Vec<String>,
+}
+
+impl IdentityBundle {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let bundle: IdentityBundle = serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse bundle {}", path.display()))?;
+        if bundle.version != BUNDLE_VERSION {
+            return Err(anyhow!(
+                "unsupported bundle version {} in {}",
+                bundle.version,
+                path.display()
+            ));
+        }
+        Ok(bundle)
+    }
+
+    /// Writes the bundle, refusing to replace an existing file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self).context("failed to serialize bundle")?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, contents.as_bytes()))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Gathers the current DID document and audit log of a DID into a bundle.
+pub async fn export_identity(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<IdentityBundle> {
+    let document = did_plc_data(http_client, plc_hostname, did)
+        .await
+        .context("failed to get DID document")?;
+    let audit_log = did_plc_audit_log(http_client, plc_hostname, did)
+        .await
+        .context("failed to get audit log")?;
+
+    Ok(IdentityBundle {
+        version: BUNDLE_VERSION,
+        did: did.to_string(),
+        exported_at: Utc::now(),
+        did_keys: document_did_keys(&document),
+        document,
+        audit_log,
+    })
+}
+
+/// Lists the public `did:key`s a document declares, rotation keys first.
+fn document_did_keys(document: &serde_json::Value) -> Vec<String> {
+    let rotation_keys = document["rotationKeys"].as_array().into_iter().flatten();
+    let verification_methods = document["verificationMethods"]
+        .as_object()
+        .into_iter()
+        .flat_map(|methods| methods.values());
+
+    let mut did_keys: Vec<String> = Vec::new();
+    for key in rotation_keys
+        .chain(verification_methods)
+        .filter_map(|key| key.as_str())
+    {
+        if !did_keys.iter().any(|existing| existing == key) {
+            did_keys.push(key.to_string());
+        }
+    }
+    did_keys
+}
+
+/// Describes how the live state of an identity differs from a bundle. An
+/// empty result means nothing has changed since the bundle was exported.
+pub fn bundle_drift(bundle: &IdentityBundle, live: &IdentityBundle) -> Vec<String> {
+    let mut drift = Vec::new();
+
+    if bundle.did != live.did {
+        drift.push(format!("DID changed from {} to {}", bundle.did, live.did));
+    }
+
+    for field in DOCUMENT_FIELDS {
+        if bundle.document[field] != live.document[field] {
+            drift.push(format!(
+                "{} changed from {} to {}",
+                field, bundle.document[field], live.document[field]
+            ));
+        }
+    }
+
+    for key in &bundle.did_keys {
+        if !live.did_keys.contains(key) {
+            drift.push(format!("key {} was removed", key));
+        }
+    }
+    for key in &live.did_keys {
+        if !bundle.did_keys.contains(key) {
+            drift.push(format!("key {} was added", key));
+        }
+    }
+
+    let bundled_cids = bundle.audit_log.iter().map(|entry| &entry.cid);
+    let live_cids = live
+        .audit_log
+        .iter()
+        .map(|entry| &entry.cid)
+        .collect::<Vec<&String>>();
+    for cid in bundled_cids {
+        if !live_cids.contains(&cid) {
+            drift.push(format!("operation {} is no longer in the audit log", cid));
+        }
+    }
+    let new_operations = live
+        .audit_log
+        .iter()
+        .filter(|entry| {
+            !bundle
+                .audit_log
+                .iter()
+                .any(|bundled| bundled.cid == entry.cid)
+        })
+        .count();
+    if new_operations > 0 {
+        drift.push(format!(
+            "{} operation(s) were added to the audit log",
+            new_operations
+        ));
+    }
+
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn bundle(also_known_as: &[&str], cids: &[&str]) -> IdentityBundle {
+        let document = json!({
+            "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "rotationKeys": ["did:key:zQ3shA", "did:key:zDnaeB"],
+            "verificationMethods": { "atproto": "did:key:zQ3shA" },
+            "alsoKnownAs": also_known_as,
+            "services": {},
+        });
+        IdentityBundle {
+            version: BUNDLE_VERSION,
+            did: "did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+            exported_at: Utc::now(),
+            did_keys: document_did_keys(&document),
+            document,
+            audit_log: cids
+                .iter()
+                .map(|cid| AuditEntry {
+                    operation: json!({}),
+                    cid: cid.to_string(),
+                    created_at: Utc::now(),
+                    nullified: false,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn bundle_keys_are_public_and_deduplicated() {
+        let bundle = bundle(&["at://alice.example.com"], &["bafyreia"]);
+        assert_eq!(bundle.did_keys, vec!["did:key:zQ3shA", "did:key:zDnaeB"]);
+
+        let serialized = serde_json::to_value(&bundle).unwrap();
+        assert_eq!(serialized["version"], 1);
+        assert!(serialized.get("auditLog").is_some());
+    }
+
+    #[test]
+    fn bundle_drifts() {
+        let exported = bundle(&["at://alice.example.com"], &["bafyreia"]);
+        assert!(bundle_drift(&exported, &exported.clone()).is_empty());
+
+        let live = bundle(
+            &["at://alice.example.com", "at://alice.example.net"],
+            &["bafyreia", "bafyreib"],
+        );
+        let drift = bundle_drift(&exported, &live);
+        assert_eq!(drift.len(), 2, "{:?}", drift);
+        assert!(drift[0].starts_with("alsoKnownAs changed"));
+        assert_eq!(drift[1], "1 operation(s) were added to the audit log");
+
+        let forked = bundle(&["at://alice.example.com"], &["bafyreic"]);
+        let drift = bundle_drift(&exported, &forked);
+        assert!(drift.contains(&"operation bafyreia is no longer in the audit log".to_string()));
+    }
+}
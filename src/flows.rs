@@ -0,0 +1,840 @@
+//! Prompt-free building blocks for the identity flows. Each step takes its
+//! inputs as parameters and returns a structured result, so the interactive
+//! actions, scripted callers, and tests all drive the same code.
+
+use anyhow::{anyhow, Context, Result};
+use json_patch::{patch, Patch};
+use serde_json::{from_value, json};
+
+use crate::{
+    crypto::{find_rotation_key, sign_operation, verify_operation},
+    multikey::{canonical_did_key, did_key_to_verifying_key},
+    plc::{
+        check_prepared_operation, check_rotation_key_capacity, check_services_preserved,
+        did_plc_audit_log, did_plc_last_operation, ensure_did_exists, find_audit_entry,
+        get_verification_methods, operation_cid, submit_operation, validate_audit_cids,
+        validate_operation, verify_audit_chain, ATPROTO_PDS_SERVICE, ATPROTO_VERIFICATION_METHOD,
+        MAX_ROTATION_KEYS,
+    },
+    resolve::{resolve_did_only, resolve_handle, ResolvedHandle},
+    signer::Signer,
+    xrpc::RecommendedCredentials,
+};
+
+/// The operation a new operation is built on.
+#[derive(Clone, Debug)]
+pub struct LastOperation {
+    pub cid: String,
+    pub operation: serde_json::Value,
+
+    /// False when a pinned `prev` is not the head, so building on it forks
+    /// the chain.
+    pub is_head: bool,
+}
+
+/// The result of appending a handle.
+#[derive(Clone, Debug)]
+pub enum AppendHandleOutcome {
+    /// The handle was already listed, so nothing was submitted.
+    AlreadyPresent,
+
+    /// The signed operation was accepted by the directory.
+    Submitted {
+        cid: String,
+        operation: serde_json::Value,
+    },
+}
+
+/// Resolves a handle, or a DID even when it lists no handle.
+pub async fn resolve(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    subject: &str,
+) -> Result<ResolvedHandle> {
+    if subject.starts_with("did:") {
+        resolve_did_only(http_client, plc_hostname, subject).await
+    } else {
+        resolve_handle(http_client, plc_hostname, subject).await
+    }
+}
+
+/// Fetches the head of the DID's audit log, or the operation `prev` names,
+/// after verifying the log's chain of operations and signatures.
+pub async fn last_operation(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+    prev: Option<&str>,
+) -> Result<LastOperation> {
+    ensure_did_exists(http_client, plc_hostname, did).await?;
+
+    match prev {
+        Some(prev) => {
+            let operations = did_plc_audit_log(http_client, plc_hostname, did).await?;
+            validate_audit_cids(&operations)
+                .and_then(|_| verify_audit_chain(&operations))
+                .with_context(|| format!("the audit log of {} failed verification", did))?;
+            let (entry, is_head) = find_audit_entry(&operations, prev)?;
+            Ok(LastOperation {
+                cid: entry.cid,
+                operation: entry.operation,
+                is_head,
+            })
+        }
+        None => {
+            let (cid, operation) =
+                did_plc_last_operation(http_client, plc_hostname, did, true).await?;
+            Ok(LastOperation {
+                cid,
+                operation,
+                is_head: true,
+            })
+        }
+    }
+}
+
+/// Builds the unsigned operation that appends `new_handle`, leaving every
+/// other field, including services, intact. Returns `None` when the handle is
+/// already listed, which happens when a previous submission succeeded but the
+/// response was lost.
+pub fn build_append_handle(
+    last: &LastOperation,
+    new_handle: &str,
+) -> Result<Option<serde_json::Value>> {
+    if contains_handle(&last.operation, new_handle) {
+        return Ok(None);
+    }
+
+    build_operation(
+        last,
+        json!([
+            { "op": "add", "path": "/alsoKnownAs/-", "value": format!("at://{}", new_handle) },
+        ]),
+    )
+    .map(Some)
+}
+
+/// Builds the unsigned operation that removes `handle` from wherever it is
+/// listed in `alsoKnownAs`, keeping the order of the other handles.
+pub fn build_remove_handle(last: &LastOperation, handle: &str) -> Result<serde_json::Value> {
+    let index = handle_position(&last.operation, handle)
+        .ok_or_else(|| anyhow!("{} is not one of this DID's handles", handle))?;
+
+    build_operation(
+        last,
+        json!([
+            { "op": "remove", "path": format!("/alsoKnownAs/{}", index) },
+        ]),
+    )
+}
+
+/// Builds the unsigned operation that replaces every handle with `handles`,
+/// primary first.
+pub fn build_set_handles(last: &LastOperation, handles: &[String]) -> Result<serde_json::Value> {
+    let also_known_as = handles
+        .iter()
+        .map(|handle| format!("at://{}", handle))
+        .collect::<Vec<String>>();
+
+    build_operation(
+        last,
+        json!([
+            { "op": "replace", "path": "/alsoKnownAs", "value": also_known_as },
+        ]),
+    )
+}
+
+/// Builds the unsigned operation that adds `recovery_key` as the first
+/// rotation key. Recovery keys are ordinary rotation keys; listing one first
+/// gives it the highest precedence, so within the 72 hour recovery window an
+/// operation it signs can override ones signed by any later key.
+pub fn build_add_recovery_key(
+    last: &LastOperation,
+    recovery_key: &str,
+) -> Result<serde_json::Value> {
+    if !recovery_key.starts_with("did:key:") {
+        return Err(anyhow!("recovery key {} must be a did:key", recovery_key));
+    }
+    did_key_to_verifying_key(recovery_key)?;
+
+    let rotation_keys = last.operation["rotationKeys"]
+        .as_array()
+        .ok_or_else(|| anyhow!("last operation has no rotationKeys"))?;
+    if rotation_keys
+        .iter()
+        .any(|key| key.as_str() == Some(recovery_key))
+    {
+        return Err(anyhow!(
+            "{} is already a rotation key; remove it before adding it as a recovery key",
+            recovery_key
+        ));
+    }
+    check_rotation_key_capacity(&last.operation, 1)?;
+
+    build_operation(
+        last,
+        json!([
+            { "op": "add", "path": "/rotationKeys/0", "value": recovery_key },
+        ]),
+    )
+}
+
+/// Builds the unsigned operation that removes the rotation key at `index`,
+/// keeping the precedence of the others. Removing the signer's own key is
+/// refused unless another valid rotation key remains, so the DID is never
+/// left without a key that can sign its next operation.
+pub fn build_remove_rotation_key(
+    last: &LastOperation,
+    index: usize,
+    signer_key: &str,
+) -> Result<serde_json::Value> {
+    let rotation_keys = last.operation["rotationKeys"]
+        .as_array()
+        .ok_or_else(|| anyhow!("last operation has no rotationKeys"))?
+        .iter()
+        .filter_map(|key| key.as_str())
+        .collect::<Vec<&str>>();
+    let removed = *rotation_keys
+        .get(index)
+        .ok_or_else(|| anyhow!("there is no rotation key at index {}", index))?;
+
+    let remaining = rotation_keys
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| *position != index)
+        .map(|(_, key)| *key)
+        .collect::<Vec<&str>>();
+    if remaining.is_empty() {
+        return Err(anyhow!(
+            "{} is the only rotation key and cannot be removed",
+            removed
+        ));
+    }
+    if find_rotation_key(signer_key, &[removed])?.is_some()
+        && !remaining
+            .iter()
+            .any(|key| did_key_to_verifying_key(key).is_ok())
+    {
+        return Err(anyhow!(
+            "{} signs this operation and no other valid rotation key would remain",
+            removed
+        ));
+    }
+
+    build_operation(
+        last,
+        json!([
+            { "op": "remove", "path": format!("/rotationKeys/{}", index) },
+        ]),
+    )
+}
+
+/// Builds the unsigned operation that moves the DID to a new PDS: the
+/// `atproto` verification method and `atproto_pds` service are taken from the
+/// destination's recommended credentials, and the rotation keys become
+/// `kept_rotation_keys`, in order, followed by the destination's recommended
+/// rotation keys. Handles and every other service are left as they are.
+pub fn build_migrate(
+    last: &LastOperation,
+    recommended: &RecommendedCredentials,
+    kept_rotation_keys: &[String],
+) -> Result<serde_json::Value> {
+    let signing_key = recommended
+        .verification_methods
+        .get(ATPROTO_VERIFICATION_METHOD)
+        .ok_or_else(|| anyhow!("destination PDS did not recommend an atproto signing key"))?;
+    let pds_service = recommended
+        .services
+        .get(ATPROTO_PDS_SERVICE)
+        .ok_or_else(|| {
+            anyhow!(
+                "destination PDS did not recommend an {} service endpoint",
+                ATPROTO_PDS_SERVICE
+            )
+        })?;
+    if recommended.rotation_keys.is_empty() {
+        return Err(anyhow!(
+            "destination PDS did not recommend any rotation keys"
+        ));
+    }
+
+    let mut rotation_keys = kept_rotation_keys.to_vec();
+    for key in &recommended.rotation_keys {
+        if !rotation_keys.contains(key) {
+            rotation_keys.push(key.clone());
+        }
+    }
+    if rotation_keys.len() > MAX_ROTATION_KEYS {
+        return Err(anyhow!(
+            "keeping {} rotation key(s) alongside the destination's {} would exceed the limit of {}; keep fewer keys",
+            kept_rotation_keys.len(),
+            rotation_keys.len() - kept_rotation_keys.len(),
+            MAX_ROTATION_KEYS
+        ));
+    }
+
+    build_operation_changing(
+        last,
+        json!([
+            { "op": "replace", "path": "/rotationKeys", "value": rotation_keys },
+            { "op": "add", "path": format!("/verificationMethods/{}", ATPROTO_VERIFICATION_METHOD), "value": signing_key },
+            { "op": "add", "path": format!("/services/{}", ATPROTO_PDS_SERVICE), "value": pds_service },
+        ]),
+        Some(ATPROTO_PDS_SERVICE),
+    )
+}
+
+/// A verification method whose key is not in the canonical `did:key` form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationMethodDrift {
+    pub id: String,
+    pub found: String,
+    pub canonical: String,
+}
+
+/// Lists the verification methods written in a legacy encoding, such as a
+/// bare `publicKeyMultibase` value or an uncompressed point. New operations
+/// rewrite these in the canonical form. Keys that cannot be decoded at all
+/// are left for `validate_operation` to report.
+pub fn verification_method_drift(operation: &serde_json::Value) -> Vec<VerificationMethodDrift> {
+    get_verification_methods(operation)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(id, found)| {
+            let canonical = canonical_did_key(&found).ok()?;
+            (canonical != found).then_some(VerificationMethodDrift {
+                id,
+                found,
+                canonical,
+            })
+        })
+        .collect()
+}
+
+/// A change to one named verification method.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationMethodEdit {
+    /// Adds the method, or replaces its key if it already exists.
+    Set {
+        id: String,
+        did_key: String,
+    },
+    Remove {
+        id: String,
+    },
+}
+
+/// Builds the unsigned operation that applies `edits` to the DID's
+/// verification methods. Every key must be a valid `did:key` point, and the
+/// `atproto` method can be replaced but never removed.
+pub fn build_edit_verification_methods(
+    last: &LastOperation,
+    edits: &[VerificationMethodEdit],
+) -> Result<serde_json::Value> {
+    let mut methods = get_verification_methods(&last.operation)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<Vec<String>>();
+
+    let mut changes = Vec::new();
+    for edit in edits {
+        match edit {
+            VerificationMethodEdit::Set { id, did_key } => {
+                check_verification_method_id(id)?;
+                if !did_key.starts_with("did:key:") {
+                    return Err(anyhow!("verification method {} must be a did:key", id));
+                }
+                did_key_to_verifying_key(did_key)?;
+                changes.push(json!({ "op": "add", "path": format!("/verificationMethods/{}", id), "value": did_key }));
+                if !methods.contains(id) {
+                    methods.push(id.clone());
+                }
+            }
+            VerificationMethodEdit::Remove { id } => {
+                if id == ATPROTO_VERIFICATION_METHOD {
+                    return Err(anyhow!(
+                        "the {} verification method cannot be removed, only replaced",
+                        ATPROTO_VERIFICATION_METHOD
+                    ));
+                }
+                if !methods.contains(id) {
+                    return Err(anyhow!("there is no verification method named {}", id));
+                }
+                changes.push(
+                    json!({ "op": "remove", "path": format!("/verificationMethods/{}", id) }),
+                );
+                methods.retain(|method| method != id);
+            }
+        }
+    }
+
+    build_operation(last, serde_json::Value::Array(changes))
+}
+
+/// Verification method ids are used as JSON pointer segments and `#` URI
+/// fragments, so they are limited to letters, digits, `_`, and `-`.
+fn check_verification_method_id(id: &str) -> Result<()> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(anyhow!(
+            "invalid verification method name {}: use letters, digits, _, and -",
+            id
+        ));
+    }
+    Ok(())
+}
+
+/// Applies `changes` to the last operation, then chains it to the last
+/// operation's CID, drops its signature, and rewrites any legacy
+/// verification method encodings in the canonical form.
+fn build_operation(last: &LastOperation, changes: serde_json::Value) -> Result<serde_json::Value> {
+    build_operation_changing(last, changes, None)
+}
+
+/// Like `build_operation`, but allows `service` to be changed.
+fn build_operation_changing(
+    last: &LastOperation,
+    changes: serde_json::Value,
+    service: Option<&str>,
+) -> Result<serde_json::Value> {
+    let mut changes: Vec<serde_json::Value> =
+        from_value(changes).context("failed to create patch")?;
+    changes.push(json!({ "op": "remove", "path": "/sig" }));
+    changes.push(json!({ "op": "replace", "path": "/prev", "value": last.cid }));
+    let operation_patch: Patch =
+        from_value(serde_json::Value::Array(changes)).context("failed to create patch")?;
+
+    let mut operation = last.operation.clone();
+    patch(&mut operation, &operation_patch)?;
+    for drift in verification_method_drift(&operation) {
+        operation["verificationMethods"][&drift.id] = json!(drift.canonical);
+    }
+    check_prepared_operation(&operation, &last.cid)?;
+    check_services_preserved(&last.operation, &operation, service)?;
+    Ok(operation)
+}
+
+/// Errors unless the signer's key is one of the rotation keys of the
+/// operation being replaced, since the directory rejects operations signed
+/// by any other key. Returns the key's precedence.
+pub fn check_signer_is_rotation_key(
+    signer: &dyn Signer,
+    last_operation: &serde_json::Value,
+) -> Result<usize> {
+    let did_key = signer.did_key();
+    let rotation_keys = last_operation["rotationKeys"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|key| key.as_str())
+        .collect::<Vec<&str>>();
+    find_rotation_key(&did_key, &rotation_keys)?.ok_or_else(|| {
+        anyhow!(
+            "{} is not one of this DID's rotation keys ({}), so the directory would reject an operation it signs",
+            did_key,
+            rotation_keys.join(", ")
+        )
+    })
+}
+
+/// Validates an unsigned operation, signs it, and checks the signature
+/// against the signer's key, so a faulty signer is caught before the
+/// operation is submitted.
+pub fn sign(signer: &dyn Signer, operation: &serde_json::Value) -> Result<serde_json::Value> {
+    validate_operation(operation)?;
+    let signed_operation = sign_operation(signer, operation)?;
+    verify_operation(&signed_operation, &signer.did_key())
+        .context("signed operation failed its self-check; nothing was submitted")?;
+    Ok(signed_operation)
+}
+
+/// Submits a signed operation, returning its CID.
+pub async fn submit(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+    signed_operation: &serde_json::Value,
+) -> Result<String> {
+    submit_operation(http_client, plc_hostname, did, signed_operation).await?;
+    operation_cid(signed_operation)
+}
+
+/// Appends `new_handle` to the DID's handles on top of the current head.
+pub async fn append_handle(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+    signer: &dyn Signer,
+    new_handle: &str,
+) -> Result<AppendHandleOutcome> {
+    let last = last_operation(http_client, plc_hostname, did, None).await?;
+    check_signer_is_rotation_key(signer, &last.operation)?;
+    let Some(operation) = build_append_handle(&last, new_handle)? else {
+        return Ok(AppendHandleOutcome::AlreadyPresent);
+    };
+    let signed_operation = sign(signer, &operation)?;
+    let cid = submit(http_client, plc_hostname, did, &signed_operation).await?;
+    Ok(AppendHandleOutcome::Submitted {
+        cid,
+        operation: signed_operation,
+    })
+}
+
+/// Returns true when the operation's `alsoKnownAs` already lists the handle.
+fn contains_handle(operation: &serde_json::Value, handle: &str) -> bool {
+    handle_position(operation, handle).is_some()
+}
+
+/// Finds the index in the operation's `alsoKnownAs` of a handle as it is
+/// displayed: without its `at://` prefix and in any case.
+pub fn handle_position(operation: &serde_json::Value, handle: &str) -> Option<usize> {
+    let handle = handle.strip_prefix("at://").unwrap_or(handle);
+    operation["alsoKnownAs"]
+        .as_array()?
+        .iter()
+        .position(|value| {
+            value.as_str().is_some_and(|value| {
+                value
+                    .strip_prefix("at://")
+                    .unwrap_or(value)
+                    .eq_ignore_ascii_case(handle)
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plc::get_services;
+
+    #[test]
+    fn handle_already_present() {
+        let operation = json!({
+            "type": "plc_operation",
+            "alsoKnownAs": ["at://alice.example.com", "at://alice.test.example"],
+            "prev": "bafyreid",
+        });
+        assert!(contains_handle(&operation, "alice.example.com"));
+        assert!(contains_handle(&operation, "alice.test.example"));
+    }
+
+    #[test]
+    fn handle_not_present() {
+        let operation = json!({
+            "type": "plc_operation",
+            "alsoKnownAs": ["at://alice.example.com"],
+            "prev": "bafyreid",
+        });
+        assert!(!contains_handle(&operation, "bob.example.com"));
+        assert!(!contains_handle(&json!({}), "alice.example.com"));
+    }
+
+    #[test]
+    fn handle_positions() {
+        let operation = json!({
+            "alsoKnownAs": ["at://alice.example.com", "alice.example.net", "at://Alice.Test"],
+        });
+        assert_eq!(handle_position(&operation, "alice.example.com"), Some(0));
+        assert_eq!(
+            handle_position(&operation, "at://alice.example.net"),
+            Some(1)
+        );
+        assert_eq!(handle_position(&operation, "alice.test"), Some(2));
+        assert_eq!(handle_position(&operation, "alice.example.org"), None);
+        assert_eq!(handle_position(&json!({}), "alice.example.com"), None);
+
+        let mut last = multi_key_operation();
+        last.operation["alsoKnownAs"] = operation["alsoKnownAs"].clone();
+        let operation = build_remove_handle(&last, "alice.example.net").unwrap();
+        assert_eq!(
+            operation["alsoKnownAs"],
+            json!(["at://alice.example.com", "at://Alice.Test"])
+        );
+        assert!(build_remove_handle(&last, "alice.example.org").is_err());
+    }
+
+    #[test]
+    fn append_handle_preserves_services() {
+        let last = LastOperation {
+            cid: "bafyreid".to_string(),
+            operation: json!({
+                "type": "plc_operation",
+                "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+                "verificationMethods": {
+                    "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+                },
+                "alsoKnownAs": ["at://alice.example.com"],
+                "services": {
+                    "atproto_pds": {
+                        "type": "AtprotoPersonalDataServer",
+                        "endpoint": "https://pds.example.com"
+                    },
+                    "atproto_labeler": {
+                        "type": "AtprotoLabeler",
+                        "endpoint": "https://labeler.example.com"
+                    }
+                },
+                "prev": null,
+                "sig": "abc"
+            }),
+            is_head: true,
+        };
+
+        let operation = build_append_handle(&last, "alice.example.net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            get_services(&operation).unwrap(),
+            get_services(&last.operation).unwrap()
+        );
+        assert_eq!(
+            operation["alsoKnownAs"],
+            json!(["at://alice.example.com", "at://alice.example.net"])
+        );
+        assert_eq!(operation["prev"], json!("bafyreid"));
+        assert!(operation.get("sig").is_none());
+
+        assert!(build_append_handle(&last, "Alice.Example.com")
+            .unwrap()
+            .is_none());
+    }
+
+    fn multi_key_operation() -> LastOperation {
+        LastOperation {
+            cid: "bafyreid".to_string(),
+            operation: json!({
+                "type": "plc_operation",
+                "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+                "verificationMethods": {
+                    "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+                },
+                "alsoKnownAs": ["at://alice.example.com"],
+                "services": {
+                    "atproto_pds": {
+                        "type": "AtprotoPersonalDataServer",
+                        "endpoint": "https://pds.example.com"
+                    }
+                },
+                "prev": null,
+                "sig": "abc"
+            }),
+            is_head: true,
+        }
+    }
+
+    #[test]
+    fn atproto_method_cannot_be_removed() {
+        let last = multi_key_operation();
+        let err = build_edit_verification_methods(
+            &last,
+            &[VerificationMethodEdit::Remove {
+                id: "atproto".to_string(),
+            }],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the atproto verification method cannot be removed, only replaced"
+        );
+
+        assert!(build_edit_verification_methods(
+            &last,
+            &[VerificationMethodEdit::Remove {
+                id: "atproto_label".to_string(),
+            }],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn added_verification_method_survives() {
+        let label_key = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        let mut last = multi_key_operation();
+        let operation = build_edit_verification_methods(
+            &last,
+            &[VerificationMethodEdit::Set {
+                id: "atproto_label".to_string(),
+                did_key: label_key.to_string(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            operation["verificationMethods"],
+            json!({
+                "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF",
+                "atproto_label": label_key
+            })
+        );
+
+        // Later operations built on it keep the custom method.
+        last.operation = operation;
+        last.operation["sig"] = json!("abc");
+        let operation = build_append_handle(&last, "alice.example.net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(operation["verificationMethods"]["atproto_label"], label_key);
+
+        assert!(build_edit_verification_methods(
+            &last,
+            &[VerificationMethodEdit::Set {
+                id: "atproto/label".to_string(),
+                did_key: label_key.to_string(),
+            }],
+        )
+        .is_err());
+        assert!(build_edit_verification_methods(
+            &last,
+            &[VerificationMethodEdit::Set {
+                id: "atproto_label".to_string(),
+                did_key: "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGb".to_string(),
+            }],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn recovery_key_takes_precedence() {
+        let recovery_key = "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo";
+        let mut last = multi_key_operation();
+        let operation = build_add_recovery_key(&last, recovery_key).unwrap();
+        assert_eq!(
+            operation["rotationKeys"],
+            json!([
+                recovery_key,
+                "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"
+            ])
+        );
+
+        assert!(build_add_recovery_key(
+            &last,
+            "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"
+        )
+        .unwrap_err()
+        .to_string()
+        .contains("is already a rotation key"));
+
+        last.operation["rotationKeys"] = json!([
+            "did:key:zQ3shA",
+            "did:key:zQ3shB",
+            "did:key:zQ3shC",
+            "did:key:zQ3shD",
+            "did:key:zQ3shE"
+        ]);
+        assert!(build_add_recovery_key(&last, recovery_key)
+            .unwrap_err()
+            .to_string()
+            .starts_with("DID document already has 5 of 5 rotation keys"));
+    }
+
+    #[test]
+    fn remove_rotation_keys() {
+        let signer_key = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        let other_key = "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo";
+        let mut last = multi_key_operation();
+
+        assert_eq!(
+            build_remove_rotation_key(&last, 0, signer_key)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "{} is the only rotation key and cannot be removed",
+                signer_key
+            )
+        );
+
+        last.operation["rotationKeys"] = json!([other_key, signer_key]);
+        let operation = build_remove_rotation_key(&last, 0, signer_key).unwrap();
+        assert_eq!(operation["rotationKeys"], json!([signer_key]));
+        assert_eq!(operation["prev"], "bafyreid");
+        assert!(operation.get("sig").is_none());
+
+        // The signer may remove its own key while a valid key remains.
+        let operation = build_remove_rotation_key(&last, 1, signer_key).unwrap();
+        assert_eq!(operation["rotationKeys"], json!([other_key]));
+
+        last.operation["rotationKeys"] = json!(["did:key:zQ3shA", signer_key]);
+        assert!(build_remove_rotation_key(&last, 1, signer_key)
+            .unwrap_err()
+            .to_string()
+            .contains("no other valid rotation key would remain"));
+        assert!(build_remove_rotation_key(&last, 2, signer_key).is_err());
+    }
+
+    #[test]
+    fn legacy_verification_methods_are_normalized() {
+        let canonical = "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF";
+        let mut last = multi_key_operation();
+        assert!(verification_method_drift(&last.operation).is_empty());
+
+        last.operation["verificationMethods"]["atproto"] =
+            json!(canonical.strip_prefix("did:key:").unwrap());
+        assert_eq!(
+            verification_method_drift(&last.operation),
+            vec![VerificationMethodDrift {
+                id: "atproto".to_string(),
+                found: canonical.strip_prefix("did:key:").unwrap().to_string(),
+                canonical: canonical.to_string(),
+            }]
+        );
+
+        let operation = build_append_handle(&last, "alice.example.net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(operation["verificationMethods"]["atproto"], canonical);
+        assert!(verification_method_drift(&operation).is_empty());
+    }
+
+    #[test]
+    fn migrate_swaps_pds_credentials() {
+        let user_key = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        let destination_key = "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo";
+        let mut last = multi_key_operation();
+        last.operation["services"]["atproto_labeler"] = json!({
+            "type": "AtprotoLabeler",
+            "endpoint": "https://labeler.example.com"
+        });
+        let recommended: RecommendedCredentials = from_value(json!({
+            "rotationKeys": [destination_key],
+            "alsoKnownAs": ["at://alice.example.com"],
+            "verificationMethods": { "atproto": destination_key },
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds2.example.com"
+                }
+            }
+        }))
+        .unwrap();
+
+        let operation = build_migrate(&last, &recommended, &[user_key.to_string()]).unwrap();
+        assert_eq!(
+            operation["rotationKeys"],
+            json!([user_key, destination_key])
+        );
+        assert_eq!(operation["verificationMethods"]["atproto"], destination_key);
+        assert_eq!(
+            operation["services"]["atproto_pds"]["endpoint"],
+            "https://pds2.example.com"
+        );
+        assert_eq!(
+            operation["services"]["atproto_labeler"],
+            last.operation["services"]["atproto_labeler"]
+        );
+        assert_eq!(operation["alsoKnownAs"], last.operation["alsoKnownAs"]);
+        assert_eq!(operation["prev"], "bafyreid");
+
+        // Keys already kept are not listed twice.
+        let operation = build_migrate(&last, &recommended, &[destination_key.to_string()]).unwrap();
+        assert_eq!(operation["rotationKeys"], json!([destination_key]));
+
+        let kept = vec![user_key.to_string(); 5];
+        assert!(build_migrate(&last, &recommended, &kept).is_err());
+
+        let mut no_endpoint = recommended.clone();
+        no_endpoint.services.clear();
+        assert!(build_migrate(&last, &no_endpoint, &[]).is_err());
+    }
+}
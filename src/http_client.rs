@@ -0,0 +1,249 @@
+//! The HTTP client shared by every command, configured from the TLS flags.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+
+use crate::trace::{enable_debug_responses, RequestTracer, TraceTarget};
+
+/// The oldest TLS version a connection may negotiate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinTlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl MinTlsVersion {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "1.2" => Ok(MinTlsVersion::Tls12),
+            "1.3" => Ok(MinTlsVersion::Tls13),
+            name => Err(anyhow!("unsupported --min-tls={}: use 1.2 or 1.3", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MinTlsVersion::Tls12 => "1.2",
+            MinTlsVersion::Tls13 => "1.3",
+        }
+    }
+
+    fn version(&self) -> reqwest::tls::Version {
+        match self {
+            MinTlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            MinTlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+/// Which certificate authorities HTTPS connections trust, and how old a TLS
+/// version they accept.
+#[derive(Clone, Debug)]
+pub struct HttpClientOptions {
+    /// Certificate authorities trusted in addition to, or with
+    /// `--no-system-roots` instead of, the system store.
+    pub ca_certificates: Vec<PathBuf>,
+
+    /// Trust the platform's root certificate store.
+    pub system_roots: bool,
+
+    /// Connections that would negotiate an older TLS version fail.
+    pub min_tls: MinTlsVersion,
+
+    /// Where every request is traced, for `--trace-requests`.
+    pub trace_requests: Option<TraceTarget>,
+
+    /// Print the full account and session responses, for `--debug-responses`.
+    pub debug_responses: bool,
+}
+
+impl Default for HttpClientOptions {
+    fn default() -> Self {
+        Self {
+            ca_certificates: Vec::new(),
+            system_roots: true,
+            min_tls: MinTlsVersion::default(),
+            trace_requests: None,
+            debug_responses: false,
+        }
+    }
+}
+
+impl HttpClientOptions {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut options = HttpClientOptions::default();
+
+        for arg in args {
+            if arg == "--use-system-roots" {
+                options.system_roots = true;
+            } else if arg == "--no-system-roots" {
+                options.system_roots = false;
+            } else if arg == "--debug-responses" {
+                options.debug_responses = true;
+            } else if arg == "--trace-requests" {
+                options.trace_requests = Some(TraceTarget::Stderr);
+            } else if let Some(trace_file) = arg.strip_prefix("--trace-requests=") {
+                options.trace_requests = Some(TraceTarget::File(PathBuf::from(trace_file)));
+            } else if let Some(min_tls) = arg.strip_prefix("--min-tls=") {
+                options.min_tls = MinTlsVersion::from_name(min_tls)?;
+            } else if let Some(ca_certificate) = arg.strip_prefix("--ca-certificate=") {
+                options.ca_certificates.push(PathBuf::from(ca_certificate));
+            }
+        }
+
+        if !options.system_roots && options.ca_certificates.is_empty() {
+            return Err(anyhow!(
+                "--no-system-roots requires at least one --ca-certificate to trust"
+            ));
+        }
+
+        Ok(options)
+    }
+
+    /// Builds the client, installing the request tracer when
+    /// `--trace-requests` was given and enabling `--debug-responses`.
+    pub fn build(&self) -> Result<reqwest::Client> {
+        if let Some(target) = &self.trace_requests {
+            RequestTracer::for_target(target)?.install();
+        }
+        if self.debug_responses {
+            enable_debug_responses();
+        }
+
+        let mut client_builder = reqwest::Client::builder()
+            .tls_built_in_root_certs(self.system_roots)
+            .min_tls_version(self.min_tls.version());
+
+        for ca_certificate in &self.ca_certificates {
+            let cert_data = std::fs::read(ca_certificate).with_context(|| {
+                format!(
+                    "failed to read CA certificate: {}",
+                    ca_certificate.display()
+                )
+            })?;
+            let cert = reqwest::Certificate::from_pem(&cert_data).with_context(|| {
+                format!(
+                    "failed to parse CA certificate: {}",
+                    ca_certificate.display()
+                )
+            })?;
+            client_builder = client_builder.add_root_certificate(cert);
+        }
+
+        client_builder.build().with_context(|| {
+            format!(
+                "failed to create HTTP client; the platform's TLS backend may not support --min-tls={}",
+                self.min_tls.name()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    const TEST_CA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/ca.pem");
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// Serves `connections` HTTPS requests with a certificate issued by the
+    /// test CA, returning the server's URL.
+    fn serve_tls(connections: usize) -> String {
+        let identity = native_tls::Identity::from_pkcs8(
+            include_bytes!("../testdata/tls/localhost.pem"),
+            include_bytes!("../testdata/tls/localhost.key"),
+        )
+        .unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://127.0.0.1:{}/",
+            listener.local_addr().unwrap().port()
+        );
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let Ok(mut stream) = acceptor.accept(stream.unwrap()) else {
+                    continue;
+                };
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                );
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn system_roots_options() {
+        let options = HttpClientOptions::from_args(&args(&[])).unwrap();
+        assert!(options.system_roots);
+        assert!(options.ca_certificates.is_empty());
+
+        let options =
+            HttpClientOptions::from_args(&args(&["--ca-certificate=ca.pem", "--no-system-roots"]))
+                .unwrap();
+        assert!(!options.system_roots);
+        assert_eq!(options.ca_certificates, vec![PathBuf::from("ca.pem")]);
+
+        let err = HttpClientOptions::from_args(&args(&["--no-system-roots"])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("requires at least one --ca-certificate"));
+    }
+
+    #[test]
+    fn min_tls_options() {
+        let options = HttpClientOptions::from_args(&args(&[])).unwrap();
+        assert_eq!(options.min_tls, MinTlsVersion::Tls12);
+        assert!(options.build().is_ok());
+
+        let options = HttpClientOptions::from_args(&args(&["--min-tls=1.3"])).unwrap();
+        assert_eq!(options.min_tls, MinTlsVersion::Tls13);
+
+        let err = HttpClientOptions::from_args(&args(&["--min-tls=1.1"])).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported --min-tls=1.1: use 1.2 or 1.3");
+    }
+
+    #[tokio::test]
+    async fn custom_roots_are_added_to_system_roots() -> Result<()> {
+        let url = serve_tls(3);
+
+        let with_system_roots =
+            HttpClientOptions::from_args(&args(&[&format!("--ca-certificate={}", TEST_CA)]))?
+                .build()?;
+        assert!(with_system_roots
+            .get(&url)
+            .send()
+            .await?
+            .status()
+            .is_success());
+
+        let custom_only = HttpClientOptions::from_args(&args(&[
+            &format!("--ca-certificate={}", TEST_CA),
+            "--no-system-roots",
+        ]))?
+        .build()?;
+        assert!(custom_only.get(&url).send().await?.status().is_success());
+
+        let system_only = HttpClientOptions::from_args(&args(&[]))?.build()?;
+        assert!(system_only.get(&url).send().await.is_err());
+
+        Ok(())
+    }
+}
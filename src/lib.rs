@@ -1,5 +1,17 @@
 pub mod actions;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod bundle;
 pub(crate) mod crypto;
-pub(crate) mod plc;
-pub(crate) mod resolve;
+pub mod flows;
+pub mod http_client;
+pub mod multikey;
+pub mod output;
+pub mod plc;
+pub(crate) mod rate_limit;
+pub mod receipt;
+pub mod resolve;
+pub mod signer;
+pub(crate) mod state;
+pub mod trace;
 pub(crate) mod xrpc;
@@ -0,0 +1,230 @@
+//! Decoding of `did:key` values into their curve and public key.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// The multicodec prefix of a secp256k1 public key.
+pub const K256_MULTICODEC: [u8; 2] = [0xe7, 0x01];
+
+/// The multicodec prefix of a P-256 public key.
+pub const P256_MULTICODEC: [u8; 2] = [0x80, 0x24];
+
+/// The curves atproto accepts for `did:key` values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    K256,
+    P256,
+}
+
+impl Curve {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::K256 => "k256",
+            Curve::P256 => "p256",
+        }
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A `did:key` split into its curve and compressed SEC1 public key bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedDidKey {
+    pub curve: Curve,
+    pub public_key: Vec<u8>,
+}
+
+impl DecodedDidKey {
+    /// The public key as lowercase hex.
+    pub fn public_key_hex(&self) -> String {
+        self.public_key
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// A public key that signatures can be verified against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyingKey {
+    K256(::k256::ecdsa::VerifyingKey),
+    P256(::p256::ecdsa::VerifyingKey),
+}
+
+impl VerifyingKey {
+    pub fn curve(&self) -> Curve {
+        match self {
+            VerifyingKey::K256(_) => Curve::K256,
+            VerifyingKey::P256(_) => Curve::P256,
+        }
+    }
+
+    /// Verifies a 64 byte `r || s` signature over `content`.
+    pub fn verify(&self, content: &[u8], signature: &[u8]) -> Result<()> {
+        match self {
+            VerifyingKey::K256(key) => {
+                let signature = ::k256::ecdsa::Signature::from_slice(signature)?;
+                ecdsa::signature::Verifier::verify(key, content, &signature)?;
+            }
+            VerifyingKey::P256(key) => {
+                let signature = ::p256::ecdsa::Signature::from_slice(signature)?;
+                ecdsa::signature::Verifier::verify(key, content, &signature)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Multibase-decodes a `did:key`, with or without its `did:key:` prefix, and
+/// identifies its curve from the multicodec prefix. The point itself is not
+/// checked; see `did_key_to_verifying_key`.
+pub fn decode_did_key(did_key: &str) -> Result<DecodedDidKey> {
+    let encoded = did_key.strip_prefix("did:key:").unwrap_or(did_key);
+    let (_, decoded) = multibase::decode(encoded)
+        .map_err(|err| anyhow!("invalid multibase in did:key {}: {}", did_key, err))?;
+
+    let curve = match decoded.get(..2) {
+        Some(prefix) if prefix == K256_MULTICODEC => Curve::K256,
+        Some(prefix) if prefix == P256_MULTICODEC => Curve::P256,
+        Some(prefix) => {
+            return Err(anyhow!(
+                "unsupported multicodec prefix {:02x}{:02x} in did:key {}",
+                prefix[0],
+                prefix[1],
+                did_key
+            ))
+        }
+        None => return Err(anyhow!("did:key {} is too short", did_key)),
+    };
+
+    Ok(DecodedDidKey {
+        curve,
+        public_key: decoded[2..].to_vec(),
+    })
+}
+
+/// Decodes a `did:key` into a verifying key, failing when the public key is
+/// not a valid point on its curve.
+pub fn did_key_to_verifying_key(did_key: &str) -> Result<VerifyingKey> {
+    let decoded = decode_did_key(did_key)?;
+    let invalid_point = |_| anyhow!("did:key {} is not a valid {} point", did_key, decoded.curve);
+    Ok(match decoded.curve {
+        Curve::K256 => VerifyingKey::K256(
+            ::k256::ecdsa::VerifyingKey::from_sec1_bytes(&decoded.public_key)
+                .map_err(invalid_point)?,
+        ),
+        Curve::P256 => VerifyingKey::P256(
+            ::p256::ecdsa::VerifyingKey::from_sec1_bytes(&decoded.public_key)
+                .map_err(invalid_point)?,
+        ),
+    })
+}
+
+/// Re-encodes a public key in the canonical `did:key` form: the `did:key:`
+/// prefix, base58btc, and a compressed point. Keys written by older software
+/// may be bare `publicKeyMultibase` values, use another multibase, or hold an
+/// uncompressed point; all of these name the same key.
+pub fn canonical_did_key(key: &str) -> Result<String> {
+    let compressed = match did_key_to_verifying_key(key)? {
+        VerifyingKey::K256(key) => (K256_MULTICODEC, key.to_encoded_point(true).to_bytes()),
+        VerifyingKey::P256(key) => (P256_MULTICODEC, key.to_encoded_point(true).to_bytes()),
+    };
+    Ok(format!(
+        "did:key:{}",
+        crate::crypto::encode_multikey(&compressed.0, &compressed.1, None)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::encode_multikey;
+
+    #[test]
+    fn decode_did_keys() -> Result<()> {
+        let (_, k256_key) = crate::crypto::k256::gen_key(crate::crypto::KeyFormat::Jwk)?;
+        let decoded = decode_did_key(&format!("did:key:{}", k256_key))?;
+        assert_eq!(decoded.curve, Curve::K256);
+        assert_eq!(decoded.public_key.len(), 33);
+        assert_eq!(decode_did_key(&k256_key)?, decoded);
+        assert_eq!(did_key_to_verifying_key(&k256_key)?.curve(), Curve::K256);
+
+        let (_, p256_key) = crate::crypto::p256::gen_key(crate::crypto::KeyFormat::Jwk)?;
+        let decoded = decode_did_key(&p256_key)?;
+        assert_eq!(decoded.curve, Curve::P256);
+        assert_eq!(decoded.public_key_hex().len(), 66);
+        assert_eq!(did_key_to_verifying_key(&p256_key)?.curve(), Curve::P256);
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_invalid_did_keys() {
+        let ed25519 = encode_multikey(&[0xed, 0x01], &[0x02; 32], None);
+        assert!(decode_did_key(&ed25519)
+            .unwrap_err()
+            .to_string()
+            .starts_with("unsupported multicodec prefix ed01"));
+
+        // The x coordinate is larger than the field modulus.
+        let not_a_point = encode_multikey(
+            &P256_MULTICODEC,
+            &[[0x02].as_slice(), &[0xff; 32]].concat(),
+            None,
+        );
+        assert_eq!(decode_did_key(&not_a_point).unwrap().curve, Curve::P256);
+        assert_eq!(
+            did_key_to_verifying_key(&not_a_point)
+                .unwrap_err()
+                .to_string(),
+            format!("did:key {} is not a valid p256 point", not_a_point)
+        );
+
+        assert!(decode_did_key("did:key:!").is_err());
+    }
+
+    #[test]
+    fn canonical_did_keys() -> Result<()> {
+        let canonical = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        assert_eq!(canonical_did_key(canonical)?, canonical);
+
+        // A bare publicKeyMultibase value.
+        assert_eq!(
+            canonical_did_key(canonical.strip_prefix("did:key:").unwrap())?,
+            canonical
+        );
+
+        // The same key in base32 and as an uncompressed point.
+        let decoded = decode_did_key(canonical)?;
+        let base32 = encode_multikey(
+            &K256_MULTICODEC,
+            &decoded.public_key,
+            Some(multibase::Base::Base32Lower),
+        );
+        assert_eq!(
+            canonical_did_key(&format!("did:key:{}", base32))?,
+            canonical
+        );
+        let VerifyingKey::K256(key) = did_key_to_verifying_key(canonical)? else {
+            unreachable!()
+        };
+        let uncompressed = encode_multikey(
+            &K256_MULTICODEC,
+            key.to_encoded_point(false).as_bytes(),
+            None,
+        );
+        assert_eq!(
+            canonical_did_key(&format!("did:key:{}", uncompressed))?,
+            canonical
+        );
+
+        assert!(
+            canonical_did_key("did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGb").is_err()
+        );
+        Ok(())
+    }
+}
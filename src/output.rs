@@ -0,0 +1,284 @@
+//! Machine-readable output for the read-only commands under `--format json`.
+//!
+//! The structs here are a contract for tools built on tandem: fields are only
+//! added, never renamed or removed, without bumping `OUTPUT_SCHEMA_VERSION`.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::{
+    multikey::decode_did_key,
+    plc::{did_plc_data, get_verification_methods},
+    resolve::{ResolvedHandle, Resolver},
+};
+
+/// The version of the JSON output schema. Bumped on breaking changes.
+pub const OUTPUT_SCHEMA_VERSION: u32 = 1;
+
+/// How a read-only command prints its result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    /// Reads `--format json`, `--format=json`, `--json`, or the `text`
+    /// equivalents.
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut remaining = args.iter();
+        while let Some(arg) = remaining.next() {
+            let format = if arg == "--json" {
+                "json"
+            } else if arg == "--format" {
+                remaining
+                    .next()
+                    .ok_or_else(|| anyhow!("--format requires json or text"))?
+                    .as_str()
+            } else if let Some(format) = arg.strip_prefix("--format=") {
+                format
+            } else {
+                continue;
+            };
+            return match format {
+                "json" => Ok(OutputFormat::Json),
+                "text" => Ok(OutputFormat::Text),
+                format => Err(anyhow!(
+                    "unknown output format {}: use json or text",
+                    format
+                )),
+            };
+        }
+        Ok(OutputFormat::Text)
+    }
+}
+
+/// The result of `tandem resolve --format json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ResolveOutput {
+    pub version: u32,
+    pub did: String,
+    pub pds: String,
+    pub handles: Vec<HandleOutput>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HandleOutput {
+    pub handle: String,
+    pub dns_verified: bool,
+    pub http_verified: bool,
+}
+
+impl ResolveOutput {
+    /// Checks each handle the DID claims, verified or not, with DNS and
+    /// HTTPS resolution against the DID.
+    pub async fn verify(resolver: &Resolver, resolved: &ResolvedHandle) -> Self {
+        let mut handles = Vec::new();
+        for handle in resolved.handles.iter().chain(&resolved.unverified_handles) {
+            let verification = resolver.verify_handle(handle, &resolved.did).await;
+            handles.push(HandleOutput {
+                handle: handle.clone(),
+                dns_verified: verification.dns_verified,
+                http_verified: verification.http_verified,
+            });
+        }
+        handles.sort_by(|a, b| a.handle.cmp(&b.handle));
+
+        Self {
+            version: OUTPUT_SCHEMA_VERSION,
+            did: resolved.did.clone(),
+            pds: resolved.pds.clone(),
+            handles,
+        }
+    }
+}
+
+/// The result of `tandem inspect --format json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InspectOutput {
+    pub version: u32,
+    pub did: String,
+    pub pds: Option<String>,
+
+    /// Handles without their `at://` prefix, primary first.
+    pub handles: Vec<String>,
+
+    /// Highest precedence first.
+    pub rotation_keys: Vec<KeyOutput>,
+    pub verification_methods: Vec<VerificationMethodOutput>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct KeyOutput {
+    pub did_key: String,
+
+    /// `k256` or `p256`, or `None` when the key is not recognized.
+    pub curve: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VerificationMethodOutput {
+    pub id: String,
+    #[serde(flatten)]
+    pub key: KeyOutput,
+}
+
+impl InspectOutput {
+    /// Fetches the DID's current document from the directory.
+    pub async fn fetch(
+        http_client: &reqwest::Client,
+        plc_hostname: &str,
+        did: &str,
+    ) -> Result<Self> {
+        let document = did_plc_data(http_client, plc_hostname, did).await?;
+        Self::from_document(did, &document)
+    }
+
+    /// Summarizes a DID document's current state.
+    pub fn from_document(did: &str, document: &serde_json::Value) -> Result<Self> {
+        let handles = document["alsoKnownAs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|handle| handle.as_str()?.strip_prefix("at://"))
+            .map(str::to_string)
+            .collect();
+        let rotation_keys = document["rotationKeys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|key| key.as_str())
+            .map(KeyOutput::new)
+            .collect();
+        let verification_methods = get_verification_methods(document)?
+            .into_iter()
+            .map(|(id, did_key)| VerificationMethodOutput {
+                id,
+                key: KeyOutput::new(&did_key),
+            })
+            .collect();
+
+        Ok(Self {
+            version: OUTPUT_SCHEMA_VERSION,
+            did: did.to_string(),
+            pds: document["services"]["atproto_pds"]["endpoint"]
+                .as_str()
+                .map(str::to_string),
+            handles,
+            rotation_keys,
+            verification_methods,
+        })
+    }
+}
+
+impl KeyOutput {
+    fn new(did_key: &str) -> Self {
+        Self {
+            did_key: did_key.to_string(),
+            curve: decode_did_key(did_key)
+                .ok()
+                .map(|decoded| decoded.curve.name().to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn output_formats() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            OutputFormat::from_args(&args(&[])).unwrap(),
+            OutputFormat::Text
+        );
+        assert_eq!(
+            OutputFormat::from_args(&args(&["--format", "json", "alice.example.com"])).unwrap(),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            OutputFormat::from_args(&args(&["--format=text"])).unwrap(),
+            OutputFormat::Text
+        );
+        assert_eq!(
+            OutputFormat::from_args(&args(&["--json"])).unwrap(),
+            OutputFormat::Json
+        );
+        assert!(OutputFormat::from_args(&args(&["--format=yaml"])).is_err());
+        assert!(OutputFormat::from_args(&args(&["--format"])).is_err());
+    }
+
+    #[test]
+    fn resolve_output_schema() {
+        let output = ResolveOutput {
+            version: OUTPUT_SCHEMA_VERSION,
+            did: "did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+            pds: "https://pds.example.com".to_string(),
+            handles: vec![HandleOutput {
+                handle: "alice.example.com".to_string(),
+                dns_verified: true,
+                http_verified: false,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            json!({
+                "version": 1,
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "pds": "https://pds.example.com",
+                "handles": [
+                    { "handle": "alice.example.com", "dns_verified": true, "http_verified": false }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn inspect_output_schema() {
+        let document = json!({
+            "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "rotationKeys": [
+                "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                "did:key:zUnknown"
+            ],
+            "verificationMethods": {
+                "atproto": "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo"
+            },
+            "alsoKnownAs": ["at://alice.example.com", "https://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            }
+        });
+        let output =
+            InspectOutput::from_document("did:plc:cbkjy5n7bk3ax2wplmtjofq2", &document).unwrap();
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            json!({
+                "version": 1,
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "pds": "https://pds.example.com",
+                "handles": ["alice.example.com"],
+                "rotation_keys": [
+                    {
+                        "did_key": "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                        "curve": "k256"
+                    },
+                    { "did_key": "did:key:zUnknown", "curve": null }
+                ],
+                "verification_methods": [
+                    {
+                        "id": "atproto",
+                        "did_key": "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo",
+                        "curve": "p256"
+                    }
+                ]
+            })
+        );
+    }
+}
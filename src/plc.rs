@@ -1,6 +1,20 @@
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use cid::Cid;
+use multihash::Multihash;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+use crate::{
+    actions::inputs::{is_valid_handle, is_valid_hostname},
+    crypto::{did_key_fingerprint, sign_operation_with_encoding, verify_operation},
+    multikey::did_key_to_verifying_key,
+    signer::{SignatureEncoding, Signer},
+    trace::TracedSend,
+    xrpc::json_body,
+};
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -13,53 +27,226 @@ struct PlcService {
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct ResolveDid {
+pub(crate) struct ResolveDid {
     also_known_as: Vec<String>,
     service: Vec<PlcService>,
 }
 
-#[derive(Clone, Deserialize)]
-struct AuditEntry {
-    operation: serde_json::Value,
-    cid: String,
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub operation: serde_json::Value,
+    pub cid: String,
     #[serde(rename = "createdAt")]
-    created_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+
+    /// Set on operations a later, higher precedence operation has undone.
+    #[serde(default)]
+    pub nullified: bool,
+}
+
+/// A named PLC directory deployment, selected with `--env=`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlcEnvironment {
+    Production,
+
+    /// A directory for experimenting without touching real identities.
+    #[default]
+    Sandbox,
 }
 
-pub(crate) async fn plc_query(
+impl PlcEnvironment {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "production" => Ok(PlcEnvironment::Production),
+            "sandbox" => Ok(PlcEnvironment::Sandbox),
+            _ => Err(anyhow!(
+                "invalid --env value {}: expected production or sandbox",
+                name
+            )),
+        }
+    }
+
+    pub const fn plc_hostname(&self) -> &'static str {
+        match self {
+            PlcEnvironment::Production => "plc.directory",
+            PlcEnvironment::Sandbox => "plc.pyroclastic.cloud",
+        }
+    }
+}
+
+/// The PLC directory used when neither `--plc=` nor `--env=` is given.
+pub const DEFAULT_PLC_HOSTNAME: &str = PlcEnvironment::Sandbox.plc_hostname();
+
+/// Picks the PLC directory from `--plc=`, then the `--env=` preset, then the
+/// default, and normalizes it.
+pub fn plc_hostname_from_args(args: &[String]) -> Result<String> {
+    if let Some(plc) = args.iter().find_map(|arg| arg.strip_prefix("--plc=")) {
+        return normalize_plc_hostname(plc);
+    }
+    let environment = match args.iter().find_map(|arg| arg.strip_prefix("--env=")) {
+        Some(name) => PlcEnvironment::from_name(name)?,
+        None => PlcEnvironment::default(),
+    };
+    normalize_plc_hostname(environment.plc_hostname())
+}
+
+/// Normalizes a PLC directory given as a hostname or URL to the bare
+/// hostname, keeping an explicit port. Plain `http://` URLs are kept as-is for
+/// directories running locally.
+pub fn normalize_plc_hostname(input: &str) -> Result<String> {
+    let trimmed = input.trim().trim_end_matches('/');
+    let (scheme, host) = if let Some(host) = trimmed.strip_prefix("http://") {
+        (Some("http://"), host)
+    } else {
+        (None, trimmed.strip_prefix("https://").unwrap_or(trimmed))
+    };
+    let host = host.trim_end_matches('/').to_lowercase();
+
+    let (hostname, port) = match host.rsplit_once(':') {
+        Some((hostname, port)) => (hostname, Some(port)),
+        None => (host.as_str(), None),
+    };
+    if !is_valid_hostname(hostname) || port.is_some_and(|port| port.parse::<u16>().is_err()) {
+        return Err(anyhow!(
+            "invalid PLC directory {}: expected a hostname such as plc.directory",
+            input
+        ));
+    }
+
+    Ok(format!("{}{}", scheme.unwrap_or_default(), host))
+}
+
+/// Builds a URL on the PLC directory. A bare hostname is reached over HTTPS,
+/// while a value that already includes a scheme is used as given.
+pub(crate) fn plc_url(plc_hostname: &str, path: &str) -> String {
+    if plc_hostname.contains("://") {
+        format!("{}/{}", plc_hostname.trim_end_matches('/'), path)
+    } else {
+        format!("https://{}/{}", plc_hostname, path)
+    }
+}
+
+/// Returns whether the PLC directory knows the DID, including tombstoned DIDs.
+pub(crate) async fn did_exists(
     http_client: &reqwest::Client,
     plc_hostname: &str,
     did: &str,
-) -> Result<(Vec<String>, Vec<String>)> {
-    let url = format!("https://{}/{}", plc_hostname, did);
+) -> Result<bool> {
+    let url = plc_url(plc_hostname, did);
 
-    let resolved_did: ResolveDid = http_client.get(url).send().await?.json().await?;
+    let response = http_client
+        .get(url)
+        .send_traced()
+        .await
+        .context("unable to query PLC directory")?;
 
-    let handles = resolved_did
-        .also_known_as
-        .iter()
-        .map(|value| {
-            if let Some(handle) = value.strip_prefix("at://") {
-                handle.to_string()
-            } else {
-                value.to_string()
-            }
-        })
-        .collect::<Vec<String>>();
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        reqwest::StatusCode::GONE => Ok(true),
+        reqwest::StatusCode::NOT_FOUND => Ok(false),
+        status => Err(anyhow!("unexpected response {} from PLC directory", status)),
+    }
+}
 
-    let pds = resolved_did
-        .service
-        .iter()
-        .filter_map(|value| {
-            if value.service_type == "AtprotoPersonalDataServer" {
-                Some(value.service_endpoint.clone())
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<String>>();
+/// Errors with a hint about the PLC directory setting when it does not know the DID.
+pub(crate) async fn ensure_did_exists(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<()> {
+    if did_exists(http_client, plc_hostname, did).await? {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} not found on {} — is this the correct PLC directory?",
+            did,
+            plc_hostname
+        ))
+    }
+}
+
+/// How long the health probe waits for the directory to respond.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks that the PLC directory is responding before an action starts using
+/// it. Directories without a `/_health` endpoint answer 404, which still shows
+/// they are reachable.
+pub async fn check_directory_health(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+) -> Result<()> {
+    let response = http_client
+        .get(plc_url(plc_hostname, "_health"))
+        .timeout(HEALTH_TIMEOUT)
+        .send_traced()
+        .await
+        .map_err(|err| {
+            anyhow!(
+                "PLC directory {} is not responding ({})",
+                plc_hostname,
+                root_cause(&err)
+            )
+        })?;
 
-    Ok((pds, handles))
+    let status = response.status();
+    if status.is_success() || status == reqwest::StatusCode::NOT_FOUND {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "PLC directory {} is not healthy (status {})",
+            plc_hostname,
+            status
+        ))
+    }
+}
+
+fn root_cause(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut source = err;
+    while let Some(next) = source.source() {
+        source = next;
+    }
+    source.to_string()
+}
+
+/// Fetches the DID document at `url` and returns its PDS endpoints and its
+/// `alsoKnownAs` entries, with `at://` handles stripped of their scheme.
+pub(crate) async fn did_document_query(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let resolved_did: ResolveDid = json_body(http_client.get(url).send_traced().await?).await?;
+    Ok(resolved_did.endpoints())
+}
+
+impl ResolveDid {
+    /// The PDS endpoints and handles `did_document_query` returns.
+    pub(crate) fn endpoints(self) -> (Vec<String>, Vec<String>) {
+        let handles = self
+            .also_known_as
+            .iter()
+            .map(|value| {
+                if let Some(handle) = value.strip_prefix("at://") {
+                    handle.to_string()
+                } else {
+                    value.to_string()
+                }
+            })
+            .collect::<Vec<String>>();
+
+        let pds = self
+            .service
+            .iter()
+            .filter_map(|value| {
+                if value.service_type == "AtprotoPersonalDataServer" {
+                    Some(value.service_endpoint.clone())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<String>>();
+
+        (pds, handles)
+    }
 }
 
 pub(crate) async fn did_plc_data(
@@ -67,58 +254,983 @@ pub(crate) async fn did_plc_data(
     plc_hostname: &str,
     did: &str,
 ) -> Result<serde_json::Value> {
-    let url = format!("https://{}/{}/data", plc_hostname, did);
+    let url = plc_url(plc_hostname, &format!("{}/data", did));
 
-    http_client
-        .get(url)
-        .send()
-        .await
-        .context("unable to get DID document")?
-        .json()
-        .await
-        .context("unable to deserialize DID document")
+    json_body(
+        http_client
+            .get(url)
+            .send_traced()
+            .await
+            .context("unable to get DID document")?,
+    )
+    .await
+    .context("unable to deserialize DID document")
+}
+
+pub(crate) async fn did_plc_audit_log(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<Vec<AuditEntry>> {
+    let url = plc_url(plc_hostname, &format!("{}/log/audit", did));
+
+    let mut operations: Vec<AuditEntry> = json_body(
+        http_client
+            .get(url)
+            .send_traced()
+            .await
+            .context("unable to get DID audit log")?,
+    )
+    .await
+    .context("unable to deserialize DID audit log")?;
+
+    operations.sort_by_key(|entry| entry.created_at);
+
+    Ok(operations)
 }
 
+/// Fetches the head of the DID's audit log. With `verify_chain`, the log is
+/// first checked with `verify_audit_chain`, so the head is not trusted to a
+/// directory that forged or forked the history.
 pub(crate) async fn did_plc_last_operation(
     http_client: &reqwest::Client,
     plc_hostname: &str,
     did: &str,
+    verify_chain: bool,
 ) -> Result<(String, serde_json::Value)> {
-    let url = format!("https://{}/{}/log/audit", plc_hostname, did);
+    let operations = did_plc_audit_log(http_client, plc_hostname, did).await?;
+    verified_last_operation(did, operations, verify_chain)
+}
 
-    println!("url: {}", url);
+/// Checks a sorted audit log as `did_plc_last_operation` describes and
+/// returns the CID and operation of its head.
+pub(crate) fn verified_last_operation(
+    did: &str,
+    operations: Vec<AuditEntry>,
+    verify_chain: bool,
+) -> Result<(String, serde_json::Value)> {
+    validate_audit_cids(&operations)
+        .with_context(|| format!("the audit log of {} failed verification", did))?;
+    if verify_chain {
+        verify_audit_chain(&operations)
+            .with_context(|| format!("the audit log of {} failed verification", did))?;
+    }
 
-    let mut operations: Vec<AuditEntry> = http_client
-        .get(url)
-        .send()
-        .await
-        .context("unable to get DID audit log")?
-        .json()
-        .await
-        .context("unable to deserialize DID audit log")?;
+    let selected = audit_log_head(&operations)?.clone();
 
-    operations.sort_by_key(|entry| entry.created_at);
+    Ok((selected.cid, selected.operation))
+}
+
+/// Operations created this close together are ordered by their `prev` links
+/// rather than by timestamp, which may collide or be skewed.
+const HEAD_TIMESTAMP_TOLERANCE: chrono::TimeDelta = chrono::TimeDelta::seconds(1);
 
-    let selected = operations
+/// Returns the head of an audit log sorted by `createdAt`. When other entries
+/// were created within `HEAD_TIMESTAMP_TOLERANCE` of the latest, the head is
+/// the one no other entry names as its `prev`, and an error is returned if
+/// that does not single out one entry.
+pub(crate) fn audit_log_head(operations: &[AuditEntry]) -> Result<&AuditEntry> {
+    let latest = operations
         .last()
-        .cloned()
         .ok_or_else(|| anyhow!("no operations found"))?;
 
-    Ok((selected.cid, selected.operation))
+    let recent = operations
+        .iter()
+        .filter(|entry| latest.created_at - entry.created_at <= HEAD_TIMESTAMP_TOLERANCE)
+        .collect::<Vec<&AuditEntry>>();
+    if recent.len() == 1 {
+        return Ok(latest);
+    }
+
+    let heads = recent
+        .into_iter()
+        .filter(|candidate| {
+            !operations
+                .iter()
+                .any(|entry| entry.operation["prev"].as_str() == Some(candidate.cid.as_str()))
+        })
+        .collect::<Vec<&AuditEntry>>();
+    match heads[..] {
+        [head] => Ok(head),
+        [] => Err(anyhow!(
+            "audit log has no head: every recent operation is the prev of another"
+        )),
+        _ => Err(anyhow!(
+            "audit log head is ambiguous between {}",
+            heads
+                .iter()
+                .map(|entry| entry.cid.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
 }
 
-pub(crate) async fn submit_operation(
+/// Recomputes each entry's CID from its operation with `operation_cid` and
+/// checks it against the CID the directory reported, listing every entry
+/// that does not match.
+pub fn validate_audit_cids(entries: &[AuditEntry]) -> Result<()> {
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        let computed = operation_cid(&entry.operation)?;
+        if computed != entry.cid {
+            mismatches.push(format!("{} (computed {})", entry.cid, computed));
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "audit log entries do not match their operations: {}",
+            mismatches.join(", ")
+        ))
+    }
+}
+
+/// Returns the rotation keys that may sign the operation after `operation`.
+/// Legacy `create` operations name a recovery key and a signing key instead.
+fn authorized_rotation_keys(operation: &serde_json::Value) -> Result<Vec<&str>> {
+    let keys = match operation["type"].as_str() {
+        Some("plc_tombstone") => return Err(anyhow!("no operation may follow a tombstone")),
+        Some("create") => vec![&operation["recoveryKey"], &operation["signingKey"]],
+        _ => operation["rotationKeys"]
+            .as_array()
+            .ok_or_else(|| anyhow!("operation has no rotation keys"))?
+            .iter()
+            .collect(),
+    };
+    keys.into_iter()
+        .map(|key| {
+            key.as_str()
+                .ok_or_else(|| anyhow!("rotation key {} is not a string", key))
+        })
+        .collect()
+}
+
+/// Walks the audit log's operations from genesis, following `prev` links
+/// rather than timestamps, and checks that each operation names the one
+/// before it and is signed by a rotation key that operation authorized.
+/// Nullified operations are skipped, since a recovery operation replaced
+/// them, and every other entry must be part of the single chain.
+pub fn verify_audit_chain(entries: &[AuditEntry]) -> Result<()> {
+    let entries: Vec<&AuditEntry> = entries.iter().filter(|entry| !entry.nullified).collect();
+
+    let mut previous: Option<&AuditEntry> = None;
+    for verified in 0..entries.len() {
+        let prev = previous.map(|entry| entry.cid.as_str());
+        let next = entries
+            .iter()
+            .filter(|entry| entry.operation["prev"].as_str() == prev)
+            .collect::<Vec<_>>();
+        let entry = match next[..] {
+            [entry] => *entry,
+            [] => {
+                return Err(anyhow!(
+                    "no operation follows {}, but {} operation(s) remain",
+                    prev.unwrap_or("genesis"),
+                    entries.len() - verified
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "the chain forks after {}: {}",
+                    prev.unwrap_or("genesis"),
+                    next.iter()
+                        .map(|entry| entry.cid.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ))
+            }
+        };
+
+        if let Some(previous) = previous {
+            let authorized = authorized_rotation_keys(&previous.operation)
+                .with_context(|| format!("operation {} cannot be followed", previous.cid))?;
+            if !authorized
+                .iter()
+                .any(|key| verify_operation(&entry.operation, key).is_ok())
+            {
+                return Err(anyhow!(
+                    "operation {} is not signed by a rotation key of {}",
+                    entry.cid,
+                    previous.cid
+                ));
+            }
+        }
+        previous = Some(entry);
+    }
+
+    Ok(())
+}
+
+const DAG_CBOR_CODEC: u64 = 0x71;
+const SHA2_256_CODE: u64 = 0x12;
+
+/// Computes the CID the PLC directory assigns to an operation: a CIDv1 of its
+/// dag-cbor encoding with a SHA-256 multihash, in base32.
+pub fn operation_cid(operation: &serde_json::Value) -> Result<String> {
+    let encoded =
+        serde_ipld_dagcbor::to_vec(operation).context("failed to encode operation as dag-cbor")?;
+    let digest = Sha256::digest(&encoded);
+    let multihash =
+        Multihash::<64>::wrap(SHA2_256_CODE, &digest).context("failed to wrap operation digest")?;
+    Ok(Cid::new_v1(DAG_CBOR_CODEC, multihash).to_string())
+}
+
+/// Builds the unsigned genesis operation for a new identity. The first
+/// rotation key has the highest precedence.
+pub fn genesis_operation(
+    rotation_keys: &[String],
+    signing_key: &str,
+    handle: &str,
+    pds: &str,
+) -> Result<serde_json::Value> {
+    if rotation_keys.is_empty() || rotation_keys.len() > MAX_ROTATION_KEYS {
+        return Err(anyhow!(
+            "a genesis operation needs between 1 and {} rotation keys",
+            MAX_ROTATION_KEYS
+        ));
+    }
+    if let Some(key) = rotation_keys
+        .iter()
+        .map(String::as_str)
+        .chain([signing_key])
+        .find(|key| !key.starts_with("did:key:"))
+    {
+        return Err(anyhow!("{} is not a did:key", key));
+    }
+    let handle = is_valid_handle(handle).ok_or_else(|| anyhow!("invalid handle {}", handle))?;
+
+    Ok(json!({
+        "type": "plc_operation",
+        "rotationKeys": rotation_keys,
+        "verificationMethods": { ATPROTO_VERIFICATION_METHOD: signing_key },
+        "alsoKnownAs": [format!("at://{}", handle)],
+        "services": {
+            "atproto_pds": {
+                "type": "AtprotoPersonalDataServer",
+                "endpoint": pds
+            }
+        },
+        "prev": null,
+    }))
+}
+
+/// Errors unless the operation is an unsigned `plc_operation` or
+/// `plc_tombstone` with every field the directory requires.
+pub fn check_unsigned_operation(operation: &serde_json::Value) -> Result<()> {
+    let fields = operation
+        .as_object()
+        .ok_or_else(|| anyhow!("operation is not a JSON object"))?;
+    if fields.contains_key("sig") {
+        return Err(anyhow!("operation is already signed"));
+    }
+    if !fields.contains_key("prev") {
+        return Err(anyhow!(
+            "operation is missing prev, which is null for a genesis operation"
+        ));
+    }
+
+    match operation["type"].as_str() {
+        Some("plc_operation") => {
+            for (field, is_valid) in [
+                ("rotationKeys", operation["rotationKeys"].is_array()),
+                (
+                    "verificationMethods",
+                    operation["verificationMethods"].is_object(),
+                ),
+                ("alsoKnownAs", operation["alsoKnownAs"].is_array()),
+                ("services", operation["services"].is_object()),
+            ] {
+                if !is_valid {
+                    return Err(anyhow!("operation is missing a valid {}", field));
+                }
+            }
+        }
+        Some("plc_tombstone") => {
+            if !operation["prev"].is_string() {
+                return Err(anyhow!("operation is missing a valid prev"));
+            }
+        }
+        _ => {
+            return Err(anyhow!(
+                "operation type must be plc_operation or plc_tombstone"
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Errors unless the operation carries a `sig` and is otherwise well formed,
+/// and, for a genesis operation, unless it creates `did`.
+pub fn check_signed_operation(did: &str, operation: &serde_json::Value) -> Result<()> {
+    let mut unsigned_operation = operation.clone();
+    match unsigned_operation
+        .as_object_mut()
+        .and_then(|fields| fields.remove("sig"))
+    {
+        Some(serde_json::Value::String(sig)) if !sig.is_empty() => {}
+        _ => return Err(anyhow!("operation is not signed")),
+    }
+    check_unsigned_operation(&unsigned_operation)?;
+
+    if operation["prev"].is_null() {
+        let genesis_did = did_from_genesis(operation)?;
+        if genesis_did != did {
+            return Err(anyhow!(
+                "genesis operation creates {} rather than {}",
+                genesis_did,
+                did
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the operation's structure with `check_unsigned_operation` and signs
+/// it with `signer`.
+pub fn sign_unsigned_operation(
+    signer: &dyn Signer,
+    operation: &serde_json::Value,
+    encoding: SignatureEncoding,
+) -> Result<serde_json::Value> {
+    check_unsigned_operation(operation)?;
+    validate_operation(operation)?;
+    sign_operation_with_encoding(signer, operation, encoding)
+}
+
+/// Derives the `did:plc` a signed genesis operation creates: the first 15
+/// bytes of the SHA-256 of its dag-cbor encoding, in lowercase base32.
+pub fn did_from_genesis(operation: &serde_json::Value) -> Result<String> {
+    if !operation["prev"].is_null() {
+        return Err(anyhow!("operation is not a genesis operation"));
+    }
+    if !operation["sig"].is_string() {
+        return Err(anyhow!("genesis operation is not signed"));
+    }
+
+    let encoded =
+        serde_ipld_dagcbor::to_vec(operation).context("failed to encode operation as dag-cbor")?;
+    let digest = Sha256::digest(&encoded);
+    Ok(format!(
+        "did:plc:{}",
+        multibase::Base::Base32Lower.encode(&digest[..15])
+    ))
+}
+
+/// Polls the audit log until the submitted operation is the head of the DID's
+/// operation chain, returning false if it has not appeared after all attempts.
+pub(crate) async fn wait_for_head(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+    operation: &serde_json::Value,
+    attempts: usize,
+    delay: Duration,
+) -> Result<bool> {
+    let cid = operation_cid(operation)?;
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+        }
+
+        let (head_cid, _) = did_plc_last_operation(http_client, plc_hostname, did, false).await?;
+        if head_cid == cid {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Finds the audit log entry with the given CID, returning it along with
+/// whether it is the current head of the log.
+pub(crate) fn find_audit_entry(operations: &[AuditEntry], cid: &str) -> Result<(AuditEntry, bool)> {
+    let position = operations
+        .iter()
+        .position(|entry| entry.cid == cid)
+        .ok_or_else(|| anyhow!("operation {} not found in audit log", cid))?;
+
+    let head = audit_log_head(operations)?;
+    Ok((operations[position].clone(), head.cid == cid))
+}
+
+/// The kind of change an operation made relative to the operation before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OperationChange {
+    Genesis,
+    RotationKeys,
+    VerificationMethods,
+    Handles,
+    Services,
+    Tombstone,
+}
+
+impl OperationChange {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            OperationChange::Genesis => "genesis",
+            OperationChange::RotationKeys => "rotation keys changed",
+            OperationChange::VerificationMethods => "verification methods changed",
+            OperationChange::Handles => "handles changed",
+            OperationChange::Services => "services changed",
+            OperationChange::Tombstone => "tombstone",
+        }
+    }
+}
+
+pub(crate) struct OperationSummary {
+    pub(crate) operation: serde_json::Value,
+    pub(crate) changes: Vec<OperationChange>,
+}
+
+/// Returns the rotation keys, verification methods, handles, and services an
+/// operation declares, translating the legacy `create` operation shape.
+fn operation_state(operation: &serde_json::Value) -> [serde_json::Value; 4] {
+    if operation["type"] == "create" {
+        return [
+            json!([operation["recoveryKey"], operation["signingKey"]]),
+            json!({ "atproto": operation["signingKey"] }),
+            json!([format!(
+                "at://{}",
+                operation["handle"].as_str().unwrap_or_default()
+            )]),
+            json!({
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": operation["service"],
+                }
+            }),
+        ];
+    }
+    [
+        operation["rotationKeys"].clone(),
+        operation["verificationMethods"].clone(),
+        operation["alsoKnownAs"].clone(),
+        operation["services"].clone(),
+    ]
+}
+
+/// Classifies each operation in a chain by diffing it against its predecessor.
+pub(crate) fn summarize_operations(operations: &[serde_json::Value]) -> Vec<OperationSummary> {
+    let mut previous_state: Option<[serde_json::Value; 4]> = None;
+
+    operations
+        .iter()
+        .map(|operation| {
+            let changes = if operation["type"] == "plc_tombstone" {
+                vec![OperationChange::Tombstone]
+            } else {
+                let state = operation_state(operation);
+                let changes = match &previous_state {
+                    None => vec![OperationChange::Genesis],
+                    Some(previous_state) => [
+                        OperationChange::RotationKeys,
+                        OperationChange::VerificationMethods,
+                        OperationChange::Handles,
+                        OperationChange::Services,
+                    ]
+                    .into_iter()
+                    .zip(previous_state.iter().zip(state.iter()))
+                    .filter(|(_, (previous, current))| previous != current)
+                    .map(|(change, _)| change)
+                    .collect(),
+                };
+                previous_state = Some(state);
+                changes
+            };
+
+            OperationSummary {
+                operation: operation.clone(),
+                changes,
+            }
+        })
+        .collect()
+}
+
+pub(crate) async fn operation_history(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<Vec<OperationSummary>> {
+    let url = plc_url(plc_hostname, &format!("{}/log", did));
+
+    let operations: Vec<serde_json::Value> = json_body(
+        http_client
+            .get(url)
+            .send_traced()
+            .await
+            .context("unable to get DID operation log")?,
+    )
+    .await
+    .context("unable to deserialize DID operation log")?;
+
+    Ok(summarize_operations(&operations))
+}
+
+/// Converts the data of a PLC operation into the `did.json` document a
+/// `did:web` identity serves. Rotation keys have no did:web equivalent and
+/// are omitted.
+pub fn to_did_web_document(
+    operation: &serde_json::Value,
+    did_web: &str,
+) -> Result<serde_json::Value> {
+    if !did_web.starts_with("did:web:") {
+        return Err(anyhow!("{} is not a did:web identifier", did_web));
+    }
+    if operation["type"] != "plc_operation" {
+        return Err(anyhow!("operation is not a plc_operation"));
+    }
+
+    let verification_methods = operation["verificationMethods"]
+        .as_object()
+        .ok_or_else(|| anyhow!("operation is missing verificationMethods"))?
+        .iter()
+        .map(|(name, key)| {
+            let key = key
+                .as_str()
+                .and_then(|key| key.strip_prefix("did:key:"))
+                .ok_or_else(|| anyhow!("verification method {} is not a did:key", name))?;
+            Ok(json!({
+                "id": format!("{}#{}", did_web, name),
+                "type": "Multikey",
+                "controller": did_web,
+                "publicKeyMultibase": key,
+            }))
+        })
+        .collect::<Result<Vec<serde_json::Value>>>()?;
+
+    let services = get_services(operation)?
+        .iter()
+        .map(|(name, service)| {
+            json!({
+                "id": format!("#{}", name),
+                "type": service["type"],
+                "serviceEndpoint": service["endpoint"],
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+
+    Ok(json!({
+        "@context": [
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/multikey/v1",
+        ],
+        "id": did_web,
+        "alsoKnownAs": operation["alsoKnownAs"],
+        "verificationMethod": verification_methods,
+        "service": services,
+    }))
+}
+
+/// Returns every service an operation declares, keyed by service id, so that
+/// labeler and feed generator entries are carried along with the PDS.
+pub fn get_services(
+    operation: &serde_json::Value,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    operation["services"]
+        .as_object()
+        .cloned()
+        .ok_or_else(|| anyhow!("operation is missing services"))
+}
+
+/// Fields of a DID's PLC state that the directory and a PDS can each report.
+const DID_STATE_FIELDS: &[&str] = &[
+    "rotationKeys",
+    "alsoKnownAs",
+    "verificationMethods",
+    "services",
+];
+
+/// Lists the fields where the directory's view of a DID differs from the
+/// credentials a PDS recommends, so a lagging directory or a misconfigured PDS
+/// is noticed before an operation is built on either.
+pub(crate) fn did_state_disagreements(
+    directory: &serde_json::Value,
+    pds: &serde_json::Value,
+) -> Vec<String> {
+    DID_STATE_FIELDS
+        .iter()
+        .filter(|field| directory[**field] != pds[**field])
+        .map(|field| {
+            format!(
+                "{} differs: directory has {}, PDS has {}",
+                field, directory[*field], pds[*field]
+            )
+        })
+        .collect()
+}
+
+/// Returns the `atproto_pds` endpoint a DID document lists, whether it comes
+/// from the directory's `services` map or a PDS's `service` array.
+pub(crate) fn document_pds_endpoint(document: &serde_json::Value) -> Option<&str> {
+    document["services"]["atproto_pds"]["endpoint"]
+        .as_str()
+        .or_else(|| {
+            document["service"].as_array()?.iter().find_map(|service| {
+                service["id"]
+                    .as_str()
+                    .is_some_and(|id| id.ends_with("#atproto_pds"))
+                    .then(|| service["serviceEndpoint"].as_str())
+                    .flatten()
+            })
+        })
+}
+
+/// The verification method atproto repository commits are signed with.
+pub const ATPROTO_VERIFICATION_METHOD: &str = "atproto";
+
+/// The service that names the account's PDS.
+pub const ATPROTO_PDS_SERVICE: &str = "atproto_pds";
+
+/// Returns each verification method an operation declares as `(id, did:key)`
+/// pairs, so methods other than `atproto` can be shown alongside it.
+pub fn get_verification_methods(operation: &serde_json::Value) -> Result<Vec<(String, String)>> {
+    operation["verificationMethods"]
+        .as_object()
+        .ok_or_else(|| anyhow!("operation is missing verificationMethods"))?
+        .iter()
+        .map(|(id, key)| {
+            key.as_str()
+                .map(|key| (id.clone(), key.to_string()))
+                .ok_or_else(|| anyhow!("verification method {} is not a did:key", id))
+        })
+        .collect()
+}
+
+/// Returns a copy of the operation whose `atproto` verification method is
+/// `did_key`, leaving every other verification method untouched.
+pub fn with_signing_key(operation: &serde_json::Value, did_key: &str) -> Result<serde_json::Value> {
+    if !did_key.starts_with("did:key:") {
+        return Err(anyhow!("signing key {} is not a did:key", did_key));
+    }
+
+    let mut operation = operation.clone();
+    operation["verificationMethods"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("operation is missing verificationMethods"))?
+        .insert(
+            ATPROTO_VERIFICATION_METHOD.to_string(),
+            serde_json::Value::String(did_key.to_string()),
+        );
+    Ok(operation)
+}
+
+/// Errors when a prepared operation drops or changes any service other than
+/// `target`, which is the only one the caller intends to modify.
+pub(crate) fn check_services_preserved(
+    previous: &serde_json::Value,
+    prepared: &serde_json::Value,
+    target: Option<&str>,
+) -> Result<()> {
+    let prepared_services = get_services(prepared)?;
+    for (id, service) in get_services(previous)? {
+        if Some(id.as_str()) == target {
+            continue;
+        }
+        if prepared_services.get(&id) != Some(&service) {
+            return Err(anyhow!(
+                "prepared operation would drop or change the {} service",
+                id
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The most rotation keys a PLC operation may list.
+pub(crate) const MAX_ROTATION_KEYS: usize = 5;
+
+/// Errors when adding `additional` rotation keys to the document would exceed
+/// the PLC limit, naming the existing keys so the user can pick one to drop.
+pub(crate) fn check_rotation_key_capacity(
+    document: &serde_json::Value,
+    additional: usize,
+) -> Result<()> {
+    let rotation_keys = document["rotationKeys"]
+        .as_array()
+        .map(|keys| {
+            keys.iter()
+                .filter_map(|key| key.as_str())
+                .collect::<Vec<&str>>()
+        })
+        .unwrap_or_default();
+
+    if rotation_keys.len() + additional > MAX_ROTATION_KEYS {
+        return Err(anyhow!(
+            "DID document already has {} of {} rotation keys; remove one before adding another: {}",
+            rotation_keys.len(),
+            MAX_ROTATION_KEYS,
+            rotation_keys.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Why an operation would be unsafe to sign. Each of these either locks the
+/// user out of their identity or is rejected by the directory.
+#[derive(Debug, PartialEq)]
+pub enum InvalidOperation {
+    NoRotationKeys,
+    TooManyRotationKeys(usize),
+    NoHandles,
+    InvalidSigningKey(Option<String>),
+    MissingPrev,
+}
+
+impl std::fmt::Display for InvalidOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidOperation::NoRotationKeys => write!(
+                f,
+                "operation has no rotation keys, which would permanently lock you out of the identity"
+            ),
+            InvalidOperation::TooManyRotationKeys(count) => write!(
+                f,
+                "operation has {} rotation keys but at most {} are allowed",
+                count, MAX_ROTATION_KEYS
+            ),
+            InvalidOperation::NoHandles => write!(
+                f,
+                "operation has no handles in alsoKnownAs, which would detach the identity from every handle"
+            ),
+            InvalidOperation::InvalidSigningKey(Some(key)) => write!(
+                f,
+                "operation's atproto verification method {} is not a P-256 or secp256k1 did:key",
+                key
+            ),
+            InvalidOperation::InvalidSigningKey(None) => {
+                write!(f, "operation has no atproto verification method")
+            }
+            InvalidOperation::MissingPrev => write!(
+                f,
+                "operation is missing prev, which is null only for a genesis operation"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidOperation {}
+
+/// Checks the parts of a DID's state that every operation must keep usable:
+/// between one and `MAX_ROTATION_KEYS` rotation keys, at least one handle,
+/// and a valid `atproto` verification method.
+pub(crate) fn validate_did_state(
+    state: &serde_json::Value,
+) -> std::result::Result<(), InvalidOperation> {
+    let rotation_keys = state["rotationKeys"].as_array().map_or(0, Vec::len);
+    if rotation_keys == 0 {
+        return Err(InvalidOperation::NoRotationKeys);
+    }
+    if rotation_keys > MAX_ROTATION_KEYS {
+        return Err(InvalidOperation::TooManyRotationKeys(rotation_keys));
+    }
+
+    if state["alsoKnownAs"].as_array().is_none_or(Vec::is_empty) {
+        return Err(InvalidOperation::NoHandles);
+    }
+
+    match state["verificationMethods"][ATPROTO_VERIFICATION_METHOD].as_str() {
+        Some(key) if did_key_fingerprint(key).is_ok() => {}
+        key => return Err(InvalidOperation::InvalidSigningKey(key.map(str::to_string))),
+    }
+
+    Ok(())
+}
+
+/// Checks an operation before it is signed. Tombstones only need a `prev`;
+/// other operations must also pass `validate_did_state`.
+pub fn validate_operation(operation: &serde_json::Value) -> Result<()> {
+    let has_prev = match operation.get("prev") {
+        Some(serde_json::Value::Null) => operation["type"] != "plc_tombstone",
+        Some(serde_json::Value::String(prev)) => !prev.is_empty(),
+        _ => false,
+    };
+    if !has_prev {
+        return Err(InvalidOperation::MissingPrev.into());
+    }
+
+    if operation["type"] != "plc_tombstone" {
+        validate_did_state(operation)?;
+    }
+
+    Ok(())
+}
+
+/// The outcome of one structural invariant checked by `check_invariants`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantCheck {
+    pub name: &'static str,
+
+    /// What was found when the check passed, or why it failed.
+    pub result: std::result::Result<String, String>,
+}
+
+impl InvariantCheck {
+    fn new(name: &'static str, result: std::result::Result<String, String>) -> Self {
+        Self { name, result }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Checks every PLC invariant of an operation, or of the operation data the
+/// directory serves from `/data`, without stopping at the first failure.
+/// This is the offline, itemized counterpart to `validate_operation`.
+pub fn check_invariants(operation: &serde_json::Value) -> Vec<InvariantCheck> {
+    let prev = match operation.get("prev") {
+        Some(serde_json::Value::Null) => Ok("null, so this is a genesis operation".to_string()),
+        Some(serde_json::Value::String(prev)) if !prev.is_empty() => Ok(prev.clone()),
+        Some(_) => Err("prev must be a CID string, or null for a genesis operation".to_string()),
+        None if operation.get("type").is_none() => {
+            Ok("not present, so this is operation data rather than an operation".to_string())
+        }
+        None => Err(InvalidOperation::MissingPrev.to_string()),
+    };
+    let mut checks = vec![InvariantCheck::new("prev", prev)];
+
+    if operation["type"] == "plc_tombstone" {
+        return checks;
+    }
+
+    checks.push(InvariantCheck::new(
+        "rotation keys",
+        check_rotation_keys(operation),
+    ));
+    checks.push(InvariantCheck::new(
+        "atproto verification method",
+        match operation["verificationMethods"][ATPROTO_VERIFICATION_METHOD].as_str() {
+            Some(key) => did_key_to_verifying_key(key)
+                .map(|verifying_key| format!("{} ({})", key, verifying_key.curve()))
+                .map_err(|err| err.to_string()),
+            None => Err(InvalidOperation::InvalidSigningKey(None).to_string()),
+        },
+    ));
+    checks.push(InvariantCheck::new("handles", check_handles(operation)));
+    checks.push(InvariantCheck::new(
+        "PDS endpoint",
+        check_pds_endpoint(operation),
+    ));
+
+    checks
+}
+
+fn check_rotation_keys(operation: &serde_json::Value) -> std::result::Result<String, String> {
+    let rotation_keys = operation["rotationKeys"]
+        .as_array()
+        .ok_or_else(|| InvalidOperation::NoRotationKeys.to_string())?;
+    if rotation_keys.is_empty() {
+        return Err(InvalidOperation::NoRotationKeys.to_string());
+    }
+    if rotation_keys.len() > MAX_ROTATION_KEYS {
+        return Err(InvalidOperation::TooManyRotationKeys(rotation_keys.len()).to_string());
+    }
+    for rotation_key in rotation_keys {
+        let rotation_key = rotation_key
+            .as_str()
+            .ok_or_else(|| format!("rotation key {} is not a string", rotation_key))?;
+        if !rotation_key.starts_with("did:key:") {
+            return Err(format!("rotation key {} is not a did:key", rotation_key));
+        }
+        did_key_to_verifying_key(rotation_key).map_err(|err| err.to_string())?;
+    }
+    Ok(format!("{} valid did:key(s)", rotation_keys.len()))
+}
+
+fn check_handles(operation: &serde_json::Value) -> std::result::Result<String, String> {
+    let handles = operation["alsoKnownAs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|alias| alias.as_str()?.strip_prefix("at://"))
+        .collect::<Vec<&str>>();
+    if handles.is_empty() {
+        return Err(InvalidOperation::NoHandles.to_string());
+    }
+    if let Some(invalid) = handles
+        .iter()
+        .find(|handle| is_valid_handle(handle).is_none())
+    {
+        return Err(format!("at://{} is not a valid handle", invalid));
+    }
+    Ok(handles.join(", "))
+}
+
+fn check_pds_endpoint(operation: &serde_json::Value) -> std::result::Result<String, String> {
+    let endpoint = operation["services"]["atproto_pds"]["endpoint"]
+        .as_str()
+        .ok_or_else(|| "operation has no atproto_pds service endpoint".to_string())?;
+    let url = reqwest::Url::parse(endpoint)
+        .map_err(|err| format!("PDS endpoint {} is not a URL: {}", endpoint, err))?;
+    if !matches!(url.scheme(), "https" | "http") || url.host_str().is_none_or(str::is_empty) {
+        return Err(format!(
+            "PDS endpoint {} must be an http or https URL with a host",
+            endpoint
+        ));
+    }
+    Ok(endpoint.to_string())
+}
+
+/// Confirms that a patched operation chains to the expected CID and no longer
+/// carries the signature of the operation it was derived from.
+pub(crate) fn check_prepared_operation(operation: &serde_json::Value, prev: &str) -> Result<()> {
+    match operation.get("prev").and_then(|value| value.as_str()) {
+        Some(value) if value == prev => {}
+        Some(value) => {
+            return Err(anyhow!(
+                "prepared operation has prev {} but expected {}",
+                value,
+                prev
+            ))
+        }
+        None => return Err(anyhow!("prepared operation is missing prev")),
+    }
+
+    if operation.get("sig").is_some() {
+        return Err(anyhow!("prepared operation still contains a signature"));
+    }
+
+    Ok(())
+}
+
+/// Renders the `curl` invocation that submits the operation to the PLC
+/// directory, quoted for POSIX shells.
+pub(crate) fn curl_command(
+    plc_hostname: &str,
+    did: &str,
+    operation: &serde_json::Value,
+) -> Result<String> {
+    fn shell_quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+
+    let body = serde_json::to_string(operation).context("failed to serialize operation")?;
+
+    Ok(format!(
+        "curl -X POST {} -H 'Content-Type: application/json' --data-raw {}",
+        shell_quote(&plc_url(plc_hostname, did)),
+        shell_quote(&body)
+    ))
+}
+
+pub async fn submit_operation(
     http_client: &reqwest::Client,
     plc_hostname: &str,
     did: &str,
     operation: &serde_json::Value,
 ) -> Result<()> {
-    let url = format!("https://{}/{}", plc_hostname, did);
+    let url = plc_url(plc_hostname, did);
 
     http_client
         .post(url)
         .json(operation)
-        .send()
+        .send_traced()
         .await
         .context("unable to submit operation")
         .and_then(|response| {
@@ -130,3 +1242,893 @@ pub(crate) async fn submit_operation(
             }
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[test]
+    fn operation_cids() {
+        // The CID of an empty dag-cbor map.
+        assert_eq!(
+            operation_cid(&json!({})).unwrap(),
+            "bafyreigbtj4x7ip5legnfznufuopl4sg4knzc2cof6duas4b3q2fy6swua"
+        );
+
+        // Signed by tandem, not taken from the directory; see
+        // `directory_genesis_vectors` for operations the directory serves.
+        let operation = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+            },
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            },
+            "prev": null,
+            "sig": "8Wj9Cf74dZFNKx7oucZSHbBDFOMJ3xx9lkvj5rT9xMErssWYl1D9n4PeGC0mNml7xDG7uoQqZ1JWoApGADUgXg"
+        });
+        assert_eq!(
+            operation_cid(&operation).unwrap(),
+            "bafyreidodhqsrkvlhfuuhukfrd5vobdwcg7l5vn7lq2fhgse6lrvlfebde"
+        );
+    }
+
+    #[test]
+    fn sign_unsigned_operations() -> Result<()> {
+        use crate::crypto::{p256, validate_bytes, KeyFormat};
+        use crate::signer::JwkSigner;
+        use base64::{engine::general_purpose, Engine as _};
+        use std::str::FromStr;
+
+        let (secret_jwk, encoded_public_key) = p256::gen_key(KeyFormat::Jwk)?;
+        let signer = JwkSigner::new(elliptic_curve::JwkEcKey::from_str(&secret_jwk)?)?;
+
+        let operation = json!({
+            "type": "plc_operation",
+            "rotationKeys": [signer.did_key()],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+            },
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {},
+            "prev": "bafyreid",
+        });
+        let signed_operation =
+            sign_unsigned_operation(&signer, &operation, SignatureEncoding::RawBase64Url)?;
+
+        let mut unsigned_operation = signed_operation.clone();
+        let signature = unsigned_operation
+            .as_object_mut()
+            .unwrap()
+            .remove("sig")
+            .unwrap();
+        assert_eq!(unsigned_operation, operation);
+        validate_bytes(
+            &encoded_public_key,
+            &general_purpose::URL_SAFE_NO_PAD.decode(signature.as_str().unwrap())?,
+            &serde_ipld_dagcbor::to_vec(&operation)?,
+        )?;
+
+        let err =
+            sign_unsigned_operation(&signer, &signed_operation, SignatureEncoding::RawBase64Url)
+                .unwrap_err();
+        assert_eq!(err.to_string(), "operation is already signed");
+
+        let mut missing_prev = operation.clone();
+        missing_prev.as_object_mut().unwrap().remove("prev");
+        assert!(check_unsigned_operation(&missing_prev).is_err());
+
+        let mut missing_services = operation.clone();
+        missing_services.as_object_mut().unwrap().remove("services");
+        let err = check_unsigned_operation(&missing_services).unwrap_err();
+        assert_eq!(err.to_string(), "operation is missing a valid services");
+
+        check_signed_operation("did:plc:cbkjy5n7bk3ax2wplmtjofq2", &signed_operation)?;
+        let err =
+            check_signed_operation("did:plc:cbkjy5n7bk3ax2wplmtjofq2", &operation).unwrap_err();
+        assert_eq!(err.to_string(), "operation is not signed");
+
+        assert!(
+            check_unsigned_operation(&json!({ "type": "plc_tombstone", "prev": "bafyreid" }))
+                .is_ok()
+        );
+        assert!(check_unsigned_operation(&json!({ "type": "create", "prev": null })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn genesis_dids() {
+        let operation = genesis_operation(
+            &["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg".to_string()],
+            "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF",
+            "alice.example.com",
+            "https://pds.example.com",
+        )
+        .unwrap();
+        assert!(did_from_genesis(&operation).is_err());
+
+        // A signature made by tandem, so this checks the DID derivation
+        // against itself; `directory_genesis_vectors` checks it against DIDs
+        // the directory created.
+        let mut signed_operation = operation.clone();
+        signed_operation["sig"] = json!(
+            "8Wj9Cf74dZFNKx7oucZSHbBDFOMJ3xx9lkvj5rT9xMErssWYl1D9n4PeGC0mNml7xDG7uoQqZ1JWoApGADUgXg"
+        );
+        assert_eq!(
+            did_from_genesis(&signed_operation).unwrap(),
+            "did:plc:nym6ckfkvm4wsq6riwepwvye"
+        );
+        assert!(
+            check_signed_operation("did:plc:nym6ckfkvm4wsq6riwepwvye", &signed_operation).is_ok()
+        );
+        let err = check_signed_operation("did:plc:cbkjy5n7bk3ax2wplmtjofq2", &signed_operation)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("genesis operation creates did:plc:nym6ckfkvm4wsq6riwepwvye"));
+
+        assert!(genesis_operation(
+            &[],
+            "did:key:zQ3sh",
+            "alice.example.com",
+            "https://pds.example.com"
+        )
+        .is_err());
+        assert!(genesis_operation(
+            &["zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg".to_string()],
+            "did:key:zQ3sh",
+            "alice.example.com",
+            "https://pds.example.com"
+        )
+        .is_err());
+    }
+
+    /// Checks CIDs and DIDs derived from real genesis operations against the
+    /// ones plc.directory reports. Needs network access, so it only runs
+    /// with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn directory_genesis_vectors() -> Result<()> {
+        let http_client = reqwest::Client::new();
+        // atproto.com, created with a legacy `create` operation, and
+        // bsky.app.
+        for did in [
+            "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+            "did:plc:z72i7hdynmk6r22z27h6tvur",
+        ] {
+            let operations = did_plc_audit_log(&http_client, "plc.directory", did).await?;
+            validate_audit_cids(&operations)?;
+            let genesis = operations
+                .iter()
+                .find(|entry| entry.operation["prev"].is_null())
+                .ok_or_else(|| anyhow!("{} has no genesis operation", did))?;
+            assert_eq!(operation_cid(&genesis.operation)?, genesis.cid);
+            assert_eq!(did_from_genesis(&genesis.operation)?, did);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn genesis_encoding_is_canonical() {
+        // The directory hashes dag-cbor, which orders map keys by length
+        // before bytes, not alphabetically as serde_json does.
+        assert_eq!(
+            serde_ipld_dagcbor::to_vec(&json!({ "aa": 1, "b": 2 })).unwrap(),
+            vec![0xa2, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x01]
+        );
+
+        let operation = genesis_operation(
+            &["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg".to_string()],
+            "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF",
+            "alice.example.com",
+            "https://pds.example.com",
+        )
+        .unwrap();
+        let mut signed_operation = operation.clone();
+        signed_operation["sig"] = json!(
+            "8Wj9Cf74dZFNKx7oucZSHbBDFOMJ3xx9lkvj5rT9xMErssWYl1D9n4PeGC0mNml7xDG7uoQqZ1JWoApGADUgXg"
+        );
+        let did = did_from_genesis(&signed_operation).unwrap();
+        let identifier = did.strip_prefix("did:plc:").unwrap();
+        assert_eq!(identifier.len(), 24);
+        assert!(identifier
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)));
+
+        // Every field, including the signature, is part of the identifier.
+        let mut changed = signed_operation.clone();
+        changed["alsoKnownAs"] = json!(["at://bob.example.com"]);
+        assert_ne!(did_from_genesis(&changed).unwrap(), did);
+        let mut changed = signed_operation.clone();
+        changed["sig"] = json!("AAAA");
+        assert_ne!(did_from_genesis(&changed).unwrap(), did);
+    }
+
+    #[test]
+    fn directory_and_pds_disagreements() {
+        let directory = json!({
+            "rotationKeys": ["did:key:zQ3shA", "did:key:zDnaeB"],
+            "alsoKnownAs": ["at://alice.example.com"],
+            "verificationMethods": { "atproto": "did:key:zQ3shC" },
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            },
+        });
+        assert!(did_state_disagreements(&directory, &directory).is_empty());
+
+        let mut pds = directory.clone();
+        pds["verificationMethods"]["atproto"] = json!("did:key:zQ3shD");
+        let disagreements = did_state_disagreements(&directory, &pds);
+        assert_eq!(disagreements.len(), 1);
+        assert!(disagreements[0].starts_with("verificationMethods differs"));
+
+        assert_eq!(
+            document_pds_endpoint(&directory),
+            Some("https://pds.example.com")
+        );
+        let did_doc = json!({
+            "id": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds2.example.com"
+            }]
+        });
+        assert_eq!(
+            document_pds_endpoint(&did_doc),
+            Some("https://pds2.example.com")
+        );
+        assert_eq!(document_pds_endpoint(&json!({})), None);
+    }
+
+    #[test]
+    fn signing_key_rotation_preserves_other_methods() {
+        let operation = json!({
+            "type": "plc_operation",
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF",
+                "atproto_label": "did:key:zDnaeTiq1PdzvZXUaMdezchcMJQpBdH2VN4pgrrEhMCCbmwSb"
+            },
+        });
+
+        let rotated = with_signing_key(
+            &operation,
+            "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+        )
+        .unwrap();
+        assert_eq!(
+            get_verification_methods(&rotated).unwrap(),
+            vec![
+                (
+                    "atproto".to_string(),
+                    "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg".to_string()
+                ),
+                (
+                    "atproto_label".to_string(),
+                    "did:key:zDnaeTiq1PdzvZXUaMdezchcMJQpBdH2VN4pgrrEhMCCbmwSb".to_string()
+                ),
+            ]
+        );
+
+        assert!(with_signing_key(
+            &operation,
+            "zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"
+        )
+        .is_err());
+        assert!(with_signing_key(&json!({}), "did:key:zQ3sh").is_err());
+    }
+
+    #[test]
+    fn invalid_operations() {
+        let operation = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+            },
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {},
+            "prev": "bafyreid",
+        });
+        assert!(validate_operation(&operation).is_ok());
+
+        let mut genesis = operation.clone();
+        genesis["prev"] = json!(null);
+        assert!(validate_operation(&genesis).is_ok());
+
+        let invalid = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut operation = operation.clone();
+            change(&mut operation);
+            validate_operation(&operation)
+                .unwrap_err()
+                .downcast::<InvalidOperation>()
+                .unwrap()
+        };
+        assert_eq!(
+            invalid(&|operation| operation["rotationKeys"] = json!([])),
+            InvalidOperation::NoRotationKeys
+        );
+        assert_eq!(
+            invalid(&|operation| {
+                operation.as_object_mut().unwrap().remove("rotationKeys");
+            }),
+            InvalidOperation::NoRotationKeys
+        );
+        assert_eq!(
+            invalid(&|operation| operation["rotationKeys"] = json!(vec!["did:key:zQ3shA"; 6])),
+            InvalidOperation::TooManyRotationKeys(6)
+        );
+        assert_eq!(
+            invalid(&|operation| operation["alsoKnownAs"] = json!([])),
+            InvalidOperation::NoHandles
+        );
+        assert_eq!(
+            invalid(&|operation| operation["verificationMethods"] = json!({})),
+            InvalidOperation::InvalidSigningKey(None)
+        );
+        assert_eq!(
+            invalid(&|operation| operation["verificationMethods"]["atproto"] =
+                json!("did:web:example.com")),
+            InvalidOperation::InvalidSigningKey(Some("did:web:example.com".to_string()))
+        );
+        assert_eq!(
+            invalid(&|operation| {
+                operation.as_object_mut().unwrap().remove("prev");
+            }),
+            InvalidOperation::MissingPrev
+        );
+
+        assert!(
+            validate_operation(&json!({ "type": "plc_tombstone", "prev": "bafyreid" })).is_ok()
+        );
+        assert!(validate_operation(&json!({ "type": "plc_tombstone", "prev": null })).is_err());
+    }
+
+    #[test]
+    fn prepared_operation_checks() {
+        let operation = json!({ "type": "plc_operation", "prev": "bafyreia" });
+        assert!(check_prepared_operation(&operation, "bafyreia").is_ok());
+        assert!(check_prepared_operation(&operation, "bafyreib").is_err());
+        assert!(check_prepared_operation(&json!({ "type": "plc_operation" }), "bafyreia").is_err());
+
+        let signed = json!({ "type": "plc_operation", "prev": "bafyreia", "sig": "abc" });
+        assert!(check_prepared_operation(&signed, "bafyreia").is_err());
+    }
+
+    #[test]
+    fn summarize_operation_changes() {
+        let operations = vec![
+            json!({
+                "type": "create",
+                "signingKey": "did:key:zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+                "recoveryKey": "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                "handle": "alice.example.com",
+                "service": "https://pds.example.com",
+                "prev": null,
+            }),
+            json!({
+                "type": "plc_operation",
+                "rotationKeys": [
+                    "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                    "did:key:zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+                ],
+                "verificationMethods": {
+                    "atproto": "did:key:zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+                },
+                "alsoKnownAs": ["at://alice.example.net"],
+                "services": {
+                    "atproto_pds": {
+                        "type": "AtprotoPersonalDataServer",
+                        "endpoint": "https://pds.example.com",
+                    }
+                },
+                "prev": "bafyreia",
+            }),
+            json!({
+                "type": "plc_operation",
+                "rotationKeys": [
+                    "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                ],
+                "verificationMethods": {
+                    "atproto": "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                },
+                "alsoKnownAs": ["at://alice.example.net"],
+                "services": {
+                    "atproto_pds": {
+                        "type": "AtprotoPersonalDataServer",
+                        "endpoint": "https://pds.example.com",
+                    }
+                },
+                "prev": "bafyreib",
+            }),
+            json!({ "type": "plc_tombstone", "prev": "bafyreic" }),
+        ];
+
+        let changes = summarize_operations(&operations)
+            .into_iter()
+            .map(|summary| summary.changes)
+            .collect::<Vec<Vec<OperationChange>>>();
+
+        assert_eq!(
+            changes,
+            vec![
+                vec![OperationChange::Genesis],
+                vec![OperationChange::Handles],
+                vec![
+                    OperationChange::RotationKeys,
+                    OperationChange::VerificationMethods
+                ],
+                vec![OperationChange::Tombstone],
+            ]
+        );
+    }
+
+    #[test]
+    fn find_pinned_audit_entry() {
+        let operations: Vec<AuditEntry> = serde_json::from_value(json!([
+            { "operation": { "prev": null }, "cid": "bafyreia", "createdAt": "2024-01-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreia" }, "cid": "bafyreib", "createdAt": "2024-02-01T00:00:00Z" },
+        ]))
+        .unwrap();
+
+        let (entry, is_head) = find_audit_entry(&operations, "bafyreia").unwrap();
+        assert_eq!(entry.cid, "bafyreia");
+        assert!(!is_head);
+
+        let (entry, is_head) = find_audit_entry(&operations, "bafyreib").unwrap();
+        assert_eq!(entry.cid, "bafyreib");
+        assert!(is_head);
+
+        assert!(find_audit_entry(&operations, "bafyreic").is_err());
+    }
+
+    #[test]
+    fn audit_log_heads() {
+        let operations: Vec<AuditEntry> = serde_json::from_value(json!([
+            { "operation": { "prev": null }, "cid": "bafyreia", "createdAt": "2024-01-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreia" }, "cid": "bafyreib", "createdAt": "2024-02-01T00:00:00Z" },
+        ]))
+        .unwrap();
+        assert_eq!(audit_log_head(&operations).unwrap().cid, "bafyreib");
+
+        // Sorting by createdAt leaves the older operation last when timestamps collide.
+        let operations: Vec<AuditEntry> = serde_json::from_value(json!([
+            { "operation": { "prev": null }, "cid": "bafyreia", "createdAt": "2024-01-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreic" }, "cid": "bafyreid", "createdAt": "2024-02-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreia" }, "cid": "bafyreic", "createdAt": "2024-02-01T00:00:00Z" },
+        ]))
+        .unwrap();
+        assert_eq!(audit_log_head(&operations).unwrap().cid, "bafyreid");
+        let (_, is_head) = find_audit_entry(&operations, "bafyreic").unwrap();
+        assert!(!is_head);
+
+        let forked: Vec<AuditEntry> = serde_json::from_value(json!([
+            { "operation": { "prev": null }, "cid": "bafyreia", "createdAt": "2024-01-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreia" }, "cid": "bafyreib", "createdAt": "2024-02-01T00:00:00Z" },
+            { "operation": { "prev": "bafyreia" }, "cid": "bafyreic", "createdAt": "2024-02-01T00:00:00.500Z" },
+        ]))
+        .unwrap();
+        let err = audit_log_head(&forked).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("ambiguous between bafyreib, bafyreic"));
+
+        assert!(audit_log_head(&[]).is_err());
+    }
+
+    #[test]
+    fn audit_chain_verification() -> Result<()> {
+        let keys = [
+            crate::crypto::k256::gen_key(crate::crypto::KeyFormat::Jwk)?,
+            crate::crypto::p256::gen_key(crate::crypto::KeyFormat::Jwk)?,
+        ];
+        let signers = keys
+            .iter()
+            .map(|(secret_jwk, _)| {
+                crate::signer::JwkSigner::new(
+                    <elliptic_curve::JwkEcKey as std::str::FromStr>::from_str(secret_jwk)?,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let did_keys = keys
+            .iter()
+            .map(|(_, public_key)| format!("did:key:{}", public_key))
+            .collect::<Vec<_>>();
+
+        let entry = |signer: usize, rotation_key: usize, prev: Option<&str>, minute: u32| {
+            let operation = crate::crypto::sign_operation(
+                &signers[signer],
+                &json!({
+                    "type": "plc_operation",
+                    "rotationKeys": [did_keys[rotation_key]],
+                    "verificationMethods": {},
+                    "alsoKnownAs": [format!("at://{}.example.com", minute)],
+                    "services": {},
+                    "prev": prev,
+                }),
+            )
+            .unwrap();
+            AuditEntry {
+                cid: operation_cid(&operation).unwrap(),
+                operation,
+                created_at: format!("2024-01-01T00:{:02}:00Z", minute).parse().unwrap(),
+                nullified: false,
+            }
+        };
+
+        // Key 0 creates the DID and rotates to key 1, which signs the third.
+        let genesis = entry(0, 0, None, 0);
+        let rotation = entry(0, 1, Some(&genesis.cid), 1);
+        let update = entry(1, 1, Some(&rotation.cid), 2);
+        verify_audit_chain(&[genesis.clone(), rotation.clone(), update.clone()])?;
+
+        // Entries are ordered by prev, not by createdAt.
+        verify_audit_chain(&[update.clone(), genesis.clone(), rotation.clone()])?;
+
+        // Key 0 was rotated out, so it cannot sign after the rotation.
+        let forged = entry(0, 0, Some(&rotation.cid), 2);
+        let err =
+            verify_audit_chain(&[genesis.clone(), rotation.clone(), forged.clone()]).unwrap_err();
+        assert!(err.to_string().contains("is not signed by a rotation key"));
+
+        // A nullified operation is not part of the chain.
+        let nullified = AuditEntry {
+            nullified: true,
+            ..forged.clone()
+        };
+        verify_audit_chain(&[genesis.clone(), rotation.clone(), nullified, update.clone()])?;
+
+        let fork = entry(0, 0, Some(&genesis.cid), 3);
+        let err = verify_audit_chain(&[genesis.clone(), rotation.clone(), fork]).unwrap_err();
+        assert!(err.to_string().starts_with("the chain forks after"));
+
+        let dangling = entry(1, 1, Some("bafyreia"), 3);
+        let err = verify_audit_chain(&[genesis, rotation, dangling]).unwrap_err();
+        assert!(err.to_string().contains("1 operation(s) remain"));
+        Ok(())
+    }
+
+    #[test]
+    fn audit_cid_validation() {
+        let operation = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {},
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {},
+            "prev": null,
+            "sig": "c2ln",
+        });
+        let good = AuditEntry {
+            cid: operation_cid(&operation).unwrap(),
+            operation,
+            created_at: Utc::now(),
+            nullified: false,
+        };
+        validate_audit_cids(std::slice::from_ref(&good)).unwrap();
+
+        let mut corrupted = good.clone();
+        corrupted.operation["alsoKnownAs"] = json!(["at://mallory.example.com"]);
+        let computed = operation_cid(&corrupted.operation).unwrap();
+        let err = validate_audit_cids(&[good.clone(), corrupted]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "audit log entries do not match their operations: {} (computed {})",
+                good.cid, computed
+            )
+        );
+    }
+
+    #[test]
+    fn rotation_key_capacity() {
+        let document = json!({
+            "rotationKeys": [
+                "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg",
+                "did:key:zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+                "did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169",
+                "did:key:zDnaeUm2vWMcnwBNvPyoyjVkJrQxgEyGaThZBc35G2KmvT9p6",
+            ]
+        });
+        assert!(check_rotation_key_capacity(&document, 1).is_ok());
+
+        let mut full_document = document.clone();
+        full_document["rotationKeys"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!(
+                "did:key:zDnaetJfNkWxCJLsDCGGNmYAv9jYmAd6SDmRn6GVQKqZAXcpj"
+            ));
+        let err = check_rotation_key_capacity(&full_document, 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("zDnaetJfNkWxCJLsDCGGNmYAv9jYmAd6SDmRn6GVQKqZAXcpj"));
+    }
+
+    #[test]
+    fn did_web_document() {
+        let operation = json!({
+            "type": "plc_operation",
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {
+                "atproto": "did:key:zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+            },
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com",
+                }
+            },
+            "prev": "bafyreia",
+        });
+
+        let document = to_did_web_document(&operation, "did:web:alice.example.com").unwrap();
+        assert_eq!(document["id"], "did:web:alice.example.com");
+        assert_eq!(document["alsoKnownAs"], json!(["at://alice.example.com"]));
+        assert_eq!(
+            document["verificationMethod"],
+            json!([{
+                "id": "did:web:alice.example.com#atproto",
+                "type": "Multikey",
+                "controller": "did:web:alice.example.com",
+                "publicKeyMultibase": "zQ3shP2mWsZYWgvgM11GKWnzHWBkMUsbbqA8gzaJzQMmUNXWk",
+            }])
+        );
+        assert_eq!(
+            document["service"],
+            json!([{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com",
+            }])
+        );
+
+        assert!(to_did_web_document(&operation, "did:plc:cbkjy5n7bk3ax2wplmtjofq2").is_err());
+    }
+
+    #[test]
+    fn normalize_plc_hostnames() {
+        for input in [
+            "plc.directory",
+            "https://plc.directory",
+            "https://plc.directory/",
+            " PLC.directory// ",
+        ] {
+            assert_eq!(normalize_plc_hostname(input).unwrap(), "plc.directory");
+        }
+        assert_eq!(
+            normalize_plc_hostname("plc.example.com:8443/").unwrap(),
+            "plc.example.com:8443"
+        );
+        assert_eq!(
+            normalize_plc_hostname("http://localhost:2582/").unwrap(),
+            "http://localhost:2582"
+        );
+        assert!(normalize_plc_hostname("https://https://plc.directory").is_err());
+        assert!(normalize_plc_hostname("plc directory").is_err());
+        assert!(normalize_plc_hostname("plc.directory:port").is_err());
+        assert!(normalize_plc_hostname("").is_err());
+    }
+
+    #[test]
+    fn plc_environments() -> Result<()> {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            plc_hostname_from_args(&args(&["--env=production"]))?,
+            "plc.directory"
+        );
+        assert_eq!(
+            plc_hostname_from_args(&args(&["--env=sandbox"]))?,
+            "plc.pyroclastic.cloud"
+        );
+        assert_eq!(plc_hostname_from_args(&args(&[]))?, DEFAULT_PLC_HOSTNAME);
+        assert_eq!(
+            plc_hostname_from_args(&args(&[
+                "--env=production",
+                "--plc=https://plc.example.com/"
+            ]))?,
+            "plc.example.com"
+        );
+        assert!(plc_hostname_from_args(&args(&["--env=staging"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn plc_urls() {
+        assert_eq!(
+            plc_url("plc.directory", "did:plc:cbkjy5n7bk3ax2wplmtjofq2"),
+            "https://plc.directory/did:plc:cbkjy5n7bk3ax2wplmtjofq2"
+        );
+        assert_eq!(
+            plc_url(
+                "http://localhost:2582/",
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2/data"
+            ),
+            "http://localhost:2582/did:plc:cbkjy5n7bk3ax2wplmtjofq2/data"
+        );
+    }
+
+    #[tokio::test]
+    async fn did_not_found_on_directory() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/did:plc:cbkjy5n7bk3ax2wplmtjofq2"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let http_client = reqwest::Client::new();
+        assert!(!did_exists(
+            &http_client,
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2"
+        )
+        .await
+        .unwrap());
+
+        let err = ensure_did_exists(
+            &http_client,
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+        )
+        .await
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is this the correct PLC directory?"));
+    }
+
+    #[tokio::test]
+    async fn directory_health() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/_health"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let http_client = reqwest::Client::new();
+        assert!(check_directory_health(&http_client, &server.uri())
+            .await
+            .is_ok());
+
+        let unhealthy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/_health"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&unhealthy)
+            .await;
+        let err = check_directory_health(&http_client, &unhealthy.uri())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not healthy"), "{}", err);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let err = check_directory_health(&http_client, &closed)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not responding"), "{}", err);
+    }
+
+    #[test]
+    fn submit_curl_command() {
+        let operation = json!({ "type": "plc_tombstone", "prev": "it's" });
+        assert_eq!(
+            curl_command(
+                "plc.directory",
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                &operation
+            )
+            .unwrap(),
+            r#"curl -X POST 'https://plc.directory/did:plc:cbkjy5n7bk3ax2wplmtjofq2' -H 'Content-Type: application/json' --data-raw '{"prev":"it'\''s","type":"plc_tombstone"}'"#
+        );
+    }
+
+    fn valid_operation_data() -> serde_json::Value {
+        json!({
+            "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+            "verificationMethods": {
+                "atproto": "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo"
+            },
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            }
+        })
+    }
+
+    fn failed_checks(operation: &serde_json::Value) -> Vec<&'static str> {
+        check_invariants(operation)
+            .into_iter()
+            .filter(|check| !check.passed())
+            .map(|check| check.name)
+            .collect()
+    }
+
+    #[test]
+    fn invariants_of_valid_documents() {
+        let data = valid_operation_data();
+        assert_eq!(check_invariants(&data).len(), 5);
+        assert!(failed_checks(&data).is_empty());
+
+        let mut genesis = data.clone();
+        genesis["type"] = json!("plc_operation");
+        genesis["prev"] = json!(null);
+        assert!(failed_checks(&genesis).is_empty());
+
+        let tombstone = json!({ "type": "plc_tombstone", "prev": "bafyreid" });
+        assert_eq!(check_invariants(&tombstone).len(), 1);
+        assert!(failed_checks(&tombstone).is_empty());
+    }
+
+    #[test]
+    fn invariants_of_invalid_documents() {
+        let invalid = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut operation = valid_operation_data();
+            change(&mut operation);
+            failed_checks(&operation)
+        };
+
+        assert_eq!(
+            invalid(&|operation| operation["type"] = json!("plc_operation")),
+            vec!["prev"]
+        );
+        assert_eq!(
+            invalid(&|operation| operation["rotationKeys"] = json!([])),
+            vec!["rotation keys"]
+        );
+        assert_eq!(
+            invalid(&|operation| operation["rotationKeys"] = json!(vec![
+                "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+                6
+            ])),
+            vec!["rotation keys"]
+        );
+        assert_eq!(
+            invalid(&|operation| operation["rotationKeys"] = json!(["did:key:zQ3shA"])),
+            vec!["rotation keys"]
+        );
+        assert_eq!(
+            invalid(&|operation| {
+                operation["verificationMethods"] = json!({});
+                operation["alsoKnownAs"] = json!(["https://alice.example.com"]);
+            }),
+            vec!["atproto verification method", "handles"]
+        );
+        assert_eq!(
+            invalid(&|operation| operation["alsoKnownAs"] = json!(["at://alice.invalid"])),
+            vec!["handles"]
+        );
+        assert_eq!(
+            invalid(&|operation| {
+                operation["services"]["atproto_pds"]["endpoint"] = json!("pds.example.com")
+            }),
+            vec!["PDS endpoint"]
+        );
+        assert_eq!(
+            invalid(&|operation| {
+                operation["services"]["atproto_pds"]["endpoint"] = json!("ftp://pds.example.com")
+            }),
+            vec!["PDS endpoint"]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use rand::Rng;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Requests per second batch flows make when `--rate-limit` is not given.
+pub(crate) const DEFAULT_BATCH_RATE_LIMIT: u32 = 5;
+
+/// A token bucket holding up to `rate` requests, refilled at `rate` per
+/// second, with up to a tenth of the refill interval of jitter on requests
+/// that wait for a token. A run can burst up to a second's worth of requests, after which
+/// they are spaced `1 / rate` seconds apart. Clones share the same bucket, so
+/// one limiter covers every request a run makes.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    interval: Option<Duration>,
+    burst: u32,
+    /// When the bucket will next be full, as in the generic cell rate
+    /// algorithm: each request pushes it back by one interval.
+    full_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn per_second(rate: u32) -> Self {
+        let rate = rate.max(1);
+        Self {
+            interval: Some(Duration::from_secs(1) / rate),
+            burst: rate,
+            full_at: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub(crate) fn unlimited() -> Self {
+        Self {
+            interval: None,
+            burst: 1,
+            full_at: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub(crate) async fn wait(&self) {
+        if let Some(slot) = self.claim(Instant::now()) {
+            tokio::time::sleep_until(slot).await;
+        }
+    }
+
+    /// Takes the next token as of `now`, returning when the request may be
+    /// sent, or `None` when the limiter is unlimited.
+    fn claim(&self, now: Instant) -> Option<Instant> {
+        let interval = self.interval?;
+        let mut full_at = self.full_at.lock().expect("rate limiter lock poisoned");
+        let full = full_at.map_or(now, |full_at| full_at.max(now));
+        // A token is available once the bucket is at most `burst - 1`
+        // intervals short of full.
+        let slot = full
+            .checked_sub(interval * (self.burst - 1))
+            .map_or(now, |slot| slot.max(now));
+        // Jitter only delays requests that already wait for a token, so a
+        // burst is not cut short.
+        let jitter = if slot > now {
+            rand::thread_rng().gen_range(Duration::ZERO..=interval / 10)
+        } else {
+            Duration::ZERO
+        };
+        *full_at = Some(full + interval + jitter);
+        Some(slot)
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_spaced() {
+        let limiter = RateLimiter::per_second(20);
+        let shared = limiter.clone();
+        let interval = Duration::from_millis(50);
+
+        // A full bucket lets a second's worth of requests through at once,
+        // and clones draw from the same bucket.
+        let now = Instant::now();
+        let claimed = (0..30)
+            .map(|index| {
+                if index % 2 == 0 {
+                    limiter.claim(now).unwrap()
+                } else {
+                    shared.claim(now).unwrap()
+                }
+            })
+            .collect::<Vec<Instant>>();
+        assert!(claimed[..20].iter().all(|slot| *slot == now));
+
+        // After that, requests are spaced one interval apart plus jitter.
+        for pair in claimed[19..].windows(2) {
+            let spacing = pair[1] - pair[0];
+            assert!(spacing >= interval, "{:?}", spacing);
+            assert!(spacing <= interval + interval / 10, "{:?}", spacing);
+        }
+
+        // The bucket refills while idle.
+        let later = now + Duration::from_secs(10);
+        assert!((0..20).all(|_| limiter.claim(later).unwrap() == later));
+        assert!(limiter.claim(later).unwrap() >= later + interval);
+    }
+
+    #[tokio::test]
+    async fn unlimited_does_not_wait() {
+        let limiter = RateLimiter::unlimited();
+        let started = Instant::now();
+        for _ in 0..100 {
+            limiter.wait().await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(50));
+    }
+}
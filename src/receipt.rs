@@ -0,0 +1,132 @@
+//! Machine-readable receipts for submitted operations, written with
+//! `--receipt=PATH` as an audit trail of what was changed and when. Under
+//! `--dry-run`, a "would-submit" receipt records the signed operation that
+//! was not submitted.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use crate::plc::operation_cid;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Receipt {
+    pub did: String,
+    pub plc: String,
+    pub cid: String,
+    pub operation: serde_json::Value,
+
+    /// When the receipt was written. For a would-submit receipt, this is
+    /// when submission was skipped.
+    pub submitted_at: DateTime<Utc>,
+
+    /// False for a would-submit receipt written under `--dry-run`. Receipts
+    /// written before the field existed were all for submitted operations.
+    #[serde(default = "submitted_default")]
+    pub submitted: bool,
+}
+
+fn submitted_default() -> bool {
+    true
+}
+
+impl Receipt {
+    pub fn new(plc: &str, did: &str, operation: &serde_json::Value) -> Result<Self> {
+        Ok(Self {
+            did: did.to_string(),
+            plc: plc.to_string(),
+            cid: operation_cid(operation)?,
+            operation: operation.clone(),
+            submitted_at: Utc::now(),
+            submitted: true,
+        })
+    }
+
+    /// A receipt for an operation that was signed but, under `--dry-run`,
+    /// not submitted.
+    pub fn would_submit(plc: &str, did: &str, operation: &serde_json::Value) -> Result<Self> {
+        Ok(Self {
+            submitted: false,
+            ..Self::new(plc, did, operation)?
+        })
+    }
+}
+
+/// Appends a receipt to `path` as a single line of JSON, so a file shared by
+/// several runs, or by a batch, accumulates one receipt per submission.
+pub fn append_receipt(path: &Path, receipt: &Receipt) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open receipt file {}", path.display()))?;
+    let mut line = serde_json::to_vec(receipt)?;
+    line.push(b'\n');
+    file.write_all(&line)
+        .with_context(|| format!("failed to write receipt file {}", path.display()))
+}
+
+/// Reads every receipt in a file written by `append_receipt`.
+pub fn read_receipts(path: &Path) -> Result<Vec<Receipt>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read receipt file {}", path.display()))?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).context("invalid receipt"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn receipts_are_appended() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("tandem-receipts-{}.jsonl", std::process::id()));
+        let operation = json!({
+            "type": "plc_tombstone",
+            "prev": "bafyreid",
+            "sig": "abc",
+        });
+
+        let first = Receipt::new(
+            "plc.directory",
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            &operation,
+        )?;
+        assert_eq!(first.cid, operation_cid(&operation)?);
+        append_receipt(&path, &first)?;
+        let second = Receipt::new(
+            "plc.directory",
+            "did:plc:ewvi7nxzyoun6zhxrhs64oiz",
+            &operation,
+        )?;
+        append_receipt(&path, &second)?;
+        let dry_run = Receipt::would_submit(
+            "plc.directory",
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            &operation,
+        )?;
+        assert!(!dry_run.submitted);
+        append_receipt(&path, &dry_run)?;
+
+        assert_eq!(read_receipts(&path)?, vec![first, second, dry_run]);
+
+        // Receipts from before `submitted` was recorded were all submitted.
+        let legacy: Receipt = serde_json::from_value(json!({
+            "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "plc": "plc.directory",
+            "cid": operation_cid(&operation)?,
+            "operation": operation,
+            "submitted_at": "2024-01-01T00:00:00Z",
+        }))?;
+        assert!(legacy.submitted);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
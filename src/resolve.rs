@@ -1,27 +1,237 @@
 use anyhow::{anyhow, Result};
+use futures::{
+    future::{BoxFuture, FutureExt, Shared},
+    StreamExt,
+};
 use hickory_resolver::{
-    config::{ResolverConfig, ResolverOpts},
-    AsyncResolver,
+    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    AsyncResolver, TokioAsyncResolver,
 };
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::plc::plc_query;
+use crate::{
+    plc::{did_document_query, plc_url},
+    trace::TracedSend,
+    xrpc::PdsClient,
+};
+
+/// A public DNS-over-HTTPS provider that answers JSON queries.
+pub const DEFAULT_DOH_PROVIDER: &str = "https://cloudflare-dns.com/dns-query";
+
+/// How handles are resolved.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolveOptions {
+    /// A DNS-over-HTTPS endpoint answering `application/dns-json` queries.
+    /// When set, `_atproto` TXT lookups go over HTTPS instead of port 53,
+    /// which restrictive networks often block.
+    pub doh_provider: Option<String>,
+
+    /// Nameservers `_atproto` TXT lookups are sent to instead of the default
+    /// resolver configuration, such as a local testbed's DNS server. Ignored
+    /// when `doh_provider` is set.
+    pub nameservers: Vec<SocketAddr>,
+}
+
+impl ResolveOptions {
+    /// Reads `--doh`, which uses `DEFAULT_DOH_PROVIDER`, or `--doh=URL`, and
+    /// any number of `--nameserver=IP[:PORT]`, where the port defaults to 53.
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let doh_provider = args.iter().find_map(|arg| {
+            if arg == "--doh" {
+                Some(DEFAULT_DOH_PROVIDER.to_string())
+            } else {
+                arg.strip_prefix("--doh=").map(|url| url.to_string())
+            }
+        });
+        let nameservers = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("--nameserver="))
+            .map(parse_nameserver)
+            .collect::<Result<Vec<SocketAddr>>>()?;
+        Ok(Self {
+            doh_provider,
+            nameservers,
+        })
+    }
+
+    /// The resolver configuration for `nameservers`, or the default one when
+    /// none are given. Each nameserver is queried over UDP and TCP.
+    fn resolver_config(&self) -> ResolverConfig {
+        if self.nameservers.is_empty() {
+            return ResolverConfig::default();
+        }
+        let name_servers = self
+            .nameservers
+            .iter()
+            .flat_map(|addr| {
+                [Protocol::Udp, Protocol::Tcp]
+                    .map(|protocol| NameServerConfig::new(*addr, protocol))
+            })
+            .collect::<Vec<NameServerConfig>>();
+        ResolverConfig::from_parts(None, vec![], name_servers)
+    }
+}
+
+fn parse_nameserver(nameserver: &str) -> Result<SocketAddr> {
+    nameserver
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            nameserver
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, 53))
+        })
+        .map_err(|_| {
+            anyhow!(
+                "invalid --nameserver {}: expected an IP address with an optional port",
+                nameserver
+            )
+        })
+}
+
+/// Where TXT records are looked up.
+#[derive(Clone)]
+enum DnsTransport {
+    System(Box<TokioAsyncResolver>),
+    Https {
+        http_client: reqwest::Client,
+        provider: String,
+    },
+}
+
+impl DnsTransport {
+    fn new(
+        http_client: &reqwest::Client,
+        config: ResolverConfig,
+        opts: ResolverOpts,
+        options: &ResolveOptions,
+    ) -> Self {
+        match &options.doh_provider {
+            Some(provider) => DnsTransport::Https {
+                http_client: http_client.clone(),
+                provider: provider.clone(),
+            },
+            None => DnsTransport::System(Box::new(AsyncResolver::tokio(config, opts))),
+        }
+    }
+
+    async fn txt_records(&self, name: &str) -> Result<Vec<String>> {
+        match self {
+            DnsTransport::System(resolver) => Ok(resolver
+                .txt_lookup(name)
+                .await?
+                .iter()
+                .map(|record| record.to_string())
+                .collect()),
+            DnsTransport::Https {
+                http_client,
+                provider,
+            } => doh_txt_records(http_client, provider, name).await,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(serde::Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// The DNS record type of TXT records.
+const TXT_RECORD_TYPE: u16 = 16;
+
+/// Looks up TXT records with a JSON DNS-over-HTTPS query.
+async fn doh_txt_records(
+    http_client: &reqwest::Client,
+    provider: &str,
+    name: &str,
+) -> Result<Vec<String>> {
+    let response = http_client
+        .get(provider)
+        .query(&[("name", name), ("type", "TXT")])
+        .header("Accept", "application/dns-json")
+        .timeout(Duration::from_secs(10))
+        .send_traced()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "DNS-over-HTTPS provider {} returned status {}",
+            provider,
+            response.status()
+        ));
+    }
+
+    let response: DohResponse = response.json().await?;
+    // Status 3 is NXDOMAIN, which is an answer rather than a failure.
+    if response.status != 0 && response.status != 3 {
+        return Err(anyhow!(
+            "DNS-over-HTTPS lookup of {} failed with DNS status {}",
+            name,
+            response.status
+        ));
+    }
+
+    Ok(response
+        .answer
+        .iter()
+        .filter(|answer| answer.record_type == TXT_RECORD_TYPE)
+        .map(|answer| txt_data(&answer.data))
+        .collect())
+}
+
+/// Joins the quoted character strings of a TXT record's presentation form.
+fn txt_data(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    data.split('"').skip(1).step_by(2).collect()
+}
 
 pub async fn resolve_handle_dns(handle: &str) -> Result<String> {
-    let lookup_dns = format!("_atproto.{}", handle);
     let resolver = AsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    resolve_handle_dns_with(&DnsTransport::System(Box::new(resolver)), handle).await
+}
+
+/// Resolves a handle's `_atproto` TXT record through a DNS-over-HTTPS provider.
+pub async fn resolve_handle_doh(
+    http_client: &reqwest::Client,
+    provider: &str,
+    handle: &str,
+) -> Result<String> {
+    let transport = DnsTransport::Https {
+        http_client: http_client.clone(),
+        provider: provider.to_string(),
+    };
+    resolve_handle_dns_with(&transport, handle).await
+}
+
+async fn resolve_handle_dns_with(transport: &DnsTransport, handle: &str) -> Result<String> {
+    let records = transport.txt_records(&txt_lookup_name(handle)).await?;
+    did_from_txt_records(handle, &records)
+}
 
-    let lookup = resolver.txt_lookup(lookup_dns.clone()).await?;
+/// The name a handle's `_atproto` TXT record is published under.
+pub(crate) fn txt_lookup_name(handle: &str) -> String {
+    format!("_atproto.{}", handle)
+}
 
-    let dids = lookup
+/// Picks the DID out of a handle's `_atproto` TXT records, erroring when
+/// there is none or the records disagree.
+pub(crate) fn did_from_txt_records(handle: &str, records: &[String]) -> Result<String> {
+    let dids = records
         .iter()
-        .filter_map(|record| {
-            record
-                .to_string()
-                .strip_prefix("did=")
-                .map(|did| did.to_string())
-        })
+        .filter_map(|record| record.strip_prefix("did=").map(|did| did.to_string()))
         .collect::<HashSet<String>>();
 
     if dids.len() > 1 {
@@ -35,29 +245,225 @@ pub async fn resolve_handle_dns(handle: &str) -> Result<String> {
 }
 
 pub async fn resolve_handle_http(http_client: &reqwest::Client, handle: &str) -> Result<String> {
-    let lookup_url = format!("https://{}/.well-known/atproto-did", handle);
+    let lookup_url = well_known_url(handle);
 
-    http_client
+    let body = http_client
         .get(lookup_url.clone())
-        .timeout(Duration::from_secs(10))
-        .send()
+        .timeout(HTTP_LOOKUP_TIMEOUT)
+        .send_traced()
         .await?
         .text()
-        .await
-        .map_err(|err| err.into())
-        .and_then(|body| {
-            if body.starts_with("did:") {
-                Ok(body.to_string())
-            } else {
-                Err(anyhow!("Invalid response from {}", lookup_url))
-            }
-        })
+        .await?;
+    did_from_well_known(&lookup_url, body)
+}
+
+/// How long a handle's HTTPS well-known lookup may take.
+pub(crate) const HTTP_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL of a handle's HTTPS well-known document.
+pub(crate) fn well_known_url(handle: &str) -> String {
+    format!("https://{}/.well-known/atproto-did", handle)
+}
+
+/// Checks that the body of a handle's well-known document is a DID.
+pub(crate) fn did_from_well_known(lookup_url: &str, body: String) -> Result<String> {
+    if body.starts_with("did:") {
+        Ok(body)
+    } else {
+        Err(anyhow!("Invalid response from {}", lookup_url))
+    }
 }
 
+/// Whether a handle's DNS TXT record and HTTPS well-known document each name
+/// the expected DID.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandleVerification {
+    pub dns_verified: bool,
+    pub http_verified: bool,
+}
+
+#[derive(Clone, Debug)]
 pub struct ResolvedHandle {
     pub did: String,
     pub pds: String,
+
+    /// Handles the DID claims that also resolve back to it.
     pub handles: Vec<String>,
+
+    /// Handles the DID claims that did not resolve back to it, which may be
+    /// stale or spoofed and should not be shown as confirmed.
+    pub unverified_handles: Vec<String>,
+}
+
+type SharedResolution = Shared<BoxFuture<'static, Result<ResolvedHandle, Arc<anyhow::Error>>>>;
+
+/// Resolves handles and DIDs, sharing results between callers so repeated
+/// and concurrent lookups of the same subject only hit the network once.
+#[derive(Clone)]
+pub struct Resolver {
+    http_client: reqwest::Client,
+    plc_hostname: String,
+    dns_transport: DnsTransport,
+    cache: Arc<Mutex<HashMap<String, SharedResolution>>>,
+}
+
+impl Resolver {
+    pub fn new(http_client: &reqwest::Client, plc_hostname: &str) -> Self {
+        Self::with_config(
+            http_client,
+            plc_hostname,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        )
+    }
+
+    pub fn with_config(
+        http_client: &reqwest::Client,
+        plc_hostname: &str,
+        config: ResolverConfig,
+        opts: ResolverOpts,
+    ) -> Self {
+        Self::build(
+            http_client,
+            plc_hostname,
+            config,
+            opts,
+            &ResolveOptions::default(),
+        )
+    }
+
+    pub fn with_options(
+        http_client: &reqwest::Client,
+        plc_hostname: &str,
+        options: &ResolveOptions,
+    ) -> Self {
+        Self::build(
+            http_client,
+            plc_hostname,
+            options.resolver_config(),
+            ResolverOpts::default(),
+            options,
+        )
+    }
+
+    fn build(
+        http_client: &reqwest::Client,
+        plc_hostname: &str,
+        config: ResolverConfig,
+        opts: ResolverOpts,
+        options: &ResolveOptions,
+    ) -> Self {
+        Self {
+            http_client: http_client.clone(),
+            plc_hostname: plc_hostname.to_string(),
+            dns_transport: DnsTransport::new(http_client, config, opts, options),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn resolve(&self, subject: &str) -> Result<ResolvedHandle> {
+        let resolver = self.clone();
+        self.resolve_with(subject, move |subject| {
+            async move {
+                resolve_subject(
+                    &resolver.http_client,
+                    &resolver.dns_transport,
+                    &resolver.plc_hostname,
+                    &subject,
+                )
+                .await
+            }
+            .boxed()
+        })
+        .await
+    }
+
+    /// Resolves each subject with at most `concurrency` lookups in flight,
+    /// returning every subject paired with its own result in completion
+    /// order. A failed lookup does not stop the others.
+    pub async fn resolve_many(
+        &self,
+        subjects: Vec<String>,
+        concurrency: usize,
+    ) -> Vec<(String, Result<ResolvedHandle>)> {
+        let resolver = self.clone();
+        self.resolve_many_with(subjects, concurrency, move |subject| {
+            let resolver = resolver.clone();
+            async move { resolver.resolve(&subject).await }.boxed()
+        })
+        .await
+    }
+
+    async fn resolve_many_with<F>(
+        &self,
+        subjects: Vec<String>,
+        concurrency: usize,
+        lookup: F,
+    ) -> Vec<(String, Result<ResolvedHandle>)>
+    where
+        F: Fn(String) -> BoxFuture<'static, Result<ResolvedHandle>>,
+    {
+        futures::stream::iter(subjects)
+            .map(|subject| {
+                let resolution = lookup(subject.clone());
+                async move { (subject, resolution.await) }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Resolves a handle's `_atproto` TXT record with the resolver's DNS
+    /// configuration, reusing its DNS resolver between calls.
+    pub async fn resolve_handle_dns(&self, handle: &str) -> Result<String> {
+        resolve_handle_dns_with(&self.dns_transport, handle).await
+    }
+
+    /// Checks each of a handle's resolution methods against `did`. Lookups
+    /// that fail count as unverified rather than as errors.
+    pub async fn verify_handle(&self, handle: &str, did: &str) -> HandleVerification {
+        let (dns, http) = futures::join!(
+            resolve_handle_dns_with(&self.dns_transport, handle),
+            resolve_handle_http(&self.http_client, handle)
+        );
+        HandleVerification {
+            dns_verified: dns.is_ok_and(|resolved| resolved == did),
+            http_verified: http.is_ok_and(|resolved| resolved == did),
+        }
+    }
+
+    async fn resolve_with<F>(&self, subject: &str, lookup: F) -> Result<ResolvedHandle>
+    where
+        F: FnOnce(String) -> BoxFuture<'static, Result<ResolvedHandle>>,
+    {
+        let resolution = {
+            let mut cache = self.cache.lock().expect("resolver cache lock poisoned");
+            cache
+                .entry(subject.to_string())
+                .or_insert_with(|| {
+                    lookup(subject.to_string())
+                        .map(|result| result.map_err(Arc::new))
+                        .boxed()
+                        .shared()
+                })
+                .clone()
+        };
+
+        let result = resolution.clone().await;
+
+        if result.is_err() {
+            // Failed lookups are not cached so that a later call can retry.
+            let mut cache = self.cache.lock().expect("resolver cache lock poisoned");
+            if cache
+                .get(subject)
+                .is_some_and(|cached| cached.ptr_eq(&resolution))
+            {
+                cache.remove(subject);
+            }
+        }
+
+        result.map_err(|err| anyhow!("{:#}", err))
+    }
 }
 
 pub async fn resolve_handle(
@@ -65,87 +471,741 @@ pub async fn resolve_handle(
     plc_hostname: &str,
     subject: &str,
 ) -> Result<ResolvedHandle> {
-    let mut resolved_dids: HashSet<String> = HashSet::new();
-    let mut unresolved_dids: HashSet<String> = HashSet::new();
-    let mut resolved_handles: HashSet<String> = HashSet::new();
-    let mut unresolved_handles: HashSet<String> = HashSet::new();
+    Resolver::new(http_client, plc_hostname)
+        .resolve(subject)
+        .await
+}
+
+/// Asks a PDS who holds a handle, returning the DID when the handle is taken
+/// or `None` when it is available.
+pub async fn check_handle_on_pds(
+    http_client: &reqwest::Client,
+    pds: &str,
+    handle: &str,
+) -> Result<Option<String>> {
+    PdsClient::unauthenticated(http_client, pds)?
+        .resolve_handle(handle)
+        .await
+}
+
+/// Returns the URL of a `did:web` DID's document. The method-specific id is
+/// a domain, optionally followed by colon-separated path segments, each
+/// percent-decoded, so `did:web:example.com%3A3000` is served from
+/// `https://example.com:3000/.well-known/did.json` and
+/// `did:web:example.com:user:alice` from `https://example.com/user/alice/did.json`.
+pub fn did_web_document_url(did: &str) -> Result<String> {
+    let id = did
+        .strip_prefix("did:web:")
+        .ok_or_else(|| anyhow!("{} is not a did:web DID", did))?;
+    let segments = id
+        .split(':')
+        .map(percent_decode)
+        .collect::<Result<Vec<String>>>()
+        .map_err(|err| anyhow!("invalid did:web DID {}: {}", did, err))?;
+    let (domain, path) = segments
+        .split_first()
+        .filter(|(domain, _)| !domain.is_empty())
+        .ok_or_else(|| anyhow!("did:web DID {} has no domain", did))?;
+
+    let url = if path.is_empty() {
+        format!("https://{}/.well-known/did.json", domain)
+    } else {
+        format!("https://{}/{}/did.json", domain, path.join("/"))
+    };
+    let parsed =
+        reqwest::Url::parse(&url).map_err(|err| anyhow!("invalid did:web DID {}: {}", did, err))?;
+    if parsed.host_str() != Some(domain.split(':').next().unwrap_or(domain)) {
+        return Err(anyhow!(
+            "invalid did:web DID {}: bad domain {}",
+            did,
+            domain
+        ));
+    }
+    Ok(url)
+}
 
-    let mut found_pds: HashSet<String> = HashSet::new();
-    let mut found_handles: HashSet<String> = HashSet::new();
-    let mut found_dids: HashSet<String> = HashSet::new();
+/// Decodes `%XX` escapes in a did:web segment.
+fn percent_decode(segment: &str) -> Result<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = segment
+                .get(index + 1..index + 3)
+                .ok_or_else(|| anyhow!("truncated escape in {}", segment))?;
+            decoded.push(
+                u8::from_str_radix(hex, 16)
+                    .map_err(|_| anyhow!("invalid escape %{} in {}", hex, segment))?,
+            );
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| anyhow!("{} is not UTF-8", segment))
+}
 
-    if subject.starts_with("did:") {
-        unresolved_dids.insert(subject.to_string());
+/// Returns a DID's PDS endpoints and handles, from the PLC directory for
+/// `did:plc` and from the domain's DID document for `did:web`.
+async fn did_query(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<(Vec<String>, Vec<String>)> {
+    did_document_query(http_client, &did_document_url(plc_hostname, did)?).await
+}
+
+/// The URL of a DID's document: on the PLC directory for `did:plc`, and on
+/// the domain itself for `did:web`.
+pub(crate) fn did_document_url(plc_hostname: &str, did: &str) -> Result<String> {
+    if did.starts_with("did:web:") {
+        did_web_document_url(did)
     } else {
-        unresolved_handles.insert(subject.to_string());
+        Ok(plc_url(plc_hostname, did))
+    }
+}
+
+/// Resolves a DID directly through the PLC directory, or its own document for
+/// `did:web`, without requiring it to have an `at://` handle. Its handles are
+/// not looked up and are all returned as `unverified_handles`, so this is only
+/// suitable for inspection; actions that mutate a handle should use
+/// `resolve_handle` instead.
+pub async fn resolve_did_only(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<ResolvedHandle> {
+    if !did.starts_with("did:") {
+        return Err(anyhow!("{} is not a DID", did));
     }
 
-    let mut iterations = 0;
-    loop {
-        iterations += 1;
-        if iterations > 10 {
+    let (pds, handles) = did_query(http_client, plc_hostname, did).await?;
+    did_only_resolution(did, pds, handles)
+}
+
+/// Builds the `resolve_did_only` result from the DID document's PDS
+/// endpoints and `alsoKnownAs` entries.
+pub(crate) fn did_only_resolution(
+    did: &str,
+    pds: Vec<String>,
+    handles: Vec<String>,
+) -> Result<ResolvedHandle> {
+    let mut pds = pds.into_iter().collect::<HashSet<String>>().into_iter();
+    let found_pds = pds
+        .next()
+        .ok_or(anyhow!("No PDSs found for subject {}", did))?;
+    if pds.next().is_some() {
+        return Err(anyhow!("Multiple PDSs found for subject {}", did));
+    }
+
+    // Only `at://` entries are handles; other aliases such as `https://`
+    // URLs keep their scheme in `did_document_query` results.
+    let handles = handles
+        .into_iter()
+        .filter(|handle| !handle.contains("://"))
+        .collect();
+
+    Ok(ResolvedHandle {
+        did: did.to_string(),
+        pds: found_pds,
+        handles: vec![],
+        unverified_handles: handles,
+    })
+}
+
+/// Errors when a handle's DNS TXT record and HTTPS well-known document name
+/// different DIDs, which points to a misconfiguration or a takeover attempt.
+fn check_handle_agreement(handle: &str, dns: Option<&str>, http: Option<&str>) -> Result<()> {
+    match (dns, http) {
+        (Some(dns), Some(http)) if dns != http => Err(anyhow!(
+            "handle {} resolves to {} via DNS TXT (_atproto.{}) but to {} via HTTPS (https://{}/.well-known/atproto-did); one of them may be misconfigured or hijacked",
+            handle,
+            dns,
+            handle,
+            http,
+            handle
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn resolve_subject(
+    http_client: &reqwest::Client,
+    dns_transport: &DnsTransport,
+    plc_hostname: &str,
+    subject: &str,
+) -> Result<ResolvedHandle> {
+    let mut resolution = SubjectResolution::new(subject);
+    while let Some((next_did, next_handle)) = resolution.next_lookups()? {
+        if let Some(next_did) = next_did {
+            let query_res = did_query(http_client, plc_hostname, &next_did).await;
+            resolution.add_did_document(query_res.ok());
+        }
+
+        if let Some(next_handle) = next_handle {
+            // Both lookups run at once so a handle with only one method does
+            // not wait out the other's timeout first.
+            let (http_resolve, dns_resolve) = futures::join!(
+                resolve_handle_http(http_client, &next_handle),
+                resolve_handle_dns_with(dns_transport, &next_handle)
+            );
+            resolution.add_handle_lookups(&next_handle, http_resolve.ok(), dns_resolve.ok())?;
+        }
+    }
+    resolution.finish()
+}
+
+/// The state of resolving a subject, walking between DID documents and
+/// handle lookups until neither turns up anything new. The lookups
+/// themselves are left to the caller, so the async and blocking resolvers
+/// share this logic.
+///
+/// Only the subject decides which DID is returned: the DID itself, or the
+/// DID its own handle lookups find. Handles the DID document claims are then
+/// looked up to verify them, and one that resolves elsewhere, or nowhere, is
+/// returned as unverified rather than failing the resolution.
+pub(crate) struct SubjectResolution {
+    subject: String,
+    iterations: usize,
+
+    resolved_dids: HashSet<String>,
+    unresolved_dids: HashSet<String>,
+    resolved_handles: HashSet<String>,
+    unresolved_handles: HashSet<String>,
+
+    found_pds: HashSet<String>,
+    found_handles: HashSet<String>,
+    // The subject's DID, once known.
+    found_did: Option<String>,
+    // The DIDs each handle's lookups found.
+    handle_dids: HashMap<String, Vec<String>>,
+}
+
+impl SubjectResolution {
+    pub(crate) fn new(subject: &str) -> Self {
+        let mut resolution = Self {
+            subject: subject.to_string(),
+            iterations: 0,
+            resolved_dids: HashSet::new(),
+            unresolved_dids: HashSet::new(),
+            resolved_handles: HashSet::new(),
+            unresolved_handles: HashSet::new(),
+            found_pds: HashSet::new(),
+            found_handles: HashSet::new(),
+            found_did: None,
+            handle_dids: HashMap::new(),
+        };
+        if subject.starts_with("did:") {
+            resolution.found_did = Some(subject.to_string());
+            resolution.unresolved_dids.insert(subject.to_string());
+        } else {
+            resolution.unresolved_handles.insert(subject.to_string());
+        }
+        resolution
+    }
+
+    /// Returns the next DID to query and the next handle to look up, or
+    /// `None` once both are exhausted.
+    pub(crate) fn next_lookups(&mut self) -> Result<Option<(Option<String>, Option<String>)>> {
+        self.iterations += 1;
+        if self.iterations > 10 {
             return Err(anyhow!("resolve_handle exceeded max iteration depth"));
         }
 
-        let next_did = unresolved_dids.difference(&resolved_dids).next().cloned();
-        let next_handle = &unresolved_handles
-            .difference(&resolved_handles)
+        let next_did = self
+            .unresolved_dids
+            .difference(&self.resolved_dids)
+            .next()
+            .cloned();
+        let next_handle = self
+            .unresolved_handles
+            .difference(&self.resolved_handles)
             .next()
             .cloned();
         if next_did.is_none() && next_handle.is_none() {
-            break;
+            return Ok(None);
         }
 
-        if let Some(next_did) = next_did {
-            resolved_dids.insert(next_did.to_string());
-            let query_res = plc_query(http_client, plc_hostname, &next_did).await;
-            if let Ok((pds, handles)) = query_res {
-                found_pds.extend(pds.clone());
-                found_handles.extend(handles.clone());
-                unresolved_handles.extend(handles);
-            }
+        self.resolved_dids.extend(next_did.clone());
+        self.resolved_handles.extend(next_handle.clone());
+        Ok(Some((next_did, next_handle)))
+    }
+
+    /// Records the PDS endpoints and handles of a queried DID document, or
+    /// nothing when the query failed.
+    pub(crate) fn add_did_document(&mut self, document: Option<(Vec<String>, Vec<String>)>) {
+        if let Some((pds, handles)) = document {
+            self.found_pds.extend(pds);
+            self.found_handles.extend(handles.clone());
+            self.unresolved_handles.extend(handles);
         }
+    }
 
-        if let Some(next_handle) = next_handle {
-            resolved_handles.insert(next_handle.to_string());
-            let http_resolve = resolve_handle_http(http_client, next_handle).await;
-            if let Ok(resolved_did) = http_resolve {
-                unresolved_dids.insert(resolved_did.clone());
-                found_dids.insert(resolved_did);
+    /// Records the DIDs a handle's HTTPS and DNS TXT lookups found, each
+    /// `None` when its lookup failed. The subject handle's lookups must agree,
+    /// and name the DID to query next; any other handle's only verify it.
+    pub(crate) fn add_handle_lookups(
+        &mut self,
+        handle: &str,
+        http_resolve: Option<String>,
+        dns_resolve: Option<String>,
+    ) -> Result<()> {
+        if handle == self.subject {
+            check_handle_agreement(handle, dns_resolve.as_deref(), http_resolve.as_deref())?;
+            if let Some(did) = dns_resolve.clone().or(http_resolve.clone()) {
+                self.unresolved_dids.insert(did.clone());
+                self.found_did = Some(did);
             }
+        }
+
+        self.handle_dids
+            .entry(handle.to_string())
+            .or_default()
+            .extend(http_resolve.into_iter().chain(dns_resolve));
+        Ok(())
+    }
+
+    /// Whether every lookup of `handle` that succeeded found `did`, and at
+    /// least one did.
+    fn resolves_to(&self, handle: &str, did: &str) -> bool {
+        self.handle_dids
+            .get(handle)
+            .is_some_and(|dids| !dids.is_empty() && dids.iter().all(|resolved| resolved == did))
+    }
+
+    pub(crate) fn finish(self) -> Result<ResolvedHandle> {
+        let subject = &self.subject;
+        if self.found_handles.is_empty() {
+            return Err(anyhow!("No handles found for subject {}", subject));
+        }
+        if self.found_pds.len() > 1 {
+            return Err(anyhow!("Multiple PDSs found for subject {}", subject));
+        }
+
+        let found_did = self
+            .found_did
+            .clone()
+            .ok_or(anyhow!("No DIDs found for subject {}", subject))?;
+        let found_pds = self
+            .found_pds
+            .iter()
+            .next()
+            .cloned()
+            .ok_or(anyhow!("No PDSs found for subject {}", subject))?;
+
+        let (mut handles, mut unverified_handles): (Vec<String>, Vec<String>) = self
+            .found_handles
+            .iter()
+            .cloned()
+            .partition(|handle| self.resolves_to(handle, &found_did));
+        handles.sort();
+        unverified_handles.sort();
+
+        Ok(ResolvedHandle {
+            did: found_did,
+            pds: found_pds,
+            handles,
+            unverified_handles,
+        })
+    }
+}
 
-            let dns_resolve = resolve_handle_dns(next_handle).await;
-            if let Ok(resolved_did) = dns_resolve {
-                unresolved_dids.insert(resolved_did.clone());
-                found_dids.insert(resolved_did);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wiremock::{
+        matchers::{header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn concurrent_resolves_share_one_lookup() {
+        let resolver = Resolver::new(&reqwest::Client::new(), "plc.example.com");
+        let lookups = Arc::new(AtomicUsize::new(0));
+
+        let results = futures::future::join_all((0..50).map(|_| {
+            let lookups = lookups.clone();
+            resolver.resolve_with("alice.example.com", move |subject| {
+                async move {
+                    lookups.fetch_add(1, Ordering::SeqCst);
+                    tokio::task::yield_now().await;
+                    Ok(ResolvedHandle {
+                        did: "did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+                        pds: "https://pds.example.com".to_string(),
+                        handles: vec![subject],
+                        unverified_handles: vec![],
+                    })
+                }
+                .boxed()
+            })
+        }))
+        .await;
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result.unwrap().did, "did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_many_pairs_results() {
+        let resolver = Resolver::new(&reqwest::Client::new(), "plc.example.com");
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let subjects = (0..20)
+            .map(|index| {
+                if index % 3 == 0 {
+                    format!("invalid-{}.example.com", index)
+                } else {
+                    format!("user-{}.example.com", index)
+                }
+            })
+            .collect::<Vec<String>>();
+        let results = resolver
+            .resolve_many_with(subjects.clone(), 4, |subject| {
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                async move {
+                    let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(running, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    if subject.starts_with("invalid-") {
+                        Err(anyhow!("No handles found for subject {}", subject))
+                    } else {
+                        Ok(ResolvedHandle {
+                            did: format!("did:plc:{}", subject.trim_end_matches(".example.com")),
+                            pds: "https://pds.example.com".to_string(),
+                            handles: vec![subject],
+                            unverified_handles: vec![],
+                        })
+                    }
+                }
+                .boxed()
+            })
+            .await;
+
+        assert_eq!(results.len(), subjects.len());
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 4);
+        for (subject, result) in &results {
+            if subject.starts_with("invalid-") {
+                assert!(result
+                    .as_ref()
+                    .unwrap_err()
+                    .to_string()
+                    .contains(subject.as_str()));
+            } else {
+                let resolved = result.as_ref().unwrap();
+                assert_eq!(resolved.handles, vec![subject.clone()]);
+                assert_eq!(
+                    resolved.did,
+                    format!("did:plc:{}", subject.trim_end_matches(".example.com"))
+                );
             }
         }
+        let mut returned = results
+            .iter()
+            .map(|(subject, _)| subject.clone())
+            .collect::<Vec<String>>();
+        returned.sort();
+        let mut expected = subjects;
+        expected.sort();
+        assert_eq!(returned, expected);
     }
 
-    if found_dids.len() > 1 {
-        return Err(anyhow!("Multiple DIDs found for subject {}", subject));
+    #[tokio::test]
+    async fn failed_resolves_are_retried() {
+        let resolver = Resolver::new(&reqwest::Client::new(), "plc.example.com");
+        let lookups = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..2 {
+            let lookups = lookups.clone();
+            let result = resolver
+                .resolve_with("alice.example.com", move |_| {
+                    async move {
+                        lookups.fetch_add(1, Ordering::SeqCst);
+                        Err(anyhow!("lookup failed"))
+                    }
+                    .boxed()
+                })
+                .await;
+            assert!(result.is_err());
+        }
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
     }
-    if found_handles.is_empty() {
-        return Err(anyhow!("No handles found for subject {}", subject));
+
+    #[tokio::test]
+    async fn resolve_did_without_handles() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/did:plc:cbkjy5n7bk3ax2wplmtjofq2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "alsoKnownAs": ["https://alice.example.com"],
+                "verificationMethod": [],
+                "service": [{
+                    "id": "#atproto_pds",
+                    "type": "AtprotoPersonalDataServer",
+                    "serviceEndpoint": "https://pds.example.com"
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let resolved = resolve_did_only(
+            &reqwest::Client::new(),
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+        )
+        .await
+        .unwrap();
+        assert_eq!(resolved.did, "did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+        assert_eq!(resolved.pds, "https://pds.example.com");
+        assert!(resolved.handles.is_empty());
+        assert!(resolved.unverified_handles.is_empty());
     }
-    if found_pds.len() > 1 {
-        return Err(anyhow!("Multiple PDSs found for subject {}", subject));
+
+    #[tokio::test]
+    async fn unverified_handles_are_separated() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/did:plc:cbkjy5n7bk3ax2wplmtjofq2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "alsoKnownAs": ["at://alice.test", "at://mallory.test"],
+                "verificationMethod": [],
+                "service": [{
+                    "id": "#atproto_pds",
+                    "type": "AtprotoPersonalDataServer",
+                    "serviceEndpoint": "https://pds.example.com"
+                }]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .and(query_param("name", "_atproto.alice.test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Status": 0,
+                "Answer": [
+                    { "name": "_atproto.alice.test.", "type": 16, "TTL": 300, "data": "\"did=did:plc:cbkjy5n7bk3ax2wplmtjofq2\"" }
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Status": 3 })),
+            )
+            .mount(&server)
+            .await;
+
+        let resolver = Resolver::with_options(
+            &reqwest::Client::new(),
+            &server.uri(),
+            &ResolveOptions {
+                doh_provider: Some(format!("{}/dns-query", server.uri())),
+                ..Default::default()
+            },
+        );
+        let resolved = resolver.resolve("did:plc:cbkjy5n7bk3ax2wplmtjofq2").await?;
+        assert_eq!(resolved.handles, vec!["alice.test"]);
+        assert_eq!(resolved.unverified_handles, vec!["mallory.test"]);
+        Ok(())
     }
 
-    let found_did = found_dids
-        .iter()
-        .next()
-        .cloned()
-        .ok_or(anyhow!("No DIDs found for subject {}", subject))?;
-    let found_pds = found_pds
-        .iter()
-        .next()
-        .cloned()
-        .ok_or(anyhow!("No PDSs found for subject {}", subject))?;
+    #[tokio::test]
+    async fn spoofed_handles_are_unverified() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/did:plc:cbkjy5n7bk3ax2wplmtjofq2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "alsoKnownAs": ["at://alice.test", "at://mallory.test"],
+                "verificationMethod": [],
+                "service": [{
+                    "id": "#atproto_pds",
+                    "type": "AtprotoPersonalDataServer",
+                    "serviceEndpoint": "https://pds.example.com"
+                }]
+            })))
+            .mount(&server)
+            .await;
+        for (handle, did) in [
+            ("alice.test", "did:plc:cbkjy5n7bk3ax2wplmtjofq2"),
+            ("mallory.test", "did:plc:ewvi7nxzyoun6zhxrhs64oiz"),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/dns-query"))
+                .and(query_param("name", format!("_atproto.{}", handle)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "Status": 0,
+                    "Answer": [
+                        { "name": format!("_atproto.{}.", handle), "type": 16, "TTL": 300, "data": format!("\"did={}\"", did) }
+                    ]
+                })))
+                .mount(&server)
+                .await;
+        }
 
-    Ok(ResolvedHandle {
-        did: found_did,
-        pds: found_pds,
-        handles: found_handles.iter().cloned().collect(),
-    })
+        let resolver = Resolver::with_options(
+            &reqwest::Client::new(),
+            &server.uri(),
+            &ResolveOptions {
+                doh_provider: Some(format!("{}/dns-query", server.uri())),
+                ..Default::default()
+            },
+        );
+        // The other DID mallory.test resolves to is never queried, so the
+        // claim is reported as unverified rather than as a second DID.
+        for subject in ["did:plc:cbkjy5n7bk3ax2wplmtjofq2", "alice.test"] {
+            let resolved = resolver.resolve(subject).await?;
+            assert_eq!(resolved.did, "did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+            assert_eq!(resolved.handles, vec!["alice.test"]);
+            assert_eq!(resolved.unverified_handles, vec!["mallory.test"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn did_web_document_urls() {
+        assert_eq!(
+            did_web_document_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            did_web_document_url("did:web:example.com%3A3000").unwrap(),
+            "https://example.com:3000/.well-known/did.json"
+        );
+        assert_eq!(
+            did_web_document_url("did:web:example.com:user:alice").unwrap(),
+            "https://example.com/user/alice/did.json"
+        );
+        assert_eq!(
+            did_web_document_url("did:web:localhost%3A2583:users%20a").unwrap(),
+            "https://localhost:2583/users a/did.json"
+        );
+
+        for invalid in [
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "did:web:",
+            "did:web:example.com%3",
+            "did:web:example.com%2Fpath",
+            "did:web:alice%40evil.example.com",
+        ] {
+            assert!(did_web_document_url(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_handle_over_doh() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .and(query_param("name", "_atproto.alice.example.com"))
+            .and(query_param("type", "TXT"))
+            .and(header("accept", "application/dns-json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "Status": 0,
+                "Answer": [
+                    { "name": "_atproto.alice.example.com.", "type": 5, "TTL": 300, "data": "alias.example.com." },
+                    { "name": "_atproto.alice.example.com.", "type": 16, "TTL": 300, "data": "\"did=did:plc:\" \"cbkjy5n7bk3ax2wplmtjofq2\"" }
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .and(query_param("name", "_atproto.bob.example.com"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Status": 3 })),
+            )
+            .mount(&server)
+            .await;
+
+        let provider = format!("{}/dns-query", server.uri());
+        let http_client = reqwest::Client::new();
+        assert_eq!(
+            resolve_handle_doh(&http_client, &provider, "alice.example.com").await?,
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2"
+        );
+        assert_eq!(
+            resolve_handle_doh(&http_client, &provider, "bob.example.com")
+                .await
+                .unwrap_err()
+                .to_string(),
+            "No records found for handle bob.example.com"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_options() -> Result<()> {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            ResolveOptions::from_args(&args(&[]))?,
+            ResolveOptions::default()
+        );
+        assert_eq!(
+            ResolveOptions::from_args(&args(&["--doh"]))?
+                .doh_provider
+                .as_deref(),
+            Some(DEFAULT_DOH_PROVIDER)
+        );
+        assert_eq!(
+            ResolveOptions::from_args(&args(&["--doh=https://dns.google/resolve"]))?
+                .doh_provider
+                .as_deref(),
+            Some("https://dns.google/resolve")
+        );
+
+        let options = ResolveOptions::from_args(&args(&[
+            "--nameserver=127.0.0.1:5353",
+            "--nameserver=10.0.0.2",
+            "--nameserver=[::1]:5300",
+        ]))?;
+        assert_eq!(
+            options.nameservers,
+            vec![
+                "127.0.0.1:5353".parse::<SocketAddr>()?,
+                "10.0.0.2:53".parse()?,
+                "[::1]:5300".parse()?,
+            ]
+        );
+        assert_eq!(options.resolver_config().name_servers().len(), 6);
+        assert!(ResolveOptions::from_args(&args(&["--nameserver=ns.example.com"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_handle_lookups() {
+        let err = check_handle_agreement(
+            "alice.example.com",
+            Some("did:plc:cbkjy5n7bk3ax2wplmtjofq2"),
+            Some("did:plc:ewvi7nxzyoun6zhxrhs64oiz"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("did:plc:cbkjy5n7bk3ax2wplmtjofq2 via DNS TXT"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("did:plc:ewvi7nxzyoun6zhxrhs64oiz via HTTPS"),
+            "{}",
+            err
+        );
+
+        let did = Some("did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+        assert!(check_handle_agreement("alice.example.com", did, did).is_ok());
+        assert!(check_handle_agreement("alice.example.com", did, None).is_ok());
+        assert!(check_handle_agreement("alice.example.com", None, did).is_ok());
+    }
 }
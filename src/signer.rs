@@ -0,0 +1,164 @@
+//! Operation signing behind a trait, so rotation keys held outside of memory,
+//! such as in an HSM or on a hardware token, can sign PLC operations.
+
+use anyhow::{anyhow, Context, Result};
+use elliptic_curve::JwkEcKey;
+use serde_json::json;
+use std::path::Path;
+
+use crate::{
+    actions::inputs::read_jwk_file,
+    crypto::{
+        decode_signature, did_key_fingerprint, is_low_s, jwk_to_did_key, k256, p256,
+        sign_operation, verify_operation,
+    },
+};
+
+/// How the `sig` of an operation is encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignatureEncoding {
+    /// The 64 byte `r || s` signature in unpadded base64url, as PLC requires.
+    #[default]
+    RawBase64Url,
+
+    /// An ASN.1 DER signature in padded base64, for other verifiers.
+    DerBase64,
+}
+
+pub trait Signer: Send + Sync {
+    /// The `did:key` of the signing key, as listed in `rotationKeys`.
+    fn did_key(&self) -> String;
+
+    /// Signs a dag-cbor encoded operation, returning the 64 byte `r || s`
+    /// signature.
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Signs with a P-256 or secp256k1 private key given as a JWK.
+pub struct JwkSigner {
+    jwk: JwkEcKey,
+    did_key: String,
+}
+
+impl JwkSigner {
+    pub fn new(jwk: JwkEcKey) -> Result<Self> {
+        let did_key = format!("did:key:{}", jwk_to_did_key(&jwk)?);
+        Ok(Self { jwk, did_key })
+    }
+
+    /// Loads the JWK from a file, as with `--jwk-file`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::new(read_jwk_file(path)?)
+    }
+}
+
+impl Signer for JwkSigner {
+    fn did_key(&self) -> String {
+        self.did_key.clone()
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self.jwk.crv() {
+            "P-256" => p256::sign(&self.jwk, payload),
+            "secp256k1" => k256::sign(&self.jwk, payload),
+            _ => Err(anyhow!("unsupported curve")),
+        }
+    }
+}
+
+/// Signs a fixed operation through the same path used for real operations and
+/// verifies the signature against the signer's `did:key`, returning the curve.
+pub fn self_test(signer: &dyn Signer) -> Result<&'static str> {
+    let did_key = signer.did_key();
+    let (curve, _) = did_key_fingerprint(&did_key)?;
+
+    let operation = json!({
+        "type": "plc_operation",
+        "rotationKeys": [did_key],
+        "verificationMethods": {},
+        "alsoKnownAs": ["at://selftest.tandem.invalid"],
+        "services": {},
+        "prev": null,
+    });
+    let signed_operation = sign_operation(signer, &operation)?;
+
+    let signature = decode_signature(
+        &did_key,
+        signed_operation["sig"]
+            .as_str()
+            .ok_or_else(|| anyhow!("signed operation is missing sig"))?,
+        SignatureEncoding::RawBase64Url,
+    )
+    .context("signature is not base64url")?;
+    if signature.len() != 64 {
+        return Err(anyhow!(
+            "signature is {} bytes instead of 64",
+            signature.len()
+        ));
+    }
+    if !is_low_s(&did_key, &signature)? {
+        return Err(anyhow!("signature has a high S value"));
+    }
+
+    verify_operation(&signed_operation, &did_key)?;
+
+    Ok(curve)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{validate, KeyFormat};
+    use std::str::FromStr;
+
+    #[test]
+    fn jwk_signer_signatures_validate() -> Result<()> {
+        for gen_key in [p256::gen_key, k256::gen_key] {
+            let (secret_jwk, encoded_public_key) = gen_key(KeyFormat::Jwk)?;
+            let signer = JwkSigner::new(JwkEcKey::from_str(&secret_jwk)?)?;
+            assert_eq!(signer.did_key(), format!("did:key:{}", encoded_public_key));
+
+            let signature = signer.sign(b"hello world")?;
+            assert_eq!(signature.len(), 64);
+            validate(&encoded_public_key, &signature, "hello world")?;
+        }
+        Ok(())
+    }
+
+    struct WrongKeySigner {
+        did_key: String,
+        signer: JwkSigner,
+    }
+
+    impl Signer for WrongKeySigner {
+        fn did_key(&self) -> String {
+            self.did_key.clone()
+        }
+
+        fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+            self.signer.sign(payload)
+        }
+    }
+
+    #[test]
+    fn self_tests() -> Result<()> {
+        for _ in 0..8 {
+            let (secret_jwk, _) = p256::gen_key(KeyFormat::Jwk)?;
+            let signer = JwkSigner::new(JwkEcKey::from_str(&secret_jwk)?)?;
+            assert_eq!(self_test(&signer)?, "p256");
+
+            let (secret_jwk, _) = k256::gen_key(KeyFormat::Jwk)?;
+            let signer = JwkSigner::new(JwkEcKey::from_str(&secret_jwk)?)?;
+            assert_eq!(self_test(&signer)?, "k256");
+        }
+
+        let (secret_jwk, _) = k256::gen_key(KeyFormat::Jwk)?;
+        let (_, other_key) = k256::gen_key(KeyFormat::Jwk)?;
+        let signer = WrongKeySigner {
+            did_key: format!("did:key:{}", other_key),
+            signer: JwkSigner::new(JwkEcKey::from_str(&secret_jwk)?)?,
+        };
+        assert!(self_test(&signer).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::xrpc::RecommendedCredentials;
+
+/// How long the reference PDS accepts a PLC operation confirmation code.
+pub(crate) const PLC_SIGNATURE_TOKEN_VALIDITY: Duration = Duration::minutes(15);
+
+/// Returns the directory tandem keeps non-secret state in, honoring
+/// `TANDEM_CACHE_DIR` and `XDG_CACHE_HOME` before falling back to `~/.cache`.
+pub(crate) fn cache_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("TANDEM_CACHE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+        return Ok(PathBuf::from(dir).join("tandem"));
+    }
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".cache").join("tandem"))
+        .ok_or_else(|| anyhow!("unable to determine cache directory"))
+}
+
+fn did_file_name(did: &str, extension: &str) -> String {
+    format!("{}.{}", did.replace(':', "_"), extension)
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct PendingSignatureRequest {
+    pub(crate) did: String,
+    pub(crate) requested_at: DateTime<Utc>,
+}
+
+impl PendingSignatureRequest {
+    pub(crate) fn expires_at(&self) -> DateTime<Utc> {
+        self.requested_at + PLC_SIGNATURE_TOKEN_VALIDITY
+    }
+}
+
+/// Records PLC signature requests that have been sent but not yet used so a
+/// later run for the same DID can offer to reuse the emailed code.
+pub(crate) struct PendingSignatureRequests {
+    dir: PathBuf,
+}
+
+impl PendingSignatureRequests {
+    pub(crate) fn open() -> Result<Self> {
+        Ok(Self::new(&cache_dir()?.join("pending-signature-requests")))
+    }
+
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn path(&self, did: &str) -> PathBuf {
+        self.dir.join(did_file_name(did, "json"))
+    }
+
+    /// Returns the pending request for the DID, ignoring any whose code has expired.
+    pub(crate) fn get(&self, did: &str) -> Result<Option<PendingSignatureRequest>> {
+        let path = self.path(did);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data =
+            std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let pending: PendingSignatureRequest = serde_json::from_slice(&data)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        if pending.expires_at() <= Utc::now() {
+            self.clear(did)?;
+            return Ok(None);
+        }
+
+        Ok(Some(pending))
+    }
+
+    pub(crate) fn record(&self, did: &str) -> Result<PendingSignatureRequest> {
+        let pending = PendingSignatureRequest {
+            did: did.to_string(),
+            requested_at: Utc::now(),
+        };
+
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        std::fs::write(self.path(did), serde_json::to_vec_pretty(&pending)?)
+            .context("failed to record pending signature request")?;
+
+        Ok(pending)
+    }
+
+    pub(crate) fn clear(&self, did: &str) -> Result<()> {
+        let path = self.path(did);
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The steps of a migration that are saved once they succeed, in order.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MigrationStep {
+    CredentialsFetched,
+    OperationBuilt,
+    OperationSigned,
+}
+
+/// The non-secret progress of a migration. Passwords, sessions, and keys are
+/// never saved, so resuming still prompts for them.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct MigrationProgress {
+    pub(crate) did: String,
+    pub(crate) destination_pds: String,
+    pub(crate) step: MigrationStep,
+    pub(crate) updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) recommended_credentials: Option<RecommendedCredentials>,
+    #[serde(default)]
+    pub(crate) prev: Option<String>,
+    #[serde(default)]
+    pub(crate) operation: Option<serde_json::Value>,
+}
+
+/// Saves migration progress keyed by DID after each successful step, so a
+/// failed migration can resume instead of starting over.
+pub(crate) struct MigrationProgressStore {
+    dir: PathBuf,
+}
+
+impl MigrationProgressStore {
+    pub(crate) fn open() -> Result<Self> {
+        Ok(Self::new(&cache_dir()?.join("migrations")))
+    }
+
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn path(&self, did: &str) -> PathBuf {
+        self.dir.join(did_file_name(did, "json"))
+    }
+
+    pub(crate) fn get(&self, did: &str) -> Result<Option<MigrationProgress>> {
+        let path = self.path(did);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let data =
+            std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let progress = serde_json::from_slice(&data)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(progress))
+    }
+
+    pub(crate) fn save(&self, progress: &MigrationProgress) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let progress = MigrationProgress {
+            updated_at: Utc::now(),
+            ..progress.clone()
+        };
+        std::fs::write(
+            self.path(&progress.did),
+            serde_json::to_vec_pretty(&progress)?,
+        )
+        .context("failed to save migration progress")
+    }
+
+    pub(crate) fn clear(&self, did: &str) -> Result<()> {
+        let path = self.path(did);
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Locks older than this are assumed to be left behind by a run that crashed.
+pub(crate) const DID_LOCK_TIMEOUT: Duration = Duration::minutes(30);
+
+#[derive(Deserialize, Serialize)]
+struct DidLockInfo {
+    pid: u32,
+    locked_at: DateTime<Utc>,
+}
+
+/// A best-effort lock held while an action mutates a DID, so two runs do not
+/// both build on the same `prev` and fork the chain. Released on drop.
+pub(crate) struct DidLock {
+    path: PathBuf,
+}
+
+impl DidLock {
+    pub(crate) fn acquire(did: &str, force: bool) -> Result<Self> {
+        Self::acquire_in(&cache_dir()?.join("locks"), did, force)
+    }
+
+    pub(crate) fn acquire_in(dir: &Path, did: &str, force: bool) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(did_file_name(did, "lock"));
+
+        // The lock file is created with `create_new`, so of two runs racing for
+        // the same DID only one can take it. A stale or forced lock is replaced
+        // by `replace_lock`.
+        let mut file = match create_lock_file(&path)? {
+            Some(file) => file,
+            None => {
+                if !force {
+                    if let Some(holder) = lock_holder(&path) {
+                        return Err(lock_held_error(&holder));
+                    }
+                }
+                replace_lock(dir, &path, force)?
+            }
+        };
+
+        let info = DidLockInfo {
+            pid: std::process::id(),
+            locked_at: Utc::now(),
+        };
+        file.write_all(&serde_json::to_vec_pretty(&info)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        held_locks().push(path.clone());
+        Ok(Self { path })
+    }
+}
+
+/// Replaces a lock found to be stale, or any lock when `force` is set. This
+/// holds `takeover_guard` and checks the lock again under it, so a run that
+/// found the same lock stale cannot remove the fresh lock that replaced it.
+/// Losing the race to recreate it counts as the lock being held.
+fn replace_lock(dir: &Path, path: &Path, force: bool) -> Result<std::fs::File> {
+    let _guard = takeover_guard(dir)?;
+    if !force {
+        if let Some(holder) = lock_holder(path) {
+            return Err(lock_held_error(&holder));
+        }
+    }
+    let _ = std::fs::remove_file(path);
+    create_lock_file(path)?
+        .ok_or_else(|| lock_held_error(&lock_holder(path).unwrap_or_else(starting_holder)))
+}
+
+/// Takes an OS advisory lock on a file in `dir` that is never removed, held
+/// while a stale lock is replaced. The OS releases it if the process dies.
+fn takeover_guard(dir: &Path) -> Result<std::fs::File> {
+    let path = dir.join("takeover.guard");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("failed to lock {}", path.display()))?;
+    Ok(file)
+}
+
+/// Creates the lock file, returning `None` when it already exists.
+fn create_lock_file(path: &Path) -> Result<Option<std::fs::File>> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to create {}", path.display())),
+    }
+}
+
+/// Describes who holds the lock at `path`, or returns `None` when it is
+/// missing or stale. A lock that cannot be parsed yet was just created by
+/// another run, so its age comes from the file.
+fn lock_holder(path: &Path) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    match serde_json::from_slice::<DidLockInfo>(&data) {
+        Ok(info) => (Utc::now() - info.locked_at < DID_LOCK_TIMEOUT)
+            .then(|| format!("process {} since {}", info.pid, info.locked_at.to_rfc3339())),
+        Err(_) => {
+            let modified: DateTime<Utc> = std::fs::metadata(path).ok()?.modified().ok()?.into();
+            (Utc::now() - modified < DID_LOCK_TIMEOUT).then(starting_holder)
+        }
+    }
+}
+
+fn starting_holder() -> String {
+    "a run that is starting".to_string()
+}
+
+fn lock_held_error(holder: &str) -> anyhow::Error {
+    anyhow!(
+        "another tandem operation for this DID appears to be in progress ({}); pass --force if it is not",
+        holder
+    )
+}
+
+/// Paths of the locks this process holds, so an interrupt that exits without
+/// running `Drop` can still release them.
+fn held_locks() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    static HELD_LOCKS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    HELD_LOCKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Removes every lock this process holds.
+pub(crate) fn release_held_locks() {
+    for path in held_locks().drain(..) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+impl Drop for DidLock {
+    fn drop(&mut self) {
+        held_locks().retain(|path| path != &self.path);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_signature_requests() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tandem-pending-{}", std::process::id()));
+        let pending_requests = PendingSignatureRequests::new(&dir);
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+
+        assert!(pending_requests.get(did)?.is_none());
+
+        pending_requests.record(did)?;
+        let pending = pending_requests.get(did)?.expect("pending request");
+        assert_eq!(pending.did, did);
+
+        let expired = PendingSignatureRequest {
+            did: did.to_string(),
+            requested_at: Utc::now() - PLC_SIGNATURE_TOKEN_VALIDITY,
+        };
+        std::fs::write(pending_requests.path(did), serde_json::to_vec(&expired)?)?;
+        assert!(pending_requests.get(did)?.is_none());
+        assert!(!pending_requests.path(did).exists());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn migration_progress() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tandem-migrations-{}", std::process::id()));
+        let store = MigrationProgressStore::new(&dir);
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+
+        assert!(store.get(did)?.is_none());
+
+        let mut progress = MigrationProgress {
+            did: did.to_string(),
+            destination_pds: "https://pds.example.com".to_string(),
+            step: MigrationStep::CredentialsFetched,
+            updated_at: Utc::now(),
+            recommended_credentials: Some(RecommendedCredentials {
+                rotation_keys: vec![],
+                also_known_as: vec![],
+                verification_methods: Default::default(),
+                services: Default::default(),
+            }),
+            prev: None,
+            operation: None,
+        };
+        store.save(&progress)?;
+        assert_eq!(
+            store.get(did)?.expect("saved progress").step,
+            MigrationStep::CredentialsFetched
+        );
+
+        progress.step = MigrationStep::OperationBuilt;
+        progress.prev = Some("bafyreid".to_string());
+        progress.operation = Some(serde_json::json!({ "type": "plc_operation" }));
+        store.save(&progress)?;
+        let saved = store.get(did)?.expect("saved progress");
+        assert!(saved.step > MigrationStep::CredentialsFetched);
+        assert_eq!(saved.prev.as_deref(), Some("bafyreid"));
+
+        store.clear(did)?;
+        assert!(store.get(did)?.is_none());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn did_locks() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tandem-locks-{}", std::process::id()));
+        let did = "did:plc:cbkjy5n7bk3ax2wplmtjofq2";
+
+        let lock = DidLock::acquire_in(&dir, did, false)?;
+        let err = DidLock::acquire_in(&dir, did, false)
+            .err()
+            .expect("lock held");
+        assert!(err
+            .to_string()
+            .starts_with("another tandem operation for this DID appears to be in progress"));
+        assert!(DidLock::acquire_in(&dir, "did:plc:ewvi7nxzyoun6zhxrhs64oiz", false).is_ok());
+
+        let forced = DidLock::acquire_in(&dir, did, true)?;
+        drop(forced);
+        drop(lock);
+        assert!(!dir.join(did_file_name(did, "lock")).exists());
+
+        let stale = DidLockInfo {
+            pid: 1,
+            locked_at: Utc::now() - DID_LOCK_TIMEOUT,
+        };
+        std::fs::write(
+            dir.join(did_file_name(did, "lock")),
+            serde_json::to_vec(&stale)?,
+        )?;
+        let lock = DidLock::acquire_in(&dir, did, false)?;
+
+        // A second run that also found the lock stale, and only now replaces
+        // it, must not remove the fresh lock the first run took.
+        let err = replace_lock(&dir, &dir.join(did_file_name(did, "lock")), false).unwrap_err();
+        assert!(err.to_string().starts_with("another tandem operation"));
+        assert!(dir.join(did_file_name(did, "lock")).exists());
+
+        release_held_locks();
+        assert!(!dir.join(did_file_name(did, "lock")).exists());
+        drop(lock);
+
+        // Runs racing for a free lock, or to replace the same stale lock,
+        // must leave exactly one holder.
+        for start_stale in [false].into_iter().chain(std::iter::repeat_n(true, 20)) {
+            if start_stale {
+                std::fs::write(
+                    dir.join(did_file_name(did, "lock")),
+                    serde_json::to_vec(&stale)?,
+                )?;
+            }
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+            let racing = (0..8)
+                .map(|_| {
+                    let dir = dir.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        DidLock::acquire_in(&dir, did, false)
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(racing.iter().filter(|lock| lock.is_ok()).count(), 1);
+            drop(racing);
+            assert!(!dir.join(did_file_name(did, "lock")).exists());
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! Request tracing for `--trace-requests`. Every request sent through
+//! `TracedSend::send_traced` is written out with its response status and
+//! headers, with credentials redacted, to help debug a failing flow.
+//!
+//! `--debug-responses` similarly prints the full, redacted JSON of the
+//! account and session responses that tandem otherwise reduces to a few
+//! fields, for diagnosing nonstandard PDS implementations.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use reqwest::{header::HeaderMap, RequestBuilder, Response};
+
+/// Headers whose values are never written to a trace.
+const REDACTED_HEADERS: [&str; 4] = ["authorization", "cookie", "set-cookie", "dpop"];
+
+/// JSON body fields whose values are never written to a trace. `d` is the
+/// private scalar of a JWK.
+const REDACTED_FIELDS: [&str; 7] = [
+    "password",
+    "adminPassword",
+    "accessJwt",
+    "refreshJwt",
+    "token",
+    "d",
+    "secret",
+];
+
+const REDACTED: &str = "[redacted]";
+
+static TRACER: OnceLock<RequestTracer> = OnceLock::new();
+
+static DEBUG_RESPONSES: AtomicBool = AtomicBool::new(false);
+
+/// Prints the redacted JSON of PDS responses passed to `debug_response` from
+/// now on.
+pub fn enable_debug_responses() {
+    DEBUG_RESPONSES.store(true, Ordering::Relaxed);
+}
+
+/// Prints a PDS response to stderr, with credentials redacted, when
+/// `--debug-responses` was given.
+pub(crate) fn debug_response(endpoint: &str, body: &serde_json::Value) {
+    if DEBUG_RESPONSES.load(Ordering::Relaxed) {
+        eprintln!("{} response: {}", endpoint, redacted_json(body));
+    }
+}
+
+/// Renders JSON with credential fields redacted.
+fn redacted_json(value: &serde_json::Value) -> String {
+    let mut value = value.clone();
+    redact_value(&mut value);
+    value.to_string()
+}
+
+/// Where traces are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceTarget {
+    Stderr,
+    File(PathBuf),
+}
+
+/// Writes traced requests and responses to stderr or a file.
+#[derive(Clone)]
+pub struct RequestTracer {
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl RequestTracer {
+    pub fn for_target(target: &TraceTarget) -> Result<Self> {
+        match target {
+            TraceTarget::Stderr => Ok(Self::stderr()),
+            TraceTarget::File(path) => Self::file(path),
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(std::io::stderr()))
+    }
+
+    /// Appends traces to `path`.
+    pub fn file(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open trace file {}", path.display()))?;
+        Ok(Self::new(Box::new(file)))
+    }
+
+    pub fn new(output: Box<dyn Write + Send>) -> Self {
+        Self {
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// Traces every request sent with `send_traced` from now on. Only the
+    /// first tracer installed is used.
+    pub fn install(self) {
+        let _ = TRACER.set(self);
+    }
+
+    async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+
+        let mut lines = vec![format!("> {} {}", request.method(), request.url())];
+        lines.extend(header_lines('>', request.headers()));
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            lines.push(format!("> {}", redact_body(body)));
+        }
+        self.write(&lines);
+
+        let started = Instant::now();
+        let result = client.execute(request).await;
+        let elapsed = started.elapsed().as_millis();
+        let lines = match &result {
+            Ok(response) => {
+                let mut lines = vec![format!("< {} ({} ms)", response.status(), elapsed)];
+                lines.extend(header_lines('<', response.headers()));
+                lines
+            }
+            Err(err) => vec![format!("< error: {} ({} ms)", err, elapsed)],
+        };
+        self.write(&lines);
+
+        result
+    }
+
+    fn write(&self, lines: &[String]) {
+        let mut output = self.output.lock().expect("trace output lock poisoned");
+        for line in lines {
+            let _ = writeln!(output, "{}", line);
+        }
+        let _ = output.flush();
+    }
+}
+
+/// Sends requests through the installed tracer, if any.
+pub(crate) trait TracedSend {
+    async fn send_traced(self) -> reqwest::Result<Response>;
+}
+
+impl TracedSend for RequestBuilder {
+    async fn send_traced(self) -> reqwest::Result<Response> {
+        match TRACER.get() {
+            Some(tracer) => tracer.send(self).await,
+            None => self.send().await,
+        }
+    }
+}
+
+fn header_lines(direction: char, headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{} {}: {}", direction, name, value)
+        })
+        .collect()
+}
+
+/// Renders a request body with credential fields redacted. Bodies that are
+/// not JSON are summarized by size, since they cannot be safely redacted.
+fn redact_body(body: &[u8]) -> String {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(value) => redacted_json(&value),
+        Err(_) => format!("[{} byte body]", body.len()),
+    }
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn traces_are_redacted() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let buffer = Buffer::default();
+        let tracer = RequestTracer::new(Box::new(buffer.clone()));
+        let response = tracer
+            .send(
+                reqwest::Client::new()
+                    .post(format!(
+                        "{}/xrpc/com.atproto.server.createSession",
+                        server.uri()
+                    ))
+                    .header("Authorization", "Bearer secret-jwt")
+                    .json(&json!({
+                        "identifier": "alice.example.com",
+                        "password": "hunter2",
+                        "jwk": { "kty": "EC", "d": "private" }
+                    })),
+            )
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert!(trace.contains("> POST http://"));
+        assert!(trace.contains("> authorization: [redacted]"));
+        assert!(trace.contains(r#""identifier":"alice.example.com""#));
+        assert!(trace.contains("< 401 Unauthorized"));
+        for secret in ["secret-jwt", "hunter2", "private"] {
+            assert!(!trace.contains(secret), "{} leaked into {}", secret, trace);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn responses_are_redacted() {
+        let response = json!({
+            "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "handle": "alice.example.com",
+            "accessJwt": "access-secret",
+            "refreshJwt": "refresh-secret",
+            "didDoc": { "service": [] }
+        });
+        let rendered = redacted_json(&response);
+        assert!(rendered.contains(r#""did":"did:plc:cbkjy5n7bk3ax2wplmtjofq2""#));
+        assert!(rendered.contains(r#""didDoc":{"service":[]}"#));
+        assert!(rendered.contains(r#""accessJwt":"[redacted]""#));
+        assert!(!rendered.contains("access-secret"));
+        assert!(!rendered.contains("refresh-secret"));
+    }
+}
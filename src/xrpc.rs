@@ -1,22 +1,409 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use json_patch::merge;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
 use serde_json::json;
 
+use crate::trace::{debug_response, TracedSend};
+
+/// How many times a request is attempted before its last failure is returned.
+pub(crate) const RETRY_ATTEMPTS: u32 = 4;
+
+/// The delay before the first retry, doubled for each one after it.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Sends the request built by `make_request`, retrying with exponential
+/// backoff on connection failures, timeouts, 429s, and 5xx responses. Other
+/// responses, including 400 and 401, are returned to the caller immediately.
+pub(crate) async fn send_with_backoff<F>(make_request: F) -> Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        let result = make_request().send_traced().await;
+
+        let retryable = match &result {
+            Ok(response) => is_retryable_status(response.status()),
+            Err(err) => err.is_connect() || err.is_timeout(),
+        };
+
+        if !retryable || attempt >= RETRY_ATTEMPTS {
+            return result.map_err(|err| err.into());
+        }
+
+        tokio::time::sleep(retry_delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+/// Whether `send_with_backoff` retries a response with this status.
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// How long to wait after the given failed attempt, counting from 1.
+pub(crate) fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BACKOFF * 2u32.pow(attempt - 1)
+}
+
+/// Deserializes a JSON response body, first checking its `Content-Type` so a
+/// misconfigured host serving an HTML page produces a clear error instead of
+/// a serde error about the first byte of the page.
+pub(crate) async fn json_body<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T> {
+    XrpcResponse::read(response).await?.json()
+}
+
+/// Like `json_body`, but first hands the raw JSON to `debug_response` so
+/// `--debug-responses` can show fields the typed response drops.
+async fn debug_json_body<T: serde::de::DeserializeOwned>(
+    endpoint: &str,
+    response: reqwest::Response,
+) -> Result<T> {
+    XrpcResponse::read(response).await?.debug_json(endpoint)
+}
+
+/// A response read in full, so the async and blocking clients interpret each
+/// endpoint's responses with the same code.
+pub(crate) struct XrpcResponse {
+    url: reqwest::Url,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl XrpcResponse {
+    pub(crate) fn new(
+        url: reqwest::Url,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Vec<u8>,
+    ) -> Self {
+        Self {
+            url,
+            status,
+            headers,
+            body,
+        }
+    }
+
+    async fn read(response: reqwest::Response) -> Result<Self> {
+        let url = response.url().clone();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?.to_vec();
+        Ok(Self::new(url, status, headers, body))
+    }
+
+    /// Deserializes the body after checking its `Content-Type`.
+    pub(crate) fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        let content_type = self
+            .headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("no content type");
+
+        if !content_type.to_ascii_lowercase().contains("json") {
+            return Err(anyhow!(
+                "expected JSON from {} but got {} (status {})",
+                self.url,
+                content_type,
+                self.status.as_u16()
+            ));
+        }
+
+        serde_json::from_slice(&self.body).with_context(|| {
+            format!(
+                "failed to parse JSON from {} (status {})",
+                self.url,
+                self.status.as_u16()
+            )
+        })
+    }
+
+    /// Like `json`, but first hands the raw JSON to `debug_response`.
+    fn debug_json<T: serde::de::DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let body: serde_json::Value = self.json()?;
+        debug_response(endpoint, &body);
+        serde_json::from_value(body).with_context(|| format!("unexpected {} response", endpoint))
+    }
+
+    /// Errors with "PDS refused to `action`" unless the request succeeded.
+    fn check_success(&self, action: &str) -> Result<()> {
+        if !self.status.is_success() {
+            return Err(anyhow!(
+                "PDS refused to {} (status {})",
+                action,
+                self.status
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a successful response as is, and reads the XRPC error out of
+    /// any other.
+    pub(crate) fn check_status(self) -> std::result::Result<Self, XrpcRequestError> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+        let error: XrpcError = self.json().unwrap_or_default();
+        Err(XrpcRequestError::new(self.status, error))
+    }
+
+    /// Like `check_status`, but returns `None` when the access token expired
+    /// and the client `can_refresh`, in which case the client refreshes the
+    /// session and sends the request once more.
+    pub(crate) fn check_session_status(
+        self,
+        can_refresh: bool,
+    ) -> std::result::Result<Option<Self>, XrpcRequestError> {
+        match self.check_status() {
+            Ok(response) => Ok(Some(response)),
+            Err(err) if can_refresh && err.is_expired_session() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The access and refresh tokens of a `createSession` or `refreshSession`
+    /// response.
+    pub(crate) fn session_tokens(&self, endpoint: &str) -> Result<(String, Option<String>)> {
+        session_tokens(self.debug_json(endpoint)?)
+    }
+
+    pub(crate) fn refreshed_session_tokens(&self) -> Result<(String, Option<String>)> {
+        self.check_success("refresh the session")?;
+        self.session_tokens("com.atproto.server.refreshSession")
+    }
+
+    pub(crate) fn recommended_credentials(&self) -> Result<RecommendedCredentials> {
+        self.check_success("recommend DID credentials")?;
+        let wrapped_response: WrappedRecommendedCredentialsResponse =
+            self.debug_json("com.atproto.identity.getRecommendedDidCredentials")?;
+        match wrapped_response {
+            WrappedRecommendedCredentialsResponse::RecommendedCredentials(credentials) => {
+                Ok(credentials)
+            }
+            WrappedRecommendedCredentialsResponse::Other { extra } => Err(anyhow!(
+                "Unexpected recommended DID credentials from PDS: {:?}",
+                extra
+            )),
+        }
+    }
+
+    pub(crate) fn session_did_document(&self) -> Result<Option<serde_json::Value>> {
+        self.check_success("describe the session")?;
+        let mut session: serde_json::Value = self.json().context("failed to parse session")?;
+        Ok(match session["didDoc"].take() {
+            serde_json::Value::Null => None,
+            did_doc => Some(did_doc),
+        })
+    }
+
+    /// The DID `handle` resolved to, or `None` when the PDS could not
+    /// resolve it.
+    pub(crate) fn resolved_handle(&self, handle: &str) -> Result<Option<String>> {
+        let status = self.status;
+        if status == StatusCode::BAD_REQUEST || status == StatusCode::NOT_FOUND {
+            let error: XrpcError = self.json().unwrap_or_default();
+            if status == StatusCode::NOT_FOUND
+                || matches!(error.error.as_str(), "HandleNotFound" | "InvalidRequest")
+            {
+                return Ok(None);
+            }
+            return Err(anyhow!(
+                "PDS refused to resolve {} ({}: {})",
+                handle,
+                error.error,
+                error.message
+            ));
+        }
+        self.check_success(&format!("resolve {}", handle))?;
+
+        let response: ResolveHandleResponse =
+            self.json().context("failed to parse resolved handle")?;
+        Ok(Some(response.did))
+    }
+
+    pub(crate) fn repo_list(&self) -> Result<(Vec<RepoInfo>, Option<String>)> {
+        self.check_success("list repos")?;
+        let response: ListReposResponse = self.json().context("failed to parse repo list")?;
+        // Some servers return the last cursor again with an empty page.
+        let cursor = response.cursor.filter(|_| !response.repos.is_empty());
+        Ok((response.repos, cursor))
+    }
+
+    pub(crate) fn signed_operation(&self) -> Result<serde_json::Value> {
+        let wrapped_operation: WrappedOperation = self.json()?;
+        Ok(wrapped_operation.operation)
+    }
+}
+
+/// A PDS request, described apart from the async or blocking client that
+/// sends it.
+pub(crate) struct XrpcCall {
+    pub(crate) method: reqwest::Method,
+    nsid: &'static str,
+    pub(crate) query: Vec<(&'static str, String)>,
+    pub(crate) body: Option<serde_json::Value>,
+
+    /// Whether the client sends it with backoff, for requests that are safe
+    /// to repeat.
+    pub(crate) retry: bool,
+}
+
+impl XrpcCall {
+    fn new(method: reqwest::Method, nsid: &'static str) -> Self {
+        Self {
+            method,
+            nsid,
+            query: Vec::new(),
+            body: None,
+            retry: false,
+        }
+    }
+
+    fn retried(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
+    fn json(mut self, body: serde_json::Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    pub(crate) fn url(&self, pds: &str) -> String {
+        format!("{}/xrpc/{}", pds, self.nsid)
+    }
+
+    /// The client's headers, along with `token` as a bearer token when there
+    /// is one.
+    pub(crate) fn headers(&self, client_headers: &HeaderMap, token: &str) -> Result<HeaderMap> {
+        let mut headers = client_headers.clone();
+        if !token.is_empty() {
+            let authorization = HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("invalid session token")?;
+            headers.insert(reqwest::header::AUTHORIZATION, authorization);
+        }
+        Ok(headers)
+    }
+
+    pub(crate) fn create_session(did: &str, password: &str) -> Self {
+        Self::new(reqwest::Method::POST, "com.atproto.server.createSession")
+            .json(json!({ "identifier": did, "password": password }))
+            .retried()
+    }
+
+    pub(crate) fn refresh_session() -> Self {
+        Self::new(reqwest::Method::POST, "com.atproto.server.refreshSession")
+    }
+
+    pub(crate) fn get_recommended_did_credentials() -> Self {
+        Self::new(
+            reqwest::Method::GET,
+            "com.atproto.identity.getRecommendedDidCredentials",
+        )
+        .retried()
+    }
+
+    pub(crate) fn get_session() -> Self {
+        Self::new(reqwest::Method::GET, "com.atproto.server.getSession").retried()
+    }
+
+    pub(crate) fn resolve_handle(handle: &str) -> Self {
+        let mut call =
+            Self::new(reqwest::Method::GET, "com.atproto.identity.resolveHandle").retried();
+        call.query.push(("handle", handle.to_string()));
+        call
+    }
+
+    pub(crate) fn list_repos(cursor: Option<String>, limit: u32) -> Self {
+        let mut call = Self::new(reqwest::Method::GET, "com.atproto.sync.listRepos").retried();
+        call.query.push(("limit", limit.to_string()));
+        if let Some(cursor) = cursor {
+            call.query.push(("cursor", cursor));
+        }
+        call
+    }
+
+    pub(crate) fn deactivate_account() -> Self {
+        Self::new(
+            reqwest::Method::POST,
+            "com.atproto.server.deactivateAccount",
+        )
+        .json(json!({}))
+    }
+
+    pub(crate) fn request_plc_op_sig() -> Self {
+        Self::new(
+            reqwest::Method::POST,
+            "com.atproto.identity.requestPlcOperationSignature",
+        )
+    }
+
+    pub(crate) fn sign_plc_op(did_doc: &serde_json::Value, token: &str) -> Self {
+        Self::new(
+            reqwest::Method::POST,
+            "com.atproto.identity.signPlcOperation",
+        )
+        .json(sign_plc_op_body(did_doc, token))
+    }
+
+    pub(crate) fn submit_plc_op(operation: &serde_json::Value) -> Self {
+        Self::new(
+            reqwest::Method::POST,
+            "com.atproto.identity.submitPlcOperation",
+        )
+        .json(json!({ "operation": operation }))
+    }
+}
+
+/// Returns the access and refresh tokens of a `createSession` or
+/// `refreshSession` response.
+pub(crate) fn session_tokens(
+    response: WrappedCredentialResponse,
+) -> Result<(String, Option<String>)> {
+    match response {
+        WrappedCredentialResponse::CredentialResponse {
+            access_jwt,
+            refresh_jwt,
+            ..
+        } => Ok((access_jwt, refresh_jwt)),
+        WrappedCredentialResponse::Other { extra } => {
+            Err(anyhow!("Unexpected response from PDS: {:?}", extra))
+        }
+    }
+}
+
 pub struct PdsClient {
     pub http_client: reqwest::Client,
     pub pds: String,
     pub access_jwt: String,
+
+    /// Exchanged for a new `access_jwt` by `refresh_session`, when the
+    /// session has one.
+    refresh_jwt: Option<String>,
+    headers: HeaderMap,
 }
 
 #[derive(serde::Deserialize)]
-enum WrappedCredentialResponse {
+pub(crate) enum WrappedCredentialResponse {
     #[serde(untagged)]
     CredentialResponse {
         #[serde(rename = "accessJwt")]
         access_jwt: String,
+        #[serde(rename = "refreshJwt", default)]
+        refresh_jwt: Option<String>,
+        #[serde(rename = "handle")]
         _handle: String,
+        #[serde(rename = "did")]
         _did: String,
     },
 
@@ -27,14 +414,31 @@ enum WrappedCredentialResponse {
     },
 }
 
+/// Treats an explicit `null` the same as a missing field.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + serde::Deserialize<'de>,
+{
+    Ok(<Option<T> as serde::Deserialize>::deserialize(deserializer)?.unwrap_or_default())
+}
+
 #[derive(serde::Deserialize)]
 enum WrappedDescribeServerResponse {
     #[serde(untagged)]
     DescribeServerResponse {
         did: String,
-        #[serde(rename = "inviteCodeRequired")]
+        #[serde(
+            rename = "inviteCodeRequired",
+            default,
+            deserialize_with = "null_as_default"
+        )]
         invite_code_required: bool,
-        #[serde(rename = "availableUserDomains")]
+        #[serde(
+            rename = "availableUserDomains",
+            default,
+            deserialize_with = "null_as_default"
+        )]
         available_user_domains: Vec<String>,
     },
 
@@ -62,6 +466,122 @@ enum WrappedCreateAccountResponse {
     },
 }
 
+/// The DID state a PDS recommends for an account it hosts, as returned by
+/// `com.atproto.identity.getRecommendedDidCredentials`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecommendedCredentials {
+    pub rotation_keys: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub also_known_as: Vec<String>,
+    pub verification_methods: BTreeMap<String, String>,
+    pub services: BTreeMap<String, RecommendedService>,
+}
+
+/// A service entry in `RecommendedCredentials`, in the directory's format.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct RecommendedService {
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub endpoint: String,
+}
+
+#[derive(serde::Deserialize)]
+enum WrappedRecommendedCredentialsResponse {
+    #[serde(untagged)]
+    RecommendedCredentials(RecommendedCredentials),
+
+    #[serde(untagged)]
+    Other {
+        #[serde(flatten)]
+        extra: HashMap<String, serde_json::Value>,
+    },
+}
+
+/// A repository hosted on a PDS, as listed by `com.atproto.sync.listRepos`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct RepoInfo {
+    pub did: String,
+
+    /// The CID of the repository's current commit.
+    pub head: String,
+    pub rev: String,
+    #[serde(default)]
+    pub active: Option<bool>,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// The body of an XRPC error response.
+#[derive(Default, serde::Deserialize)]
+pub(crate) struct XrpcError {
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// An XRPC request the PDS rejected, kept typed so callers can react to
+/// specific errors such as an expired confirmation code.
+#[derive(Debug)]
+pub struct XrpcRequestError {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for XrpcRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PDS rejected the request (status {}): {} {}",
+            self.status, self.error, self.message
+        )
+    }
+}
+
+impl std::error::Error for XrpcRequestError {}
+
+impl XrpcRequestError {
+    pub(crate) fn new(status: StatusCode, error: XrpcError) -> Self {
+        Self {
+            status: status.as_u16(),
+            error: error.error,
+            message: error.message,
+        }
+    }
+
+    /// Whether the PDS rejected the request with a 401, as it does when the
+    /// access token expired, so refreshing the session and sending it again
+    /// may succeed. A 400 `ExpiredToken` is not one: the PDS also returns it
+    /// for an expired confirmation code, which `is_expired_token_error`
+    /// handles by requesting a new code.
+    pub(crate) fn is_expired_session(&self) -> bool {
+        self.status == StatusCode::UNAUTHORIZED.as_u16()
+    }
+}
+
+/// Returns true when `err` is the PDS rejecting an expired PLC operation
+/// confirmation code, which a fresh code from `request_plc_op_sig` fixes.
+pub(crate) fn is_expired_token_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<XrpcRequestError>().is_some_and(|err| {
+        err.error == "ExpiredToken"
+            || (err.error == "InvalidToken" && err.message.to_lowercase().contains("expired"))
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct ResolveHandleResponse {
+    did: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ListReposResponse {
+    #[serde(default)]
+    cursor: Option<String>,
+    repos: Vec<RepoInfo>,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct WrappedOperation {
     pub operation: serde_json::Value,
@@ -74,104 +594,288 @@ impl PdsClient {
         did: &str,
         password: &str,
     ) -> Result<Self> {
-        let create_session_uri = format!("{}/xrpc/com.atproto.server.createSession", pds);
-
-        let mut body = HashMap::new();
-        body.insert("identifier", did);
-        body.insert("password", password);
+        let mut client = Self {
+            http_client: http_client.clone(),
+            pds: pds.to_string(),
+            access_jwt: String::new(),
+            refresh_jwt: None,
+            headers: HeaderMap::new(),
+        };
 
-        let response: WrappedCredentialResponse = http_client
-            .post(create_session_uri)
-            .json(&body)
-            .send()
+        let (access_jwt, refresh_jwt) = client
+            .send(&XrpcCall::create_session(did, password), "")
             .await?
-            .json()
-            .await?;
+            .session_tokens("com.atproto.server.createSession")?;
+        client.access_jwt = access_jwt;
+        client.refresh_jwt = refresh_jwt;
+        Ok(client)
+    }
 
-        let access_jwt = match response {
-            WrappedCredentialResponse::CredentialResponse { access_jwt, .. } => Ok(access_jwt),
-            WrappedCredentialResponse::Other { extra } => {
-                Err(anyhow!("Unexpected response from PDS: {:?}", extra))
+    /// Sends `call` with `token` as its bearer token, and with backoff when
+    /// the call is safe to repeat.
+    async fn send(&self, call: &XrpcCall, token: &str) -> Result<XrpcResponse> {
+        let uri = call.url(&self.pds);
+        let headers = call.headers(&self.headers, token)?;
+        let make_request = || {
+            let request = self
+                .http_client
+                .request(call.method.clone(), &uri)
+                .headers(headers.clone())
+                .query(&call.query);
+            match &call.body {
+                Some(body) => request.json(body),
+                None => request,
             }
-        }?;
+        };
+
+        let response = if call.retry {
+            send_with_backoff(make_request).await?
+        } else {
+            make_request().send_traced().await?
+        };
+        XrpcResponse::read(response).await
+    }
+
+    /// Exchanges the session's refresh token for new access and refresh
+    /// tokens with `com.atproto.server.refreshSession`.
+    pub async fn refresh_session(&mut self) -> Result<()> {
+        let current_refresh_jwt = self
+            .refresh_jwt
+            .clone()
+            .ok_or_else(|| anyhow!("the PDS session cannot be refreshed"))?;
+
+        let (access_jwt, refresh_jwt) = self
+            .send(&XrpcCall::refresh_session(), &current_refresh_jwt)
+            .await?
+            .refreshed_session_tokens()?;
+        self.access_jwt = access_jwt;
+        self.refresh_jwt = refresh_jwt.or(Some(current_refresh_jwt));
+        Ok(())
+    }
+
+    /// Sends `call`, and when the PDS reports an expired access token with a
+    /// 401, refreshes the session and sends it once more. Any other error
+    /// status is returned as an `XrpcRequestError`.
+    async fn send_refreshing(&mut self, call: &XrpcCall) -> Result<XrpcResponse> {
+        let response = self.send(call, &self.access_jwt).await?;
+        if let Some(response) = response.check_session_status(self.refresh_jwt.is_some())? {
+            return Ok(response);
+        }
+        self.refresh_session().await?;
+        Ok(self.send(call, &self.access_jwt).await?.check_status()?)
+    }
 
+    /// Creates a client without a session, for endpoints such as `listRepos`
+    /// that do not require authentication.
+    pub fn unauthenticated(http_client: &reqwest::Client, pds: &str) -> Result<Self> {
         Ok(Self {
             http_client: http_client.clone(),
-            pds: pds.to_string(),
-            access_jwt,
+            pds: pds_base_url(pds)?,
+            access_jwt: String::new(),
+            refresh_jwt: None,
+            headers: HeaderMap::new(),
         })
     }
 
-    pub async fn request_plc_op_sig(&self) -> Result<()> {
-        let request_plc_op_sig_uri = format!(
-            "{}/xrpc/com.atproto.identity.requestPlcOperationSignature",
-            self.pds
-        );
+    /// Adds a header that is sent with every subsequent request to the PDS.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self> {
+        insert_header(&mut self.headers, name, value)?;
+        Ok(self)
+    }
+
+    /// Routes requests through the service identified by `proxy`, in the
+    /// `did:...#service_id` form expected by the `atproto-proxy` header.
+    pub fn with_atproto_proxy(self, proxy: &str) -> Result<Self> {
+        self.with_header("atproto-proxy", proxy)
+    }
 
-        self.http_client
-            .post(request_plc_op_sig_uri)
-            .header("Authorization", format!("Bearer {}", self.access_jwt))
-            .send()
+    /// Returns the rotation keys, handles, verification methods, and services
+    /// the PDS recommends for the account's DID.
+    pub async fn get_recommended_did_credentials(&self) -> Result<RecommendedCredentials> {
+        self.send(
+            &XrpcCall::get_recommended_did_credentials(),
+            &self.access_jwt,
+        )
+        .await?
+        .recommended_credentials()
+    }
+
+    /// Returns the DID document the PDS holds for the session's account, which
+    /// some PDS implementations omit from `getSession`.
+    pub async fn get_session_did_document(&self) -> Result<Option<serde_json::Value>> {
+        self.send(&XrpcCall::get_session(), &self.access_jwt)
+            .await?
+            .session_did_document()
+    }
+
+    /// Returns the DID a handle belongs to, or `None` when the PDS cannot
+    /// resolve it, which means the handle is available.
+    pub async fn resolve_handle(&self, handle: &str) -> Result<Option<String>> {
+        self.send(&XrpcCall::resolve_handle(handle), &self.access_jwt)
+            .await?
+            .resolved_handle(handle)
+    }
+
+    /// Returns one page of up to `limit` repositories hosted on the PDS and
+    /// the cursor for the next page, which is `None` after the last page.
+    pub async fn list_repos(
+        &self,
+        cursor: Option<String>,
+        limit: u32,
+    ) -> Result<(Vec<RepoInfo>, Option<String>)> {
+        self.send(&XrpcCall::list_repos(cursor, limit), &self.access_jwt)
+            .await?
+            .repo_list()
+    }
+
+    /// Deactivates the session's account with
+    /// `com.atproto.server.deactivateAccount`. The PDS keeps the repository
+    /// but stops serving it until the account is activated again.
+    pub async fn deactivate_account(&self) -> Result<()> {
+        self.send(&XrpcCall::deactivate_account(), &self.access_jwt)
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    pub async fn request_plc_op_sig(&self) -> Result<()> {
+        self.send(&XrpcCall::request_plc_op_sig(), &self.access_jwt)
             .await
             .map(|_| ())
-            .map_err(|err| err.into())
     }
 
     pub async fn sign_plc_op(
-        &self,
+        &mut self,
         did_doc: &serde_json::Value,
         token: &str,
     ) -> Result<serde_json::Value> {
-        let request_plc_op_sig_uri =
-            format!("{}/xrpc/com.atproto.identity.signPlcOperation", self.pds);
+        self.send_refreshing(&XrpcCall::sign_plc_op(did_doc, token))
+            .await?
+            .signed_operation()
+    }
 
-        let token_patch = json!({
-            "token": token,
-        });
-        let mut request_body = did_doc.clone();
-        merge(&mut request_body, &token_patch);
+    pub async fn submit_plc_op(&mut self, operation: &serde_json::Value) -> Result<()> {
+        self.send_refreshing(&XrpcCall::submit_plc_op(operation))
+            .await
+            .map(|_| ())
+    }
+}
 
-        let wrapped_operation: WrappedOperation = self
-            .http_client
-            .post(request_plc_op_sig_uri)
-            .header("Authorization", format!("Bearer {}", self.access_jwt))
-            .json(&request_body)
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(wrapped_operation.operation)
+/// Adds a header for `with_header`, validating its name and value.
+pub(crate) fn insert_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<()> {
+    let name = HeaderName::from_bytes(name.as_bytes())
+        .with_context(|| format!("invalid header name: {}", name))?;
+    let value = HeaderValue::from_str(value)
+        .with_context(|| format!("invalid header value for {}", name))?;
+    headers.insert(name, value);
+    Ok(())
+}
+
+/// The `signPlcOperation` request body: the requested DID state along with
+/// the emailed confirmation token.
+pub(crate) fn sign_plc_op_body(did_doc: &serde_json::Value, token: &str) -> serde_json::Value {
+    let token_patch = json!({
+        "token": token,
+    });
+    let mut request_body = did_doc.clone();
+    merge(&mut request_body, &token_patch);
+    request_body
+}
+
+/// Authenticates as the PDS administrator with HTTP Basic auth, which is
+/// separate from the bearer tokens `PdsClient` uses for account sessions.
+pub struct PdsAdminClient {
+    pub http_client: reqwest::Client,
+    pub pds: String,
+    admin_password: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CreateInviteCodeResponse {
+    code: String,
+}
+
+impl PdsAdminClient {
+    pub fn new(http_client: &reqwest::Client, pds: &str, admin_password: &str) -> Result<Self> {
+        Ok(Self {
+            http_client: http_client.clone(),
+            pds: pds_base_url(pds)?,
+            admin_password: admin_password.to_string(),
+        })
     }
 
-    pub async fn submit_plc_op(&self, operation: &serde_json::Value) -> Result<()> {
-        let submit_plc_op_uri =
-            format!("{}/xrpc/com.atproto.identity.submitPlcOperation", self.pds);
+    /// Creates an invite code that can be used to create `use_count` accounts.
+    pub async fn create_invite_code(&self, use_count: u32) -> Result<String> {
+        let uri = format!("{}/xrpc/com.atproto.server.createInviteCode", self.pds);
 
-        let wrapped_operation = WrappedOperation {
-            operation: operation.clone(),
-        };
+        let response = self
+            .http_client
+            .post(uri)
+            .basic_auth("admin", Some(&self.admin_password))
+            .json(&json!({ "useCount": use_count }))
+            .send_traced()
+            .await
+            .context("failed to request invite code")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "PDS refused to create an invite code (status {}): {}",
+                status,
+                body
+            ));
+        }
 
-        self.http_client
-            .post(submit_plc_op_uri)
-            .header("Authorization", format!("Bearer {}", self.access_jwt))
-            .json(&wrapped_operation)
-            .send()
+        let response: CreateInviteCodeResponse = json_body(response)
             .await
-            .map(|_| ())
-            .map_err(|err| err.into())
+            .context("failed to parse invite code response")?;
+        Ok(response.code)
     }
 }
 
+/// Normalizes a PDS given as a hostname, `host:port`, or full URL into a base
+/// URL without a trailing slash, defaulting to https when no scheme is given.
+pub(crate) fn pds_base_url(pds: &str) -> Result<String> {
+    let pds = pds.trim().trim_end_matches('/');
+    let with_scheme = if pds.contains("://") {
+        pds.to_string()
+    } else {
+        format!("https://{}", pds)
+    };
+
+    let url = reqwest::Url::parse(&with_scheme)
+        .with_context(|| format!("invalid PDS {}: expected a hostname or URL", pds))?;
+    if !matches!(url.scheme(), "https" | "http") {
+        return Err(anyhow!("invalid PDS {}: scheme must be https or http", pds));
+    }
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| anyhow!("invalid PDS {}: missing host", pds))?;
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err(anyhow!("invalid PDS {}: unexpected path", pds));
+    }
+
+    Ok(match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    })
+}
+
 pub(crate) async fn describe_server(
     http_client: &reqwest::Client,
-    pds_hostname: &str,
+    pds: &str,
 ) -> Result<(String, bool, Vec<String>)> {
     let uri = format!(
-        "https://{}/xrpc/com.atproto.server.describeServer",
-        pds_hostname
+        "{}/xrpc/com.atproto.server.describeServer",
+        pds_base_url(pds)?
     );
-    let wrapped_response: WrappedDescribeServerResponse =
-        http_client.get(uri).send().await?.json().await?;
+    let wrapped_response: WrappedDescribeServerResponse = debug_json_body(
+        "com.atproto.server.describeServer",
+        http_client.get(uri).send_traced().await?,
+    )
+    .await?;
 
     match wrapped_response {
         WrappedDescribeServerResponse::DescribeServerResponse {
@@ -180,8 +884,7 @@ pub(crate) async fn describe_server(
             available_user_domains,
         } => Ok((did, invite_code_required, available_user_domains)),
         WrappedDescribeServerResponse::Other { extra } => {
-            println!("Unexpected response from PDS: {:?}", extra);
-            Err(anyhow!("Unexpected response from PDS"))
+            Err(anyhow!("Unexpected response from PDS: {:?}", extra))
         }
     }
 }
@@ -195,25 +898,25 @@ struct CreateAccountRequest {
     #[serde(rename = "inviteCode", skip_serializing_if = "Option::is_none")]
     invite_code: Option<String>,
     password: String,
-    #[serde(rename = "recoveryKey")]
-    recovery_key: String,
+    #[serde(rename = "recoveryKey", skip_serializing_if = "Option::is_none")]
+    recovery_key: Option<String>,
 }
 
 // TODO: Use a request object here.
 #[allow(clippy::too_many_arguments)]
 pub(crate) async fn create_account(
     http_client: &reqwest::Client,
-    pds_hostname: &str,
+    pds: &str,
     handle: &str,
     password: &str,
     email: &str,
-    recovery_key: &str,
+    recovery_key: Option<&str>,
     invite_code: Option<String>,
     did: Option<String>,
 ) -> Result<(String, String, String)> {
     let uri = format!(
-        "https://{}/xrpc/com.atproto.server.createAccount",
-        pds_hostname
+        "{}/xrpc/com.atproto.server.createAccount",
+        pds_base_url(pds)?
     );
 
     let payload = CreateAccountRequest {
@@ -222,16 +925,14 @@ pub(crate) async fn create_account(
         did,
         invite_code,
         password: password.to_string(),
-        recovery_key: recovery_key.to_string(),
+        recovery_key: recovery_key.map(str::to_string),
     };
 
-    let wrapped_response: WrappedCreateAccountResponse = http_client
-        .post(uri)
-        .json(&payload)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let wrapped_response: WrappedCreateAccountResponse = debug_json_body(
+        "com.atproto.server.createAccount",
+        http_client.post(uri).json(&payload).send_traced().await?,
+    )
+    .await?;
 
     match wrapped_response {
         WrappedCreateAccountResponse::CreateAccountResponse {
@@ -240,8 +941,567 @@ pub(crate) async fn create_account(
             access_jwt,
         } => Ok((did, handle, access_jwt)),
         WrappedCreateAccountResponse::Other { extra } => {
-            println!("Unexpected response from PDS: {:?}", extra);
-            Err(anyhow!("Unexpected response from PDS"))
+            Err(anyhow!("Unexpected response from PDS: {:?}", extra))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{body_json, header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[test]
+    fn pds_base_urls() {
+        assert_eq!(
+            pds_base_url("pds.example.com").unwrap(),
+            "https://pds.example.com"
+        );
+        assert_eq!(
+            pds_base_url("pds.example.com:8443").unwrap(),
+            "https://pds.example.com:8443"
+        );
+        assert_eq!(
+            pds_base_url("pds.example.com:443").unwrap(),
+            "https://pds.example.com"
+        );
+        assert_eq!(
+            pds_base_url("http://localhost:2583/").unwrap(),
+            "http://localhost:2583"
+        );
+        assert!(pds_base_url("pds.example.com:port").is_err());
+        assert!(pds_base_url("ftp://pds.example.com").is_err());
+        assert!(pds_base_url("https://pds.example.com/xrpc").is_err());
+    }
+
+    #[tokio::test]
+    async fn html_instead_of_json() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("<!doctype html><title>Welcome</title>", "text/html"),
+            )
+            .mount(&server)
+            .await;
+
+        let err = PdsClient::from_credentials(
+            &reqwest::Client::new(),
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "password",
+        )
+        .await
+        .err()
+        .expect("HTML response");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "expected JSON from {}/xrpc/com.atproto.server.createSession but got text/html (status 200)",
+                server.uri()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn create_invite_code_uses_admin_basic_auth() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createInviteCode"))
+            .and(header("authorization", "Basic YWRtaW46c2VjcmV0"))
+            .and(body_json(serde_json::json!({ "useCount": 2 })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "code": "pds-example-com-abcde-fghij" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let admin_client = PdsAdminClient::new(&reqwest::Client::new(), &server.uri(), "secret")?;
+        assert_eq!(
+            admin_client.create_invite_code(2).await?,
+            "pds-example-com-abcde-fghij"
+        );
+
+        let wrong_password = PdsAdminClient::new(&reqwest::Client::new(), &server.uri(), "wrong")?;
+        assert!(wrong_password.create_invite_code(2).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_account_includes_rotation_key() -> Result<()> {
+        let rotation_key = "did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg";
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createAccount"))
+            .and(body_json(serde_json::json!({
+                "handle": "alice.pds.example.com",
+                "email": "alice@example.com",
+                "password": "hunter2",
+                "recoveryKey": rotation_key
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                "handle": "alice.pds.example.com",
+                "accessJwt": "jwt"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createAccount"))
+            .and(body_json(serde_json::json!({
+                "handle": "bob.pds.example.com",
+                "email": "bob@example.com",
+                "password": "hunter2"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "did": "did:plc:7iza6de2dwap2sbkpav7c6c6",
+                "handle": "bob.pds.example.com",
+                "accessJwt": "jwt"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let http_client = reqwest::Client::new();
+        let (did, _, _) = create_account(
+            &http_client,
+            &server.uri(),
+            "alice.pds.example.com",
+            "hunter2",
+            "alice@example.com",
+            Some(rotation_key),
+            None,
+            None,
+        )
+        .await?;
+        assert_eq!(did, "did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+
+        let (did, _, _) = create_account(
+            &http_client,
+            &server.uri(),
+            "bob.pds.example.com",
+            "hunter2",
+            "bob@example.com",
+            None,
+            None,
+            None,
+        )
+        .await?;
+        assert_eq!(did, "did:plc:7iza6de2dwap2sbkpav7c6c6");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_repos_pages_with_cursor() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.sync.listRepos"))
+            .and(query_param("cursor", "page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "cursor": "page3",
+                "repos": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.sync.listRepos"))
+            .and(query_param("limit", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "cursor": "page2",
+                "repos": [
+                    { "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2", "head": "bafyreia", "rev": "3l5a", "active": true },
+                    { "did": "did:plc:ewvi7nxzyoun6zhxrhs64oiz", "head": "bafyreib", "rev": "3l5b", "active": false, "status": "takendown" }
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        let (repos, cursor) = client.list_repos(None, 2).await?;
+        assert_eq!(repos.len(), 2);
+        assert_eq!(repos[0].did, "did:plc:cbkjy5n7bk3ax2wplmtjofq2");
+        assert_eq!(repos[1].status.as_deref(), Some("takendown"));
+        assert_eq!(cursor.as_deref(), Some("page2"));
+
+        let (repos, cursor) = client.list_repos(cursor, 2).await?;
+        assert!(repos.is_empty());
+        assert!(cursor.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_taken_and_free_handles() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.identity.resolveHandle"))
+            .and(query_param("handle", "alice.pds.example.com"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2" }),
+                ),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.identity.resolveHandle"))
+            .and(query_param("handle", "bob.pds.example.com"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "InvalidRequest",
+                "message": "Unable to resolve handle"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        assert_eq!(
+            client.resolve_handle("alice.pds.example.com").await?,
+            Some("did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string())
+        );
+        assert_eq!(client.resolve_handle("bob.pds.example.com").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_confirmation_codes() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.signPlcOperation"))
+            .and(body_json(serde_json::json!({ "token": "ABCDE-FGHIJ" })))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "ExpiredToken",
+                "message": "Token is expired"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.signPlcOperation"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "InvalidToken",
+                "message": "Token is invalid"
+            })))
+            .mount(&server)
+            .await;
+        // An expired confirmation code is not an expired session, so it is
+        // returned without refreshing the session.
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.refreshSession"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let mut client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        client.access_jwt = "access".to_string();
+        client.refresh_jwt = Some("refresh".to_string());
+        let expired = client
+            .sign_plc_op(&serde_json::json!({}), "ABCDE-FGHIJ")
+            .await
+            .unwrap_err();
+        assert!(is_expired_token_error(&expired));
+
+        let invalid = client
+            .sign_plc_op(&serde_json::json!({}), "KLMNO-PQRST")
+            .await
+            .unwrap_err();
+        assert!(!is_expired_token_error(&invalid));
+        assert!(!is_expired_token_error(&anyhow!("connection reset")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn describe_server_defaults_missing_fields() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.server.describeServer"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "did": "did:web:pds.example.com",
+                "availableUserDomains": null
+            })))
+            .mount(&server)
+            .await;
+
+        let (did, invite_code_required, available_user_domains) =
+            describe_server(&reqwest::Client::new(), &server.uri()).await?;
+        assert_eq!(did, "did:web:pds.example.com");
+        assert!(!invite_code_required);
+        assert!(available_user_domains.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recommended_credentials_are_typed() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/xrpc/com.atproto.identity.getRecommendedDidCredentials",
+            ))
+            .and(header("Authorization", "Bearer access"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "rotationKeys": ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"],
+                "alsoKnownAs": ["at://alice.example.com"],
+                "verificationMethods": {
+                    "atproto": "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo"
+                },
+                "services": {
+                    "atproto_pds": {
+                        "type": "AtprotoPersonalDataServer",
+                        "endpoint": "https://pds.example.com"
+                    }
+                }
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/xrpc/com.atproto.identity.getRecommendedDidCredentials",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "unexpected": true })))
+            .mount(&server)
+            .await;
+
+        let mut pds_client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        pds_client.access_jwt = "access".to_string();
+
+        let credentials = pds_client.get_recommended_did_credentials().await?;
+        assert_eq!(
+            credentials.rotation_keys,
+            ["did:key:zQ3shhCGUqDKjStzuDxPkTxN6ujddP4RkEKJJouJGRRkaLGbg"]
+        );
+        assert_eq!(credentials.also_known_as, ["at://alice.example.com"]);
+        assert_eq!(
+            credentials.verification_methods["atproto"],
+            "did:key:zDnaembgSGUhZULN2Caob4HLJPaxBh92N7rtH21TErzqf8HQo"
+        );
+        assert_eq!(
+            credentials.services["atproto_pds"],
+            RecommendedService {
+                service_type: "AtprotoPersonalDataServer".to_string(),
+                endpoint: "https://pds.example.com".to_string(),
+            }
+        );
+
+        let err = pds_client
+            .get_recommended_did_credentials()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unexpected"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deactivate_account() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.deactivateAccount"))
+            .and(header("Authorization", "Bearer access"))
+            .and(body_json(json!({})))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.deactivateAccount"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": "AuthenticationRequired",
+                "message": "Invalid token",
+            })))
+            .mount(&server)
+            .await;
+
+        let mut pds_client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        pds_client.access_jwt = "access".to_string();
+        pds_client.deactivate_account().await?;
+
+        pds_client.access_jwt = "expired".to_string();
+        let err = pds_client.deactivate_account().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<XrpcRequestError>().map(|err| err.status),
+            Some(401)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn from_credentials_retries_transient_failures() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "accessJwt": "access",
+                "refreshJwt": "refresh",
+                "handle": "alice.example.com",
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let pds_client = PdsClient::from_credentials(
+            &reqwest::Client::new(),
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "password",
+        )
+        .await?;
+        assert_eq!(pds_client.access_jwt, "access");
+
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_session_responses() -> Result<()> {
+        let response: WrappedCredentialResponse = serde_json::from_value(json!({
+            "accessJwt": "access2",
+            "refreshJwt": "refresh2",
+            "handle": "alice.example.com",
+            "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "didDoc": {}
+        }))?;
+        assert_eq!(
+            session_tokens(response)?,
+            ("access2".to_string(), Some("refresh2".to_string()))
+        );
+
+        let response: WrappedCredentialResponse =
+            serde_json::from_value(json!({ "error": "ExpiredToken" }))?;
+        assert!(session_tokens(response).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_refreshed_once() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.refreshSession"))
+            .and(header("Authorization", "Bearer refresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "accessJwt": "access2",
+                "refreshJwt": "refresh2",
+                "handle": "alice.example.com",
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+            .and(header("Authorization", "Bearer access"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+            .and(header("Authorization", "Bearer access2"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut pds_client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        pds_client.access_jwt = "access".to_string();
+        pds_client.refresh_jwt = Some("refresh".to_string());
+
+        pds_client.submit_plc_op(&json!({})).await?;
+        assert_eq!(pds_client.access_jwt, "access2");
+        assert_eq!(pds_client.refresh_jwt.as_deref(), Some("refresh2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_submissions_are_errors() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.refreshSession"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "accessJwt": "access2",
+                "refreshJwt": "refresh2",
+                "handle": "alice.example.com",
+                "did": "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+            .and(header("Authorization", "Bearer access"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": "ExpiredToken",
+                "message": "Token has expired"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+            .and(header("Authorization", "Bearer access2"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "InvalidRequest",
+                "message": "Invalid rotation key"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut pds_client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        pds_client.access_jwt = "access".to_string();
+        pds_client.refresh_jwt = Some("refresh".to_string());
+
+        let err = pds_client.submit_plc_op(&json!({})).await.unwrap_err();
+        let err = err.downcast_ref::<XrpcRequestError>().unwrap();
+        assert_eq!(err.status, 400);
+        assert_eq!(err.error, "InvalidRequest");
+        assert_eq!(pds_client.access_jwt, "access2");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.identity.submitPlcOperation"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let mut pds_client = PdsClient::unauthenticated(&reqwest::Client::new(), &server.uri())?;
+        assert!(pds_client.submit_plc_op(&json!({})).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn from_credentials_does_not_retry_bad_credentials() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/xrpc/com.atproto.server.createSession"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "error": "AuthenticationRequired",
+                "message": "Invalid identifier or password",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = PdsClient::from_credentials(
+            &reqwest::Client::new(),
+            &server.uri(),
+            "did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+            "password",
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use serde_json::json;
+use tandem::{
+    flows::{append_handle, AppendHandleOutcome},
+    plc::operation_cid,
+    signer::{JwkSigner, Signer},
+};
+use wiremock::{
+    matchers::{body_partial_json, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+const DID: &str = "did:plc:nym6ckfkvm4wsq6riwepwvye";
+
+const ROTATION_JWK: &str = r#"{"kty":"EC","crv":"P-256","x":"c9KOD4FhIE-stzFrOiz-NsmdpxuW4p6ZRt8lGBexmOk","y":"NhlZVl-6gFT5t1bEeifAXWwt7ays9cDrUUs4a5_tZXw","d":"QKhZrsK0v_vXg976lVwMeAeG7dmFq0D-oswZVQrnlt8"}"#;
+
+#[tokio::test]
+async fn append_handle_against_mock_directory() -> anyhow::Result<()> {
+    let signer = JwkSigner::new(elliptic_curve::JwkEcKey::from_str(ROTATION_JWK)?)?;
+
+    let genesis = json!({
+        "type": "plc_operation",
+        "rotationKeys": [signer.did_key()],
+        "verificationMethods": {
+            "atproto": "did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"
+        },
+        "alsoKnownAs": ["at://alice.example.com"],
+        "services": {
+            "atproto_pds": {
+                "type": "AtprotoPersonalDataServer",
+                "endpoint": "https://pds.example.com"
+            }
+        },
+        "prev": null,
+        "sig": "8Wj9Cf74dZFNKx7oucZSHbBDFOMJ3xx9lkvj5rT9xMErssWYl1D9n4PeGC0mNml7xDG7uoQqZ1JWoApGADUgXg"
+    });
+    let genesis_cid = operation_cid(&genesis)?;
+
+    let directory = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/{}", DID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": DID })))
+        .mount(&directory)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/{}/log/audit", DID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+            "did": DID,
+            "operation": genesis,
+            "cid": genesis_cid,
+            "nullified": false,
+            "createdAt": "2024-01-01T00:00:00.000Z"
+        }])))
+        .mount(&directory)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/{}", DID)))
+        .and(body_partial_json(json!({
+            "alsoKnownAs": ["at://alice.example.com", "at://alice.example.net"],
+            "prev": genesis_cid,
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&directory)
+        .await;
+
+    let http_client = reqwest::Client::new();
+    let outcome = append_handle(
+        &http_client,
+        &directory.uri(),
+        DID,
+        &signer,
+        "alice.example.net",
+    )
+    .await?;
+
+    let AppendHandleOutcome::Submitted { cid, operation } = outcome else {
+        panic!("expected the operation to be submitted");
+    };
+    assert_eq!(cid, operation_cid(&operation)?);
+    assert_eq!(operation["services"], genesis["services"]);
+    assert!(operation["sig"].as_str().is_some_and(|sig| sig.len() == 86));
+
+    let outcome = append_handle(
+        &http_client,
+        &directory.uri(),
+        DID,
+        &signer,
+        "alice.example.com",
+    )
+    .await?;
+    assert!(matches!(outcome, AppendHandleOutcome::AlreadyPresent));
+
+    Ok(())
+}